@@ -0,0 +1,180 @@
+/** A stand-in for the parts of [the coremidi crate](https://crates.io/crates/coremidi)
+that komp uses, on platforms without CoreMIDI.
+
+There are no MIDI sources or destinations, so komp cannot play, but it builds and its
+tests run. Packets are buffered as CoreMIDI buffers them: data pushed with the same
+timestamp as the last packet is added to that packet, unless either is system exclusive.
+*/
+use std::ops::Deref;
+use std::slice;
+use std::vec;
+
+pub type OSStatus = i32;
+pub type Timestamp = u64;
+
+const SYSTEM_EXCLUSIVE: u8 = 0xF0;
+
+pub struct Packet {
+    timestamp: Timestamp,
+    data: Vec<u8>,
+}
+
+impl Packet {
+    pub fn timestamp(&self) -> Timestamp {
+        self.timestamp
+    }
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+pub struct PacketList {
+    packets: Vec<Packet>,
+}
+
+impl PacketList {
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+    pub fn iter(&self) -> slice::Iter<'_, Packet> {
+        self.packets.iter()
+    }
+}
+
+pub struct PacketBuffer {
+    list: PacketList,
+}
+
+impl Deref for PacketBuffer {
+    type Target = PacketList;
+    fn deref(&self) -> &PacketList {
+        &self.list
+    }
+}
+
+impl PacketBuffer {
+    pub fn new(timestamp: Timestamp, data: &[u8]) -> PacketBuffer {
+        let mut packet_buffer = PacketBuffer::with_capacity(data.len());
+        packet_buffer.push_data(timestamp, data);
+        packet_buffer
+    }
+
+    pub fn with_capacity(_capacity: usize) -> PacketBuffer {
+        PacketBuffer {
+            list: PacketList { packets: vec![] },
+        }
+    }
+
+    pub fn push_data(&mut self, timestamp: Timestamp, data: &[u8]) -> &mut PacketBuffer {
+        let is_sysex = |data: &[u8]| data.first() == Some(&SYSTEM_EXCLUSIVE);
+        match self.list.packets.last_mut() {
+            Some(last)
+                if last.timestamp == timestamp && !is_sysex(&last.data) && !is_sysex(data) =>
+            {
+                last.data.extend_from_slice(data)
+            }
+            _ => self.list.packets.push(Packet {
+                timestamp,
+                data: data.to_vec(),
+            }),
+        }
+        self
+    }
+
+    pub fn clear(&mut self) {
+        self.list.packets.clear()
+    }
+}
+
+pub struct Source;
+pub struct Destination;
+pub struct Sources;
+pub struct Destinations;
+
+impl Source {
+    pub fn from_index(_index: usize) -> Option<Source> {
+        None
+    }
+    pub fn display_name(&self) -> Option<String> {
+        None
+    }
+}
+
+impl Destination {
+    pub fn from_index(_index: usize) -> Option<Destination> {
+        None
+    }
+    pub fn display_name(&self) -> Option<String> {
+        None
+    }
+}
+
+impl Sources {
+    pub fn count() -> usize {
+        0
+    }
+}
+
+impl IntoIterator for Sources {
+    type Item = Source;
+    type IntoIter = vec::IntoIter<Source>;
+    fn into_iter(self) -> Self::IntoIter {
+        vec![].into_iter()
+    }
+}
+
+impl Destinations {
+    pub fn count() -> usize {
+        0
+    }
+}
+
+impl IntoIterator for Destinations {
+    type Item = Destination;
+    type IntoIter = vec::IntoIter<Destination>;
+    fn into_iter(self) -> Self::IntoIter {
+        vec![].into_iter()
+    }
+}
+
+pub struct Client;
+pub struct InputPort;
+pub struct OutputPort;
+
+impl Client {
+    pub fn new(_name: &str) -> Result<Client, OSStatus> {
+        Ok(Client)
+    }
+    pub fn input_port<F>(&self, _name: &str, _callback: F) -> Result<InputPort, OSStatus>
+    where
+        F: FnMut(&PacketList) + Send + 'static,
+    {
+        Ok(InputPort)
+    }
+    pub fn output_port(&self, _name: &str) -> Result<OutputPort, OSStatus> {
+        Ok(OutputPort)
+    }
+}
+
+impl InputPort {
+    pub fn connect_source(&self, _source: &Source) -> Result<(), OSStatus> {
+        Ok(())
+    }
+    pub fn disconnect_source(&self, _source: &Source) -> Result<(), OSStatus> {
+        Ok(())
+    }
+}
+
+impl OutputPort {
+    pub fn send(&self, _destination: &Destination, _packets: &PacketList) -> Result<(), OSStatus> {
+        Ok(())
+    }
+}
+
+/// Unschedules the packets that were sent to be played later.
+pub fn flush() -> Result<(), OSStatus> {
+    Ok(())
+}
@@ -0,0 +1,211 @@
+/** Arpeggios of the chord.
+
+The arpeggiator plays the notes of the chord one at a time, on a grid of
+`rate` notes to a whole note, over a number of octaves. Each note lasts
+`gate` of a step. The notes go up, down, up and down, in a (seeded)
+random order, or in the order in which the player pressed them.
+*/
+use crate::pattern::{create_note, Meter};
+use crate::play::TimedEvent;
+use crate::random::Random;
+use komp_core::OCTAVE_STEPS;
+
+const VELOCITY: u8 = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArpeggioOrder {
+    Up,
+    Down,
+    UpDown,
+    Random,
+    AsPlayed,
+}
+
+impl std::str::FromStr for ArpeggioOrder {
+    type Err = String;
+    fn from_str(s: &str) -> Result<ArpeggioOrder, String> {
+        match s.trim() {
+            "up" => Ok(ArpeggioOrder::Up),
+            "down" => Ok(ArpeggioOrder::Down),
+            "up-down" => Ok(ArpeggioOrder::UpDown),
+            "random" => Ok(ArpeggioOrder::Random),
+            "as-played" => Ok(ArpeggioOrder::AsPlayed),
+            _ => Err(format!("wrong arpeggio order: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Arpeggiator {
+    order: ArpeggioOrder,
+    // notes to a whole note, 16 for sixteenths
+    rate: u8,
+    octaves: u8,
+    gate: f64,
+    seed: u64,
+}
+
+impl Arpeggiator {
+    /// Sixteenths over one octave, each lasting half a step.
+    pub fn new(order: ArpeggioOrder) -> Arpeggiator {
+        Arpeggiator {
+            order,
+            rate: 16,
+            octaves: 1,
+            gate: 0.5,
+            seed: 0,
+        }
+    }
+    pub fn with_rate(mut self, rate: u8) -> Arpeggiator {
+        self.rate = rate.max(1);
+        self
+    }
+    /// Over up to four octaves.
+    pub fn with_octaves(mut self, octaves: u8) -> Arpeggiator {
+        self.octaves = octaves.clamp(1, 4);
+        self
+    }
+    pub fn with_gate(mut self, gate: f64) -> Arpeggiator {
+        self.gate = gate.clamp(0.0, 1.0);
+        self
+    }
+    pub fn with_seed(mut self, seed: u64) -> Arpeggiator {
+        self.seed = seed;
+        self
+    }
+
+    /// One cycle of the arpeggio of `notes`. `played` are the notes the
+    /// player pressed, in the order they were pressed; notes of the chord
+    /// with the same pitch class follow that order when played as played.
+    pub fn cycle(&self, notes: &[u8], played: &[u8]) -> Vec<u8> {
+        let mut notes = notes.to_vec();
+        notes.sort();
+        if self.order == ArpeggioOrder::AsPlayed {
+            let played_index = |note: &u8| {
+                played
+                    .iter()
+                    .position(|played| played % OCTAVE_STEPS == note % OCTAVE_STEPS)
+                    .unwrap_or(played.len())
+            };
+            notes.sort_by_key(played_index);
+        }
+        let spread: Vec<u8> = (0..self.octaves)
+            .flat_map(|octave| notes.iter().map(move |note| note + octave * OCTAVE_STEPS))
+            .filter(|note| *note < 128)
+            .collect();
+        match self.order {
+            ArpeggioOrder::Down => spread.into_iter().rev().collect(),
+            ArpeggioOrder::UpDown if spread.len() > 2 => {
+                // without repeating the top and the bottom notes
+                let down: Vec<u8> = spread[1..spread.len() - 1].iter().rev().copied().collect();
+                spread.into_iter().chain(down).collect()
+            }
+            _ => spread,
+        }
+    }
+
+    /// A bar of the arpeggio, on the comping channel.
+    pub fn create_bar(
+        &self,
+        ticks_per_quarter: u32,
+        meter: Meter,
+        notes: &[u8],
+        played: &[u8],
+    ) -> Vec<TimedEvent> {
+        let cycle = self.cycle(notes, played);
+        if cycle.is_empty() {
+            return vec![];
+        }
+        let step_ticks = 4 * ticks_per_quarter / self.rate as u32;
+        let length = ((step_ticks as f64 * self.gate).round() as u32).max(1);
+        let steps = meter.ticks_per_bar(ticks_per_quarter) / step_ticks.max(1);
+        let mut timed_events = vec![];
+        for step in 0..steps {
+            let note = if self.order == ArpeggioOrder::Random {
+                let mut random = Random::keyed(self.seed, &[step as u64]);
+                cycle[(random.next_u64() % cycle.len() as u64) as usize]
+            } else {
+                cycle[step as usize % cycle.len()]
+            };
+            let (on, off) = create_note(step * step_ticks, length, 0, note, VELOCITY);
+            timed_events.push(on);
+            timed_events.push(off);
+        }
+        timed_events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::COMMON_TIME;
+    use crate::play::Event;
+    use komp_core::*;
+
+    const C_MAJOR: [u8; 3] = [NOTE_C3, NOTE_E3, NOTE_G3];
+
+    fn note_ons(timed_events: &[TimedEvent]) -> Vec<(u32, u8)> {
+        timed_events
+            .iter()
+            .filter_map(|te| match te.event {
+                Event::NoteOn { note, .. } => Some((te.timing, note)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_orders() {
+        let cycle = |order| Arpeggiator::new(order).cycle(&C_MAJOR, &[]);
+        assert_eq!(cycle(ArpeggioOrder::Up), vec![NOTE_C3, NOTE_E3, NOTE_G3]);
+        assert_eq!(cycle(ArpeggioOrder::Down), vec![NOTE_G3, NOTE_E3, NOTE_C3]);
+        assert_eq!(
+            cycle(ArpeggioOrder::UpDown),
+            vec![NOTE_C3, NOTE_E3, NOTE_G3, NOTE_E3]
+        );
+        // pressed G, C and then E
+        let as_played =
+            Arpeggiator::new(ArpeggioOrder::AsPlayed).cycle(&C_MAJOR, &[NOTE_G4, NOTE_C5, NOTE_E4]);
+        assert_eq!(as_played, vec![NOTE_G3, NOTE_C3, NOTE_E3]);
+    }
+
+    #[test]
+    fn test_octaves() {
+        let arpeggiator = Arpeggiator::new(ArpeggioOrder::UpDown).with_octaves(2);
+        assert_eq!(
+            arpeggiator.cycle(&C_MAJOR, &[]),
+            vec![
+                NOTE_C3, NOTE_E3, NOTE_G3, NOTE_C4, NOTE_E4, NOTE_G4, NOTE_E4, NOTE_C4, NOTE_G3,
+                NOTE_E3
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rate_and_gate() {
+        let arpeggiator = Arpeggiator::new(ArpeggioOrder::Up)
+            .with_rate(8)
+            .with_gate(0.75);
+        let timed_events = arpeggiator.create_bar(96, COMMON_TIME, &C_MAJOR, &[]);
+        assert_eq!(
+            note_ons(&timed_events)[..4],
+            [(0, NOTE_C3), (48, NOTE_E3), (96, NOTE_G3), (144, NOTE_C3)]
+        );
+        assert_eq!(note_ons(&timed_events).len(), 8);
+        assert_eq!(timed_events[1].timing, 36);
+    }
+
+    #[test]
+    fn test_random_is_reproducible() {
+        let arpeggiator = Arpeggiator::new(ArpeggioOrder::Random).with_seed(3);
+        let timed_events = arpeggiator.create_bar(96, COMMON_TIME, &C_MAJOR, &[]);
+        assert_eq!(
+            timed_events,
+            arpeggiator.create_bar(96, COMMON_TIME, &C_MAJOR, &[])
+        );
+        let notes = note_ons(&timed_events);
+        assert_eq!(notes.len(), 16);
+        assert!(notes.iter().all(|(_, note)| C_MAJOR.contains(note)));
+        assert!(notes.iter().any(|(_, note)| *note != notes[0].1));
+    }
+}
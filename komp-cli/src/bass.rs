@@ -0,0 +1,407 @@
+/** Bass lines that follow the chords.
+
+A walking bass plays a quarter note on each beat: the root on the first
+beat, chord tones on the way, and an approach note into the root of the
+next chord on the last beat. The approach is chromatic, from a step of
+the scale, or from the dominant of the next chord. The simpler styles
+play the root and the fifth, or hold the root as a pedal.
+*/
+use crate::pattern::{create_note, Meter, TimeCode};
+use crate::play::TimedEvent;
+use crate::random::Random;
+use crate::rhythm::Rhythm;
+use komp_core::{Chord, NOTE_C3, NOTE_E1, OCTAVE_STEPS};
+
+/// The bass plays on the second MIDI channel.
+pub const BASS_CHANNEL: u8 = 1;
+const VELOCITY: u8 = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BassStyle {
+    Walking,
+    RootFifth,
+    Pedal,
+}
+
+impl std::str::FromStr for BassStyle {
+    type Err = String;
+    fn from_str(s: &str) -> Result<BassStyle, String> {
+        match s.trim() {
+            "walking" => Ok(BassStyle::Walking),
+            "root-fifth" => Ok(BassStyle::RootFifth),
+            "pedal" => Ok(BassStyle::Pedal),
+            _ => Err(format!("wrong bass style: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Approach {
+    Chromatic,
+    Scale,
+    Dominant,
+}
+
+const APPROACHES: [Approach; 3] = [Approach::Chromatic, Approach::Scale, Approach::Dominant];
+
+/// A note of a bass line, from a beat of the bar and lasting some beats.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BassNote {
+    pub beat: u8,
+    pub beats: u8,
+    pub note: u8,
+}
+
+/// A bass player, who keeps the line going from one bar to the next.
+#[derive(Clone, Debug)]
+pub struct Bass {
+    style: BassStyle,
+    low: u8,
+    high: u8,
+    seed: u64,
+    bar: u64,
+    previous: Option<u8>,
+}
+
+// the chord tones, as semitones above the root
+fn chord_tones(chord: &Chord) -> Vec<u8> {
+    let root = chord.key().0 + OCTAVE_STEPS;
+    chord.notes(0, 0).iter().map(|note| note - root).collect()
+}
+
+fn fifth(tones: &[u8]) -> u8 {
+    [7, 6, 8]
+        .iter()
+        .copied()
+        .find(|tone| tones.contains(tone))
+        .unwrap_or(7)
+}
+
+impl Bass {
+    pub fn new(style: BassStyle) -> Bass {
+        Bass {
+            style,
+            low: NOTE_E1,
+            high: NOTE_C3,
+            seed: 0,
+            bar: 0,
+            previous: None,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Bass {
+        self.seed = seed;
+        self
+    }
+
+    // the note of a pitch class (0 to 11) in the register nearest to `to`
+    fn nearest(&self, pitch_class: u8, to: u8) -> u8 {
+        (self.low..=self.high)
+            .filter(|note| note % OCTAVE_STEPS == pitch_class % OCTAVE_STEPS)
+            .min_by_key(|note| (*note as i32 - to as i32).abs())
+            .unwrap_or(self.low)
+    }
+
+    fn in_register(&self, note: i32) -> bool {
+        (self.low as i32..=self.high as i32).contains(&note)
+    }
+
+    /// The bass line of a bar of `chord`, leading into `next`.
+    pub fn bar(&mut self, chord: &Chord, next: &Chord, meter: Meter) -> Vec<BassNote> {
+        let root = self.nearest(chord.key().0, self.previous.unwrap_or(self.low));
+        let tones = chord_tones(chord);
+        let beats = meter.beats;
+        let line = match self.style {
+            BassStyle::Pedal => vec![BassNote {
+                beat: 0,
+                beats,
+                note: root,
+            }],
+            BassStyle::RootFifth if beats > 1 => {
+                let root_beats = beats.div_ceil(2);
+                let fifth = self.nearest(chord.key().0 + fifth(&tones), root);
+                vec![
+                    BassNote {
+                        beat: 0,
+                        beats: root_beats,
+                        note: root,
+                    },
+                    BassNote {
+                        beat: root_beats,
+                        beats: beats - root_beats,
+                        note: fifth,
+                    },
+                ]
+            }
+            BassStyle::RootFifth => vec![BassNote {
+                beat: 0,
+                beats,
+                note: root,
+            }],
+            BassStyle::Walking => {
+                let mut random = Random::keyed(self.seed, &[self.bar]);
+                let approach = APPROACHES[(random.next_u64() % APPROACHES.len() as u64) as usize];
+                self.walk(root, &tones, chord.key().0, next.key().0, beats, approach)
+                    .iter()
+                    .enumerate()
+                    .map(|(beat, note)| BassNote {
+                        beat: beat as u8,
+                        beats: 1,
+                        note: *note,
+                    })
+                    .collect()
+            }
+        };
+        self.previous = line.last().map(|bass_note| bass_note.note);
+        self.bar += 1;
+        line
+    }
+
+    // a note on each beat, from the root through chord tones to an
+    // approach into the next root
+    fn walk(
+        &self,
+        root: u8,
+        tones: &[u8],
+        root_class: u8,
+        next_class: u8,
+        beats: u8,
+        approach: Approach,
+    ) -> Vec<u8> {
+        let target = self.nearest(next_class, root);
+        let mut up = target >= root;
+        let mut notes = vec![root];
+        for _ in 2..beats {
+            let previous = *notes.last().unwrap();
+            let next_tone = |up: bool| {
+                (self.low..=self.high)
+                    .filter(|note| {
+                        tones.contains(&((note + OCTAVE_STEPS - root_class) % OCTAVE_STEPS))
+                    })
+                    .filter(|note| {
+                        if up {
+                            *note > previous
+                        } else {
+                            *note < previous
+                        }
+                    })
+                    .min_by_key(|note| (*note as i32 - previous as i32).abs())
+            };
+            // turn around at the edge of the register
+            let note = next_tone(up).or_else(|| {
+                up = !up;
+                next_tone(up)
+            });
+            notes.push(note.unwrap_or(previous));
+        }
+        if beats > 1 {
+            let from_below = *notes.last().unwrap() < target;
+            notes.push(self.approach(approach, target, from_below, next_class));
+        }
+        notes
+    }
+
+    fn approach(&self, approach: Approach, target: u8, from_below: bool, next_class: u8) -> u8 {
+        let step = |semitones: i32| {
+            let target = target as i32;
+            let (below, above) = (target - semitones, target + semitones);
+            let note = match (from_below, self.in_register(below), self.in_register(above)) {
+                (true, true, _) | (false, true, false) => below,
+                _ => above,
+            };
+            note as u8
+        };
+        match approach {
+            Approach::Chromatic => step(1),
+            Approach::Scale => step(2),
+            Approach::Dominant => self.nearest(next_class + 7, target),
+        }
+    }
+}
+
+/// The events of a bar of a bass line.
+pub fn create_bass_bar(
+    ticks_per_quarter: u32,
+    meter: Meter,
+    bar: u32,
+    line: &[BassNote],
+) -> Vec<TimedEvent> {
+    let ticks_per_beat = meter.ticks_per_beat(ticks_per_quarter);
+    let mut timed_events = vec![];
+    for bass_note in line {
+        let offset = TimeCode::with_meter(ticks_per_quarter, meter, bar, bass_note.beat, 0);
+        // a little short of legato, so that repeated notes are heard
+        let length = bass_note.beats as u32 * ticks_per_beat * 7 / 8;
+        let (on, off) = create_note(
+            offset.ticks(ticks_per_quarter),
+            length,
+            BASS_CHANNEL,
+            bass_note.note,
+            VELOCITY,
+        );
+        timed_events.push(on);
+        timed_events.push(off);
+    }
+    timed_events
+}
+
+/// The events of a bar of a bass line played on the hits of a rhythm, each
+/// hit playing the note of the line at that time.
+pub fn create_rhythmic_bass_bar(
+    ticks_per_quarter: u32,
+    meter: Meter,
+    bar: u64,
+    line: &[BassNote],
+    rhythm: &Rhythm,
+) -> Vec<TimedEvent> {
+    let ticks_per_beat = meter.ticks_per_beat(ticks_per_quarter);
+    let mut timed_events = vec![];
+    for hit in rhythm.hits(ticks_per_quarter, meter, bar) {
+        let playing = line
+            .iter()
+            .rev()
+            .find(|bass_note| bass_note.beat as u32 * ticks_per_beat <= hit.timing);
+        if let Some(bass_note) = playing {
+            let (on, off) = create_note(
+                hit.timing,
+                hit.length,
+                BASS_CHANNEL,
+                bass_note.note,
+                hit.velocity,
+            );
+            timed_events.push(on);
+            timed_events.push(off);
+        }
+    }
+    timed_events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::COMMON_TIME;
+    use crate::play::Event;
+    use komp_core::*;
+
+    fn notes(line: &[BassNote]) -> Vec<u8> {
+        line.iter().map(|bass_note| bass_note.note).collect()
+    }
+
+    #[test]
+    fn test_pedal_and_root_fifth() {
+        let mut bass = Bass::new(BassStyle::Pedal);
+        let line = bass.bar(&Chord::Major(G_KEY), &Chord::Major(C_KEY), COMMON_TIME);
+        assert_eq!(
+            line,
+            vec![BassNote {
+                beat: 0,
+                beats: 4,
+                note: NOTE_G1
+            }]
+        );
+
+        let mut bass = Bass::new(BassStyle::RootFifth);
+        let line = bass.bar(&Chord::Major(C_KEY), &Chord::Major(F_KEY), Meter::new(3, 4));
+        assert_eq!(notes(&line), vec![NOTE_C2, NOTE_G1]);
+        assert_eq!((line[0].beats, line[1].beat, line[1].beats), (2, 2, 1));
+        // with a flat fifth
+        let line = bass.bar(&Chord::Minor7b5(B_KEY), &Chord::Major7(E_KEY), COMMON_TIME);
+        assert_eq!(notes(&line), vec![NOTE_B1, NOTE_F1]);
+    }
+
+    #[test]
+    fn test_walking_bar() {
+        let c_major = Chord::Major(C_KEY);
+        for seed in 0..20 {
+            let mut bass = Bass::new(BassStyle::Walking).with_seed(seed);
+            let line = notes(&bass.bar(&c_major, &Chord::Major(F_KEY), COMMON_TIME));
+            assert_eq!(line.len(), 4);
+            assert_eq!(line[0], NOTE_C2);
+            // chord tones on the way, up towards the F
+            assert_eq!(line[1..3], [NOTE_E2, NOTE_G2]);
+            // and a chromatic, scale or dominant approach
+            assert!([NOTE_FSHARP2, NOTE_G2, NOTE_C2].contains(&line[3]));
+        }
+    }
+
+    #[test]
+    fn test_approaches() {
+        let bass = Bass::new(BassStyle::Walking);
+        assert_eq!(
+            bass.approach(Approach::Chromatic, NOTE_F2, true, 5),
+            NOTE_E2
+        );
+        assert_eq!(
+            bass.approach(Approach::Chromatic, NOTE_F2, false, 5),
+            NOTE_FSHARP2
+        );
+        assert_eq!(bass.approach(Approach::Scale, NOTE_F2, false, 5), NOTE_G2);
+        assert_eq!(bass.approach(Approach::Dominant, NOTE_F2, true, 5), NOTE_C2);
+        // from the other side at the edge of the register
+        assert_eq!(
+            bass.approach(Approach::Scale, NOTE_E1, true, 4),
+            NOTE_FSHARP1
+        );
+    }
+
+    #[test]
+    fn test_walking_continues_the_line() {
+        let progression = [
+            Chord::Minor7(D_KEY),
+            Chord::Major7(G_KEY),
+            Chord::MajorMaj7(C_KEY),
+            Chord::MajorMaj7(C_KEY),
+        ];
+        let mut bass = Bass::new(BassStyle::Walking).with_seed(7);
+        let mut previous = None;
+        for (bar, chord) in progression.iter().enumerate() {
+            let next = &progression[(bar + 1) % progression.len()];
+            let line = notes(&bass.bar(chord, next, COMMON_TIME));
+            assert_eq!(line[0] % 12, chord.key().0);
+            assert!(line.iter().all(|note| (NOTE_E1..=NOTE_C3).contains(note)));
+            if let Some(previous) = previous {
+                // the root comes from the approach, by a step or a fifth
+                let leap = (line[0] as i32 - previous as i32).abs();
+                assert!(leap <= 7, "{} to {}", previous, line[0]);
+            }
+            previous = line.last().copied();
+        }
+    }
+
+    #[test]
+    fn test_create_bass_bar() {
+        let mut bass = Bass::new(BassStyle::Pedal);
+        let line = bass.bar(&Chord::Major(F_KEY), &Chord::Major(C_KEY), COMMON_TIME);
+        let timed_events = create_bass_bar(96, COMMON_TIME, 1, &line);
+        assert_eq!(timed_events.len(), 2);
+        assert_eq!(timed_events[0].timing, 384);
+        assert_eq!(timed_events[1].timing, 384 + 336);
+        assert!(timed_events.iter().all(|te| match te.event {
+            Event::NoteOn { channel, .. } | Event::NoteOff { channel, .. } =>
+                channel == BASS_CHANNEL,
+        }));
+    }
+
+    #[test]
+    fn test_create_rhythmic_bass_bar() {
+        let mut bass = Bass::new(BassStyle::RootFifth);
+        let line = bass.bar(&Chord::Major(C_KEY), &Chord::Major(C_KEY), COMMON_TIME);
+        let rhythm: Rhythm = "x..x..x.".parse().unwrap();
+        let timed_events = create_rhythmic_bass_bar(96, COMMON_TIME, 0, &line, &rhythm);
+        let note_ons: Vec<(u32, u8)> = timed_events
+            .iter()
+            .filter_map(|te| match te.event {
+                Event::NoteOn { note, .. } => Some((te.timing, note)),
+                _ => None,
+            })
+            .collect();
+        // the root for two beats, and then the fifth
+        assert_eq!(note_ons, vec![(0, NOTE_C2), (144, NOTE_C2), (288, NOTE_G1)]);
+    }
+
+    #[test]
+    fn test_bass_style_from_str() {
+        assert_eq!("root-fifth".parse(), Ok(BassStyle::RootFifth));
+        assert!("slap".parse::<BassStyle>().is_err());
+    }
+}
@@ -0,0 +1,184 @@
+/** ChordPro charts of the chords heard, with the title, key, time and tempo
+directives, and the bars between `|`, four to a line.
+
+A bar of a single chord is written as `[Cm7]`, chords that share the beats
+of a bar evenly as `[Dm7] [G7]`, and other bars with a chord or a `/` for
+each beat, `/` holding the chord before. A bar that only holds the chord
+before is a `/`.
+
+The key is the major key whose scale has the most notes of the chords, each
+counted for as long as it lasts, and the chords on its root or the minor
+chords on the root of its relative minor counting more. It is the relative
+minor when the chart ends on a minor chord on that root.
+*/
+use crate::pattern::Meter;
+use crate::song::{changes_text, Change};
+use crate::tempo::bpm;
+use komp_core::{Chord, Key};
+
+const MAJOR_SCALE: [u8; 7] = [0, 2, 4, 5, 7, 9, 11];
+const BARS_PER_LINE: usize = 4;
+
+fn chord_text(chord: Option<Chord>) -> String {
+    match chord {
+        Some(chord) => format!("[{}]", chord),
+        None => "[N.C.]".to_string(),
+    }
+}
+
+fn bar_text(changes: &[Change], beats: u8) -> String {
+    if changes.is_empty() {
+        return "/".to_string();
+    }
+    changes_text(changes, beats, chord_text)
+}
+
+fn is_minor(chord: Chord) -> bool {
+    matches!(
+        chord,
+        Chord::Minor(_)
+            | Chord::Minor6(_)
+            | Chord::Minor6_9(_)
+            | Chord::Minor7(_)
+            | Chord::Minor7_9(_)
+            | Chord::Minor7_11(_)
+            | Chord::MinorMaj7(_)
+            | Chord::MinorMaj7_9(_)
+            | Chord::MinorAdd9(_)
+    )
+}
+
+// the key of the chords, with the chord each plays for how many beats
+fn key(bars: &[Vec<Change>], beats: u8) -> Option<String> {
+    let mut lasting: Vec<(Chord, u32)> = vec![];
+    let mut beat = 0;
+    for (bar, changes) in bars.iter().enumerate() {
+        for change in changes {
+            let at = bar as u32 * beats as u32 + change.beat as u32;
+            if let Some((_, length)) = lasting.last_mut() {
+                *length += at - beat;
+            }
+            beat = at;
+            if let Some(chord) = change.chord {
+                lasting.push((chord, 0));
+            }
+        }
+    }
+    let (last, length) = lasting.last_mut()?;
+    *length += bars.len() as u32 * beats as u32 - beat;
+    let last = *last;
+    let score = |key: u8| -> u32 {
+        lasting
+            .iter()
+            .map(|(chord, length)| {
+                let in_scale = chord
+                    .tones()
+                    .iter()
+                    .filter(|tone| MAJOR_SCALE.contains(&((chord.key().0 + *tone + 12 - key) % 12)))
+                    .count();
+                let root = chord.key().0;
+                let tonic = root == key || (is_minor(*chord) && root == (key + 9) % 12);
+                let tonic = if tonic { 2 } else { 0 };
+                (in_scale as u32 + tonic) * length
+            })
+            .sum()
+    };
+    // a tie goes to the key the chart ends on
+    let ending = last.key().0;
+    let key = (0..12)
+        .map(|offset| (ending + offset) % 12)
+        .max_by_key(|key| (score(*key), std::cmp::Reverse((*key + 12 - ending) % 12)))?;
+    if is_minor(last) && ending == (key + 9) % 12 {
+        Some(format!("{}m", Key(ending)))
+    } else {
+        Some(Key(key).to_string())
+    }
+}
+
+/// A ChordPro chart of the changes of each bar.
+pub fn write_chordpro(
+    title: &str,
+    meter: Meter,
+    us_per_quarter: u32,
+    bars: &[Vec<Change>],
+) -> String {
+    let mut text = format!("{{title: {}}}\n", title);
+    if let Some(key) = key(bars, meter.beats) {
+        text.push_str(&format!("{{key: {}}}\n", key));
+    }
+    text.push_str(&format!("{{time: {}}}\n", meter));
+    text.push_str(&format!("{{tempo: {}}}\n\n", bpm(us_per_quarter).round()));
+    for line in bars.chunks(BARS_PER_LINE) {
+        let bars: Vec<String> = line
+            .iter()
+            .map(|changes| bar_text(changes, meter.beats))
+            .collect();
+        text.push_str(&format!("| {} |\n", bars.join(" | ")));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::COMMON_TIME;
+    use komp_core::*;
+
+    fn bar(changes: &[(u8, Chord)]) -> Vec<Change> {
+        changes
+            .iter()
+            .map(|(beat, chord)| Change {
+                beat: *beat,
+                chord: Some(*chord),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_write_chordpro() {
+        let bars = vec![
+            bar(&[(0, Chord::Minor7(D_KEY)), (2, Chord::Major7(G_KEY))]),
+            bar(&[(0, Chord::MajorMaj7(C_KEY))]),
+            vec![],
+            bar(&[(0, Chord::Minor7(A_KEY)), (3, Chord::Major7b9(E_KEY))]),
+            vec![Change {
+                beat: 1,
+                chord: None,
+            }],
+        ];
+        assert_eq!(
+            write_chordpro("jam", COMMON_TIME, 500_000, &bars),
+            "{title: jam}\n\
+             {key: C}\n\
+             {time: 4/4}\n\
+             {tempo: 120}\n\
+             \n\
+             | [Dm7] [G7] | [Cmaj7] | / | [Am7] / / [E7b9] |\n\
+             | / [N.C.] / / |\n"
+        );
+    }
+
+    #[test]
+    fn test_key() {
+        let minor_blues = vec![
+            bar(&[(0, Chord::Minor7(C_KEY))]),
+            bar(&[(0, Chord::Minor7(F_KEY))]),
+            bar(&[(0, Chord::Minor7(C_KEY))]),
+            bar(&[(0, Chord::MajorMaj7(GSHARP_KEY)), (2, Chord::Major7(G_KEY))]),
+            bar(&[(0, Chord::Minor7(C_KEY))]),
+        ];
+        assert_eq!(key(&minor_blues, 4), Some("Cm".to_string()));
+        let blues = vec![
+            bar(&[(0, Chord::Major7(F_KEY))]),
+            bar(&[(0, Chord::Major7(ASHARP_KEY))]),
+            bar(&[(0, Chord::Major7(F_KEY))]),
+            vec![],
+        ];
+        assert_eq!(key(&blues, 4), Some("F".to_string()));
+        assert_eq!(
+            key(&[bar(&[(0, Chord::Major(G_KEY))])], 3),
+            Some("G".to_string())
+        );
+        assert_eq!(key(&[vec![]], 4), None);
+    }
+}
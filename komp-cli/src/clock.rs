@@ -0,0 +1,246 @@
+/** Following an external MIDI beat clock.
+
+The master sends 24 timing clocks per quarter, and Start/Stop/Continue to
+control the transport. The first clock after a Start is the first beat of
+the song. Clocks arrive with some jitter, and an occasional clock may get
+lost, so both the tempo and the time of each clock are estimated by
+exponential smoothing, rather than taken as they come.
+*/
+const SONG_POSITION_POINTER: u8 = 0xF2;
+const TIMING_CLOCK: u8 = 0xF8;
+const START: u8 = 0xFA;
+const CONTINUE: u8 = 0xFB;
+const STOP: u8 = 0xFC;
+const CLOCKS_PER_QUARTER: u64 = 24;
+const CLOCKS_PER_SIXTEENTH: u64 = 6;
+
+// how much of each new measurement goes into the estimates
+const PERIOD_SMOOTHING: f64 = 0.05;
+const PHASE_SMOOTHING: f64 = 0.2;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ClockFollower {
+    running: bool,
+    // the clock number of the next clock to be received
+    next_clock: u64,
+    // the smoothed time of the latest clock, and its number
+    clock: Option<(f64, u64)>,
+    // the smoothed time between clocks
+    period: Option<f64>,
+    last_time: Option<u64>,
+}
+
+impl ClockFollower {
+    pub fn new() -> ClockFollower {
+        ClockFollower {
+            running: false,
+            next_clock: 0,
+            clock: None,
+            period: None,
+            last_time: None,
+        }
+    }
+
+    /// Handle a single MIDI message received at `time` (in nanoseconds).
+    pub fn receive(&mut self, time: u64, data: &[u8]) {
+        match data {
+            [TIMING_CLOCK] => self.receive_clock(time),
+            [START] => {
+                self.running = true;
+                self.next_clock = 0;
+                self.clock = None;
+            }
+            [CONTINUE] => {
+                self.running = true;
+                self.clock = None;
+            }
+            [STOP] => self.running = false,
+            [SONG_POSITION_POINTER, lsb, msb] if !self.running => {
+                let sixteenths = (*msb as u64) << 7 | *lsb as u64;
+                self.next_clock = sixteenths * CLOCKS_PER_SIXTEENTH;
+            }
+            _ => (),
+        }
+    }
+
+    fn receive_clock(&mut self, time: u64) {
+        // clocks keep coming while stopped, which keeps the tempo up to date
+        let mut clocks = 1;
+        if let Some(last_time) = self.last_time {
+            let interval = time.saturating_sub(last_time) as f64;
+            match self.period {
+                Some(period) => {
+                    // a lost clock shows up as a (roughly) doubled interval
+                    clocks = std::cmp::max(1, (interval / period).round() as u64);
+                    let measured = interval / clocks as f64;
+                    self.period = Some(period + PERIOD_SMOOTHING * (measured - period));
+                }
+                None => self.period = Some(interval),
+            }
+        }
+        self.last_time = Some(time);
+
+        if !self.running {
+            return;
+        }
+        self.clock = match (self.clock, self.period) {
+            (Some((clock_time, number)), Some(period)) => {
+                let predicted = clock_time + clocks as f64 * period;
+                let smoothed = predicted + PHASE_SMOOTHING * (time as f64 - predicted);
+                Some((smoothed, number + clocks))
+            }
+            _ => Some((time as f64, self.next_clock)),
+        };
+        self.next_clock = self.clock.map_or(0, |(_, number)| number + 1);
+    }
+
+    /// The estimated tempo of the master.
+    pub fn us_per_quarter(&self) -> Option<u32> {
+        self.period
+            .map(|period| (period * CLOCKS_PER_QUARTER as f64 / 1_000.0).round() as u32)
+    }
+
+    /// The latest quarter (counted from the start of the song) and the
+    /// estimated time it started, while the master is running.
+    pub fn beat(&self) -> Option<(u64, u64)> {
+        match (self.running, self.clock, self.period) {
+            (true, Some((clock_time, number)), Some(period)) => {
+                let quarter = number / CLOCKS_PER_QUARTER;
+                let clocks_into_quarter = (number % CLOCKS_PER_QUARTER) as f64;
+                Some((quarter, (clock_time - clocks_into_quarter * period) as u64))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::play::{NS_PER_MS, NS_PER_US};
+
+    const ORIGIN: u64 = 200_000_000_000_000;
+
+    // a deterministic jitter of up to +/- max_jitter ns
+    fn jitter(clock: u64, max_jitter: u64) -> i64 {
+        let hash = clock
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .rotate_left(29);
+        (hash % (2 * max_jitter + 1)) as i64 - max_jitter as i64
+    }
+
+    // sends Start, and then `quarters` worth of clocks
+    fn follow(
+        follower: &mut ClockFollower,
+        quarters: u64,
+        us_per_quarter: impl Fn(u64) -> u64,
+        max_jitter: u64,
+        lost: impl Fn(u64) -> bool,
+    ) -> Vec<u64> {
+        let mut exact_time = ORIGIN;
+        let mut beat_times = vec![];
+        follower.receive(ORIGIN - 10 * NS_PER_MS, &[START]);
+        for clock in 0..quarters * CLOCKS_PER_QUARTER {
+            if clock % CLOCKS_PER_QUARTER == 0 {
+                beat_times.push(exact_time);
+            }
+            let time = (exact_time as i64 + jitter(clock, max_jitter)) as u64;
+            if !lost(clock) {
+                follower.receive(time, &[TIMING_CLOCK]);
+            }
+            exact_time += us_per_quarter(clock / CLOCKS_PER_QUARTER) * NS_PER_US / 24;
+        }
+        beat_times
+    }
+
+    fn assert_close(actual: u64, expected: u64, tolerance: u64) {
+        let difference = (actual as i64 - expected as i64).unsigned_abs();
+        assert!(
+            difference <= tolerance,
+            "{} differs {} from {}",
+            actual,
+            difference,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_steady_clock() {
+        let mut follower = ClockFollower::new();
+        let beat_times = follow(&mut follower, 8, |_| 500_000, 0, |_| false);
+
+        assert_eq!(follower.us_per_quarter(), Some(500_000));
+        let (quarter, time) = follower.beat().unwrap();
+        assert_eq!(quarter, 7);
+        assert_close(time, beat_times[7], 1_000);
+    }
+
+    #[test]
+    fn test_nothing_before_start() {
+        let mut follower = ClockFollower::new();
+        follower.receive(ORIGIN, &[TIMING_CLOCK]);
+        follower.receive(ORIGIN + 20 * NS_PER_MS, &[TIMING_CLOCK]);
+        assert_eq!(follower.beat(), None);
+        assert_eq!(follower.us_per_quarter(), Some(480_000));
+    }
+
+    #[test]
+    fn test_first_clock_after_start_is_first_beat() {
+        let mut follower = ClockFollower::new();
+        follower.receive(ORIGIN, &[TIMING_CLOCK]);
+        follower.receive(ORIGIN + 5 * NS_PER_MS, &[START]);
+        follower.receive(ORIGIN + 20 * NS_PER_MS, &[TIMING_CLOCK]);
+        assert_eq!(follower.beat(), Some((0, ORIGIN + 20 * NS_PER_MS)));
+    }
+
+    #[test]
+    fn test_jittery_clock() {
+        let mut follower = ClockFollower::new();
+        // up to a millisecond of jitter on each clock
+        let beat_times = follow(&mut follower, 16, |_| 500_000, NS_PER_MS, |_| false);
+
+        assert_close(follower.us_per_quarter().unwrap() as u64, 500_000, 2_500);
+        let (quarter, time) = follower.beat().unwrap();
+        assert_eq!(quarter, 15);
+        assert_close(time, beat_times[15], NS_PER_MS / 2);
+    }
+
+    #[test]
+    fn test_drifting_clock() {
+        let mut follower = ClockFollower::new();
+        // accelerating from 120 to 135 BPM
+        let tempo = |quarter| 500_000 - quarter * 2_000;
+        let beat_times = follow(&mut follower, 32, tempo, NS_PER_MS / 2, |_| false);
+
+        assert_close(follower.us_per_quarter().unwrap() as u64, tempo(31), 5_000);
+        let (quarter, time) = follower.beat().unwrap();
+        assert_eq!(quarter, 31);
+        // smoothing makes the estimate lag a little behind an accelerating master
+        assert_close(time, beat_times[31], 2 * NS_PER_MS);
+    }
+
+    #[test]
+    fn test_lost_clocks() {
+        let mut follower = ClockFollower::new();
+        let beat_times = follow(&mut follower, 8, |_| 500_000, 0, |clock| clock % 17 == 5);
+
+        assert_eq!(follower.us_per_quarter(), Some(500_000));
+        let (quarter, time) = follower.beat().unwrap();
+        assert_eq!(quarter, 7);
+        assert_close(time, beat_times[7], 1_000);
+    }
+
+    #[test]
+    fn test_stop_and_continue() {
+        let mut follower = ClockFollower::new();
+        follow(&mut follower, 2, |_| 500_000, 0, |_| false);
+        follower.receive(ORIGIN + 1_000 * NS_PER_MS, &[STOP]);
+        assert_eq!(follower.beat(), None);
+
+        // continue from the second bar
+        follower.receive(ORIGIN + 1_010 * NS_PER_MS, &[SONG_POSITION_POINTER, 16, 0]);
+        follower.receive(ORIGIN + 1_020 * NS_PER_MS, &[CONTINUE]);
+        follower.receive(ORIGIN + 1_030 * NS_PER_MS, &[TIMING_CLOCK]);
+        assert_eq!(follower.beat(), Some((4, ORIGIN + 1_030 * NS_PER_MS)));
+    }
+}
@@ -41,11 +41,106 @@ T1 2 3
 [- - D]
 
 At T1, we press C E and G, at T2 we release C and press B, at T3 we release E and press D.
+
+A key is in the fourth octave unless it is followed by its octave (from -1 to 9), so
+"C3 E3 G3" is C major an octave lower, and "Eb5" is the Eb an octave above the Eb in "Eb".
+A key is pressed with velocity 100 unless it is followed by ! and its velocity, as in
+"C!80" or "Eb5!40".
+A step may be followed by * and its number of units, so "[C*3 D]" means "[C _ _ D]",
+"[-*4 C]" rests for four units before pressing C, and "_*2" holds the previous step for
+two more units, up to 65536 units to a part. Steps and parts may also be separated by
+line breaks.
+
+A chord symbol in braces presses the notes of the chord together, its root in the fourth
+octave unless the braces are followed by an octave. So "{Cmaj7}" means "C E G B" and
+"[{Cmaj7} {Fm}3]" moves from Cmaj7 to F minor an octave lower. A slash chord such as
+"{F/A}" adds the bass note below the chord. Chords may be mixed with keys, holds and
+rests, and take velocities and units like keys do: "[{C}!80 _ - B]".
+
+An expression that cannot be read is an error telling the line and column (both from 1)
+of the step that is wrong.
 */
+use komp_core::{Chord, Key};
+use std::fmt;
+
+const DEFAULT_OCTAVE: i32 = 4;
+const DEFAULT_VELOCITY: u8 = 100;
+// the most units of a part, so that the ticks of any unit fit
+const MAX_UNITS: u32 = 1 << 16;
+
+// the notes pressed together in a step, none for a rest
+#[derive(Clone, Debug, PartialEq)]
+struct Step {
+    notes: Vec<u8>,
+    velocity: u8,
+    units: u32,
+}
 
-type Step = (Option<u8>, u8);
 type Part = Vec<Step>;
 
+/// Where a chord change expression went wrong, and why.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DslError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl DslError {
+    // at a byte offset of the expression
+    fn at(chord_change_dsl: &str, offset: usize, message: String) -> DslError {
+        let before = &chord_change_dsl[..offset.min(chord_change_dsl.len())];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        DslError {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for DslError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for DslError {}
+
+// the byte offset of a step, which is a slice of the expression
+fn offset(chord_change_dsl: &str, step: &str) -> usize {
+    step.as_ptr() as usize - chord_change_dsl.as_ptr() as usize
+}
+
+fn check_brackets(chord_change_dsl: &str) -> Result<(), DslError> {
+    let mut open = None;
+    for (i, c) in chord_change_dsl.char_indices() {
+        match (c, open) {
+            ('[', None) => open = Some(i),
+            ('[', Some(_)) => {
+                return Err(DslError::at(
+                    chord_change_dsl,
+                    i,
+                    "[ inside brackets".to_string(),
+                ))
+            }
+            (']', Some(_)) => open = None,
+            (']', None) => {
+                return Err(DslError::at(chord_change_dsl, i, "] without [".to_string()))
+            }
+            _ => (),
+        }
+    }
+    match open {
+        Some(i) => Err(DslError::at(chord_change_dsl, i, "[ without ]".to_string())),
+        None => Ok(()),
+    }
+}
+
 fn split_parts(chord_change_dsl: &str) -> Vec<Vec<&str>> {
     let mut start = 0;
     let mut in_chunk = false;
@@ -53,7 +148,7 @@ fn split_parts(chord_change_dsl: &str) -> Vec<Vec<&str>> {
     let mut was_space = true;
     let mut res = vec![];
     let chunk_len = |i: usize, was_chunk: bool| if was_chunk { i - 1 } else { i };
-    for (i, c) in chord_change_dsl.chars().enumerate() {
+    for (i, c) in chord_change_dsl.char_indices() {
         if c == '[' {
             start = i + 1;
             in_chunk = true;
@@ -62,7 +157,7 @@ fn split_parts(chord_change_dsl: &str) -> Vec<Vec<&str>> {
             in_chunk = false;
             was_chunk = true;
         }
-        if c == ' ' {
+        if c.is_whitespace() {
             if !in_chunk {
                 if !was_space {
                     res.push(steps(&chord_change_dsl[start..chunk_len(i, was_chunk)]));
@@ -72,7 +167,7 @@ fn split_parts(chord_change_dsl: &str) -> Vec<Vec<&str>> {
             was_chunk = false;
             was_space = true;
         }
-        if c != ' ' {
+        if !c.is_whitespace() {
             was_space = false;
         }
     }
@@ -89,82 +184,189 @@ fn steps(chord_change: &str) -> Vec<&str> {
     chord_change.split_whitespace().collect()
 }
 
-fn parse_steps(chord_steps: Vec<&str>) -> Part {
-    let mut res = vec![];
+fn parse_note(name: &str) -> Option<u8> {
+    let pitch_class = match name.get(..1)? {
+        "C" => 0,
+        "D" => 2,
+        "E" => 4,
+        "F" => 5,
+        "G" => 7,
+        "A" => 9,
+        "B" => 11,
+        _ => return None,
+    };
+    let (pitch_class, octave) = match &name[1..] {
+        rest if rest.starts_with('#') => (pitch_class + 1, &rest[1..]),
+        rest if rest.starts_with('b') => (pitch_class - 1, &rest[1..]),
+        rest => (pitch_class, rest),
+    };
+    // the lowest notes are in octave -1
+    let octave = match octave {
+        "" => DEFAULT_OCTAVE,
+        "-1" => -1,
+        octave if octave.len() == 1 => octave.parse::<i32>().ok()?,
+        _ => return None,
+    };
+    let note = (octave + 1) * 12 + pitch_class;
+    if (0..=127).contains(&note) {
+        Some(note as u8)
+    } else {
+        None
+    }
+}
+
+// a chord symbol in braces with an optional octave, like {Cmaj7}, {F/A} or {Dm7}3
+fn parse_chord(name: &str) -> Option<Vec<u8>> {
+    let end = name.find('}')?;
+    let symbol = name.get(1..end)?;
+    let octave = match &name[end + 1..] {
+        "" => DEFAULT_OCTAVE as u8,
+        octave if octave.len() == 1 => octave.parse::<u8>().ok()?,
+        _ => return None,
+    };
+    // 6/9 chords have a slash too
+    let (chord, bass) = match symbol.parse::<Chord>() {
+        Ok(chord) => (chord, None),
+        Err(_) => {
+            let slash = symbol.rfind('/')?;
+            let chord = symbol[..slash].parse::<Chord>().ok()?;
+            let bass = symbol[slash + 1..].parse::<Key>().ok()?;
+            (chord, Some(bass))
+        }
+    };
+    let mut notes = chord.notes(octave, 0);
+    if let Some(Key(bass)) = bass {
+        // in the octave below the root
+        notes.insert(0, octave * 12 + bass);
+    }
+    if notes.iter().all(|note| *note <= 127) {
+        Some(notes)
+    } else {
+        None
+    }
+}
+
+// a step split into its key, velocity and number of units, like C4!80*2
+fn split_step(step: &str) -> (&str, Option<&str>, Option<&str>) {
+    let (step, units) = match step.find('*') {
+        Some(i) => (&step[..i], Some(&step[i + 1..])),
+        None => (step, None),
+    };
+    match step.find('!') {
+        Some(i) => (&step[..i], Some(&step[i + 1..]), units),
+        None => (step, None, units),
+    }
+}
+
+fn parse_steps(chord_change_dsl: &str, chord_steps: Vec<&str>) -> Result<Part, DslError> {
+    let mut res: Part = vec![];
+    let mut total_units = 0;
     for v in chord_steps {
-        match v {
-            "C" => res.push((Some(komp_core::NOTE_C4), 1)),
-            "C#" => res.push((Some(komp_core::NOTE_CSHARP4), 1)),
-            "Db" => res.push((Some(komp_core::NOTE_DFLAT4), 1)),
-            "D" => res.push((Some(komp_core::NOTE_D4), 1)),
-            "D#" => res.push((Some(komp_core::NOTE_DSHARP4), 1)),
-            "Eb" => res.push((Some(komp_core::NOTE_EFLAT4), 1)),
-            "E" => res.push((Some(komp_core::NOTE_E4), 1)),
-            "F" => res.push((Some(komp_core::NOTE_F4), 1)),
-            "F#" => res.push((Some(komp_core::NOTE_FSHARP4), 1)),
-            "Gb" => res.push((Some(komp_core::NOTE_GFLAT4), 1)),
-            "G" => res.push((Some(komp_core::NOTE_G4), 1)),
-            "G#" => res.push((Some(komp_core::NOTE_GSHARP4), 1)),
-            "Ab" => res.push((Some(komp_core::NOTE_AFLAT4), 1)),
-            "A" => res.push((Some(komp_core::NOTE_A4), 1)),
-            "A#" => res.push((Some(komp_core::NOTE_ASHARP4), 1)),
-            "Bb" => res.push((Some(komp_core::NOTE_BFLAT4), 1)),
-            "B" => res.push((Some(komp_core::NOTE_B4), 1)),
-            "-" => res.push((None, 1)),
-            "_" => {
-                if let Some(last) = res.last_mut() {
-                    *last = (last.0, last.1 + 1)
-                }
+        let error =
+            |message: String| DslError::at(chord_change_dsl, offset(chord_change_dsl, v), message);
+        let (key, velocity, units) = split_step(v);
+        let units = match units {
+            Some(units) => match units.parse::<u32>() {
+                Ok(units) if (1..=MAX_UNITS).contains(&units) => units,
+                _ => return Err(error(format!("wrong number of units: {}", v))),
+            },
+            None => 1,
+        };
+        total_units += units;
+        if total_units > MAX_UNITS {
+            return Err(error(format!("more than {} units", MAX_UNITS)));
+        }
+        let velocity = match velocity {
+            Some(_) if key == "-" || key == "_" => {
+                return Err(error(format!("velocity without a key: {}", v)))
             }
-            _ => (),
+            Some(velocity) => match velocity.parse::<u8>() {
+                Ok(velocity) if (1..=127).contains(&velocity) => velocity,
+                _ => return Err(error(format!("wrong velocity: {}", v))),
+            },
+            None => DEFAULT_VELOCITY,
+        };
+        match key {
+            "-" => res.push(Step {
+                notes: vec![],
+                velocity,
+                units,
+            }),
+            "_" => match res.last_mut() {
+                Some(last) => last.units += units,
+                None => return Err(error("nothing to hold before _".to_string())),
+            },
+            key if key.starts_with('{') => match parse_chord(key) {
+                Some(notes) => res.push(Step {
+                    notes,
+                    velocity,
+                    units,
+                }),
+                None => return Err(error(format!("unknown chord: {}", v))),
+            },
+            key => match parse_note(key) {
+                Some(note) => res.push(Step {
+                    notes: vec![note],
+                    velocity,
+                    units,
+                }),
+                None => return Err(error(format!("unknown step: {}", v))),
+            },
         }
     }
-    res
+    Ok(res)
 }
 
-fn parse_dsl(chord_change_dsl: &str) -> Vec<Part> {
+fn parse_dsl(chord_change_dsl: &str) -> Result<Vec<Part>, DslError> {
+    check_brackets(chord_change_dsl)?;
     let parts = split_parts(chord_change_dsl);
     let mut interpreted_parts = vec![];
     for part in parts {
-        interpreted_parts.push(parse_steps(part));
+        interpreted_parts.push(parse_steps(chord_change_dsl, part)?);
     }
 
-    fn sum_len(s: &Part) -> u8 {
-        s.iter().map(|i| i.1).sum()
+    fn sum_len(s: &Part) -> u32 {
+        s.iter().map(|i| i.units).sum()
     }
 
-    let max_len: u8 = interpreted_parts
-        .iter()
-        .map(sum_len)
-        .max()
-        .expect("some steps");
+    let max_len: u32 = match interpreted_parts.iter().map(sum_len).max() {
+        Some(max_len) if max_len > 0 => max_len,
+        _ => {
+            return Err(DslError::at(
+                chord_change_dsl,
+                chord_change_dsl.len(),
+                "no steps".to_string(),
+            ))
+        }
+    };
     for steps in interpreted_parts.iter_mut() {
-        let current_len: u8 = sum_len(steps);
+        let current_len: u32 = sum_len(steps);
         if let Some(item) = steps.last_mut() {
-            *item = (item.0, max_len - current_len + item.1);
+            item.units += max_len - current_len;
         }
     }
 
-    interpreted_parts
+    Ok(interpreted_parts)
 }
-use crate::pattern::{create_note, TimeCode};
-use crate::play::TimedEvent;
+use crate::pattern::create_note;
+use crate::play::{Event, TimedEvent};
 
 fn step_to_timed_midi_events(
-    step: Step,
+    step: &Step,
     offset_ticks: u32,
     ticks_per_unit: u32,
     unit_parts: u32,
 ) -> (Vec<TimedEvent>, u32) {
-    let (maybe_note, number_of_units) = step;
-    let end_time = offset_ticks + number_of_units as u32 * ticks_per_unit;
-    if let Some(note) = maybe_note {
-        let length = ticks_per_unit * (number_of_units as u32 * unit_parts - 1) / unit_parts;
-        let (on, off) = create_note(offset_ticks, length, 0, note, 100);
-        (vec![on, off], end_time)
-    } else {
-        (vec![], end_time)
+    let number_of_units = step.units;
+    let end_time = offset_ticks + number_of_units * ticks_per_unit;
+    let length = ticks_per_unit * (number_of_units * unit_parts - 1) / unit_parts;
+    let mut events = vec![];
+    for note in &step.notes {
+        let (on, off) = create_note(offset_ticks, length, 0, *note, step.velocity);
+        events.push(on);
+        events.push(off);
     }
+    (events, end_time)
 }
 
 fn part_to_timed_midi_events(
@@ -177,7 +379,7 @@ fn part_to_timed_midi_events(
     let mut current_time = offset_ticks;
     for step in part.iter() {
         let (mut step_events, end_part) =
-            step_to_timed_midi_events(*step, current_time, ticks_per_unit, unit_parts);
+            step_to_timed_midi_events(step, current_time, ticks_per_unit, unit_parts);
         current_time = end_part;
         part_events.append(&mut step_events);
     }
@@ -207,27 +409,143 @@ pub fn interpret_dsl(
     offset_ticks: u32,
     ticks_per_unit: u32,
     unit_parts: u32,
-) -> Vec<TimedEvent> {
-    let parts = parse_dsl(chord_change_dsl);
-    parts_to_timed_midi_events(&parts, offset_ticks, ticks_per_unit, unit_parts)
+) -> Result<Vec<TimedEvent>, DslError> {
+    let parts = parse_dsl(chord_change_dsl)?;
+    Ok(parts_to_timed_midi_events(
+        &parts,
+        offset_ticks,
+        ticks_per_unit,
+        unit_parts,
+    ))
+}
+
+// a note of a rendered part, in units
+struct Held {
+    start: u32,
+    end: u32,
+    note: u8,
+    velocity: u8,
+}
+
+fn note_name(note: u8) -> String {
+    let octave = note as i32 / 12 - 1;
+    if octave == DEFAULT_OCTAVE {
+        Key(note % 12).to_string()
+    } else {
+        format!("{}{}", Key(note % 12), octave)
+    }
+}
+
+fn render_part(part: &[Held], total_units: u32) -> String {
+    let mut steps = vec![];
+    let hold = |steps: &mut Vec<String>, units: u32| {
+        steps.extend((1..units).map(|_| "_".to_string()));
+    };
+    let mut current_unit = 0;
+    for held in part {
+        if held.start > current_unit {
+            steps.push("-".to_string());
+            hold(&mut steps, held.start - current_unit);
+        }
+        if held.velocity == DEFAULT_VELOCITY {
+            steps.push(note_name(held.note));
+        } else {
+            steps.push(format!("{}!{}", note_name(held.note), held.velocity));
+        }
+        hold(&mut steps, held.end - held.start);
+        current_unit = held.end;
+    }
+    if current_unit < total_units {
+        steps.push("-".to_string());
+        hold(&mut steps, total_units - current_unit);
+    }
+    // a note that lasts as long as the others needs no brackets
+    if let [held] = part {
+        if held.start == 0 && held.end == total_units {
+            return steps.remove(0);
+        }
+    }
+    format!("[{}]", steps.join(" "))
+}
+
+/// The chord change expression of the notes of `timed_events`, which are sorted, with
+/// `ticks_per_unit` ticks to each unit of the expression, as `interpret_dsl` reads it.
+/// A note starts and ends at the nearest unit, and lasts at least one unit. The notes are
+/// written in as few parts as possible, a note in the first part that is free by then.
+pub fn render_dsl(timed_events: &[TimedEvent], ticks_per_unit: u32) -> String {
+    let ticks_per_unit = ticks_per_unit.max(1);
+    let unit = |ticks: u32| (ticks + ticks_per_unit / 2) / ticks_per_unit;
+    let mut notes = vec![];
+    let mut sounding: Vec<(u8, u32, u8)> = vec![];
+    for te in timed_events {
+        match te.event {
+            Event::NoteOn { note, velocity, .. } => sounding.push((note, te.timing, velocity)),
+            Event::NoteOff { note, .. } => {
+                if let Some(index) = sounding.iter().position(|sounding| sounding.0 == note) {
+                    let (note, timing, velocity) = sounding.remove(index);
+                    let start = unit(timing);
+                    notes.push(Held {
+                        start,
+                        end: unit(te.timing).max(start + 1),
+                        note,
+                        velocity,
+                    });
+                }
+            }
+        }
+    }
+    notes.sort_by_key(|held| (held.start, held.note));
+
+    let mut parts: Vec<Vec<Held>> = vec![];
+    for held in notes {
+        match parts
+            .iter_mut()
+            .find(|part| part.iter().all(|other| other.end <= held.start))
+        {
+            Some(part) => part.push(held),
+            None => parts.push(vec![held]),
+        }
+    }
+    let total_units = parts
+        .iter()
+        .flatten()
+        .map(|held| held.end)
+        .max()
+        .unwrap_or(0);
+    parts
+        .iter()
+        .map(|part| render_part(part, total_units))
+        .collect::<Vec<String>>()
+        .join(" ")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::pattern::TimeCode;
+    use komp_core::NOTE_A3;
     use komp_core::NOTE_A4;
+    use komp_core::NOTE_AFLAT3;
     use komp_core::NOTE_AFLAT4;
     use komp_core::NOTE_ASHARP4;
+    use komp_core::NOTE_B3;
     use komp_core::NOTE_B4;
+    use komp_core::NOTE_BFLAT3;
     use komp_core::NOTE_BFLAT4;
+    use komp_core::NOTE_C3;
     use komp_core::NOTE_C4;
+    use komp_core::NOTE_C5;
     use komp_core::NOTE_CSHARP4;
     use komp_core::NOTE_D4;
     use komp_core::NOTE_DFLAT4;
     use komp_core::NOTE_DSHARP4;
+    use komp_core::NOTE_E3;
     use komp_core::NOTE_E4;
     use komp_core::NOTE_EFLAT4;
+    use komp_core::NOTE_EFLAT5;
+    use komp_core::NOTE_F3;
     use komp_core::NOTE_F4;
+    use komp_core::NOTE_FSHARP2;
     use komp_core::NOTE_FSHARP4;
     use komp_core::NOTE_G4;
     use komp_core::NOTE_GFLAT4;
@@ -327,13 +645,25 @@ mod tests {
         }
     }
 
+    // the steps with the default velocity
+    fn part(steps: Vec<(Option<u8>, u32)>) -> Part {
+        steps
+            .into_iter()
+            .map(|(note, units)| Step {
+                notes: note.into_iter().collect(),
+                velocity: DEFAULT_VELOCITY,
+                units,
+            })
+            .collect()
+    }
+
     macro_rules! test_parse_steps {
         ($name:ident, $change:expr, $il:expr) => {
             #[test]
             fn $name() {
                 let steps = steps($change);
-                let res = parse_steps(steps);
-                let il = $il;
+                let res = parse_steps($change, steps).unwrap();
+                let il = part($il);
                 assert!(
                     res.eq(&il),
                     "{} became {:?} expected {:?}",
@@ -415,8 +745,8 @@ mod tests {
         ($name:ident, $change_dsl:expr, $il:expr) => {
             #[test]
             fn $name() {
-                let interpreted_parts = parse_dsl($change_dsl);
-                let il = $il;
+                let interpreted_parts = parse_dsl($change_dsl).unwrap();
+                let il: Vec<Part> = $il.into_iter().map(part).collect();
                 assert!(
                     interpreted_parts.eq(&il),
                     "{} \nbecame   {:?}\nexpected {:?}",
@@ -476,7 +806,8 @@ mod tests {
         let ticks_per_quarter = 96;
         let offset = TimeCode::new(1, 0, 0).ticks(ticks_per_quarter);
         let expected_end_part = TimeCode::new(4, 0, 0).ticks(ticks_per_quarter);
-        let (data, end_part) = step_to_timed_midi_events((None, 3), offset, ticks_per_quarter * 4, 4);
+        let (data, end_part) =
+            step_to_timed_midi_events(&part(vec![(None, 3)])[0], offset, ticks_per_quarter * 4, 4);
 
         assert_eq!(end_part, expected_end_part);
         assert_eq!(data.len(), 0);
@@ -488,8 +819,12 @@ mod tests {
         let offset = TimeCode::new(1, 0, 0).ticks(ticks_per_quarter);
         let expected_end_note = TimeCode::new(3, 3, 0).ticks(ticks_per_quarter);
         let expected_end_part = TimeCode::new(4, 0, 0).ticks(ticks_per_quarter);
-        let (data, end_part) =
-            step_to_timed_midi_events((Some(NOTE_C4), 3), offset, ticks_per_quarter * 4, 4);
+        let (data, end_part) = step_to_timed_midi_events(
+            &part(vec![(Some(NOTE_C4), 3)])[0],
+            offset,
+            ticks_per_quarter * 4,
+            4,
+        );
 
         assert_eq!(end_part, expected_end_part);
         assert_eq!(
@@ -532,7 +867,7 @@ mod tests {
     fn test_part_to_timed_midi_events() {
         let ticks_per_quarter = 96;
         let offset = TimeCode::new(1, 0, 0).ticks(ticks_per_quarter);
-        let parts = vec![(Some(NOTE_E4), 1), (None, 1), (Some(NOTE_EFLAT4), 1)];
+        let parts = part(vec![(Some(NOTE_E4), 1), (None, 1), (Some(NOTE_EFLAT4), 1)]);
         let events = part_to_timed_midi_events(&parts, offset, ticks_per_quarter * 4, 4);
         let note_data: Vec<NoteData> = events.iter().map(to_note_data).collect();
 
@@ -579,9 +914,9 @@ mod tests {
         let ticks_per_quarter = 96;
         let offset = TimeCode::new(1, 0, 0).ticks(ticks_per_quarter);
         let parts = vec![
-            vec![(Some(NOTE_C4), 2)],
-            vec![(Some(NOTE_E4), 1), (Some(NOTE_EFLAT4), 1)],
-            vec![(Some(NOTE_G4), 2)],
+            part(vec![(Some(NOTE_C4), 2)]),
+            part(vec![(Some(NOTE_E4), 1), (Some(NOTE_EFLAT4), 1)]),
+            part(vec![(Some(NOTE_G4), 2)]),
         ];
 
         let events = parts_to_timed_midi_events(&parts, offset, ticks_per_quarter * 4, 4);
@@ -593,7 +928,258 @@ mod tests {
         let ticks_per_quarter = 96;
         let offset = TimeCode::new(1, 0, 0).ticks(ticks_per_quarter);
 
-        let events = interpret_dsl("C [E Eb] G", offset, ticks_per_quarter * 4, 4);
+        let events = interpret_dsl("C [E Eb] G", offset, ticks_per_quarter * 4, 4).unwrap();
         assert_c_to_c_minor(&events, offset, ticks_per_quarter);
     }
+
+    #[test]
+    fn test_octaves() {
+        assert_eq!(parse_note("C"), Some(NOTE_C4));
+        assert_eq!(parse_note("C3"), Some(NOTE_C3));
+        assert_eq!(parse_note("Eb5"), Some(NOTE_EFLAT5));
+        assert_eq!(parse_note("F#2"), Some(NOTE_FSHARP2));
+        assert_eq!(parse_note("Cb4"), Some(NOTE_B3));
+        assert_eq!(parse_note("C0"), Some(12));
+        assert_eq!(parse_note("G9"), Some(127));
+        assert_eq!(parse_note("C-1"), Some(0));
+        assert_eq!(parse_note("B-1"), Some(11));
+        for wrong in &["G#9", "C10", "H", "c", "Cb-1", "C-2", "Cx"] {
+            assert_eq!(parse_note(wrong), None, "{}", wrong);
+        }
+    }
+
+    test_parse_steps!(
+        octaves,
+        "C3 _ Eb5",
+        vec![(Some(NOTE_C3), 2), (Some(NOTE_EFLAT5), 1)]
+    );
+    test_parse_steps!(
+        durations,
+        "C*3 -*2 _ D _*2",
+        vec![(Some(NOTE_C4), 3), (None, 3), (Some(NOTE_D4), 3)]
+    );
+
+    #[test]
+    fn test_velocities() {
+        let steps = parse_steps("C!80 D5!1*2 E", steps("C!80 D5!1*2 E")).unwrap();
+        let velocities: Vec<(u8, u32)> = steps
+            .iter()
+            .map(|step| (step.velocity, step.units))
+            .collect();
+        assert_eq!(velocities, vec![(80, 1), (1, 2), (DEFAULT_VELOCITY, 1)]);
+
+        let events = interpret_dsl("[C!80 -] E3!20", 0, 4, 4).unwrap();
+        assert_eq!(
+            events[..2],
+            [
+                TimedEvent {
+                    timing: 0,
+                    event: NoteOn {
+                        channel: 0,
+                        note: NOTE_E3,
+                        velocity: 20
+                    }
+                },
+                TimedEvent {
+                    timing: 0,
+                    event: NoteOn {
+                        channel: 0,
+                        note: NOTE_C4,
+                        velocity: 80
+                    }
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lines() {
+        let events = interpret_dsl("[C - -]\n[E _ -]\n[G _ _]\n[- B _]\n[- - D]", 0, 4, 4).unwrap();
+        let notes: Vec<NoteData> = events.iter().map(to_note_data).collect();
+        assert_eq!(
+            notes[..3],
+            [
+                NoteData::On(NOTE_C4, 0),
+                NoteData::On(NOTE_E4, 0),
+                NoteData::On(NOTE_G4, 0)
+            ]
+        );
+        assert_eq!(events.len(), 10);
+    }
+
+    #[test]
+    fn test_chord_symbols() {
+        assert_eq!(
+            parse_chord("{Cmaj7}"),
+            Some(vec![NOTE_C4, NOTE_E4, NOTE_G4, NOTE_B4])
+        );
+        assert_eq!(
+            parse_chord("{Fm}3"),
+            Some(vec![NOTE_F3, NOTE_AFLAT3, NOTE_C4])
+        );
+        assert_eq!(
+            parse_chord("{F/A}"),
+            Some(vec![NOTE_A3, NOTE_F4, NOTE_A4, NOTE_C5])
+        );
+        assert_eq!(
+            parse_chord("{C/Bb}"),
+            Some(vec![NOTE_BFLAT3, NOTE_C4, NOTE_E4, NOTE_G4])
+        );
+        assert_eq!(
+            parse_chord("{D6/9}"),
+            Some(vec![NOTE_D4, NOTE_E4, NOTE_FSHARP4, NOTE_A4, NOTE_B4])
+        );
+        for wrong in &["{H}", "{C/H}", "{C}10", "{C", "{Cmaj8}", "{G}9"] {
+            assert_eq!(parse_chord(wrong), None, "{}", wrong);
+        }
+    }
+
+    #[test]
+    fn test_chord_changes() {
+        let events = interpret_dsl("[{Cmaj7}!80 {Fm}3*2] [- {F/A}]", 0, 4, 4).unwrap();
+        let note_ons: Vec<(u32, u8, u8)> = events
+            .iter()
+            .filter_map(|te| match te.event {
+                NoteOn { note, velocity, .. } => Some((te.timing, note, velocity)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            note_ons,
+            vec![
+                (0, NOTE_C4, 80),
+                (0, NOTE_E4, 80),
+                (0, NOTE_G4, 80),
+                (0, NOTE_B4, 80),
+                (4, NOTE_F3, 100),
+                (4, NOTE_AFLAT3, 100),
+                (4, NOTE_A3, 100),
+                (4, NOTE_C4, 100),
+                (4, NOTE_F4, 100),
+                (4, NOTE_A4, 100),
+                (4, NOTE_C5, 100),
+            ]
+        );
+        assert_eq!(
+            interpret_dsl("C {Cmaj7} {Cmaj8}", 0, 4, 4)
+                .unwrap_err()
+                .to_string(),
+            "line 1, column 11: unknown chord: {Cmaj8}"
+        );
+    }
+
+    #[test]
+    fn test_render_dsl() {
+        let canonical = [
+            "C E G",
+            "C [E Eb] G",
+            "C3 Eb5 G9",
+            "[C!80 -] E",
+            "[C _ -] [E Eb _] G",
+            "[C B _] [E _ D] G",
+            "E [- D _] [- _ C]",
+            "[C F] [E A] [G C5]",
+            "[C _ C] [Eb!40 - Eb!40]",
+            "C-1 Bb-1 C0",
+        ];
+        for dsl in canonical.iter() {
+            let events = interpret_dsl(dsl, 0, 4, 4).unwrap();
+            assert_eq!(render_dsl(&events, 4), *dsl);
+        }
+        assert_eq!(render_dsl(&[], 4), "");
+    }
+
+    #[test]
+    fn test_render_dsl_round_trip() {
+        let expressions = [
+            "[C - -] [E _ -] [G _ _] [- B _] [- - D]",
+            "C E G [Bb -]",
+            "[C*3 D] [-*4 C]",
+            "{Cmaj7} [{F/A}3 -]",
+            "[C\n_ D!20]\n[E5 -*2]",
+            "[C C] [E Eb] [G G]",
+        ];
+        for dsl in expressions.iter() {
+            let events = interpret_dsl(dsl, 0, 96, 4).unwrap();
+            let rendered = render_dsl(&events, 96);
+            assert_eq!(
+                interpret_dsl(&rendered, 0, 96, 4),
+                Ok(events),
+                "{} became {}",
+                dsl,
+                rendered
+            );
+        }
+    }
+
+    #[test]
+    fn test_render_dsl_quantizes() {
+        let events = vec![
+            TimedEvent {
+                timing: 1,
+                event: NoteOn {
+                    channel: 0,
+                    note: NOTE_C4,
+                    velocity: 100,
+                },
+            },
+            TimedEvent {
+                timing: 5,
+                event: NoteOn {
+                    channel: 0,
+                    note: NOTE_E4,
+                    velocity: 90,
+                },
+            },
+            TimedEvent {
+                timing: 6,
+                event: NoteOff {
+                    channel: 0,
+                    note: NOTE_C4,
+                    velocity: 64,
+                },
+            },
+            TimedEvent {
+                timing: 7,
+                event: NoteOff {
+                    channel: 0,
+                    note: NOTE_E4,
+                    velocity: 64,
+                },
+            },
+        ];
+        assert_eq!(render_dsl(&events, 4), "C [- E!90]");
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |dsl: &str| {
+            let error = interpret_dsl(dsl, 0, 4, 4).unwrap_err();
+            (error.line, error.column)
+        };
+        assert_eq!(error(""), (1, 1));
+        assert_eq!(error("  "), (1, 3));
+        assert_eq!(error("C E H"), (1, 5));
+        assert_eq!(error("C [E Eb] G\n[Bb X]"), (2, 5));
+        assert_eq!(error("C [E Eb G"), (1, 3));
+        assert_eq!(error("C E] G"), (1, 4));
+        assert_eq!(error("C [E [Eb]] G"), (1, 6));
+        assert_eq!(error("[_ C]"), (1, 2));
+        assert_eq!(error("C!0"), (1, 1));
+        assert_eq!(error("C!128"), (1, 1));
+        assert_eq!(error("-!80"), (1, 1));
+        assert_eq!(error("C E*0"), (1, 3));
+        assert_eq!(error("C E*x"), (1, 3));
+        assert_eq!(error("C\n  C10"), (2, 3));
+        assert_eq!(error("C E*100000000"), (1, 3));
+        assert_eq!(error("[C*65536 _]"), (1, 10));
+        assert_eq!(
+            interpret_dsl("[C*65535 _]", 0, 384, 4).map(|events| events.len()),
+            Ok(2)
+        );
+        assert_eq!(
+            interpret_dsl("C E H", 0, 4, 4).unwrap_err().to_string(),
+            "line 1, column 5: unknown step: H"
+        );
+    }
 }
@@ -0,0 +1,233 @@
+/** General MIDI drums, written as grids.
+
+A drum grid has a lane for each drum, such as `HH: x.x.x.x. SN: ..x...x.`:
+the name of the drum, then its rhythm. The rhythm is a step for each
+character, evenly over a bar: `x` is a hit, `X` an accented hit, `o` a
+ghost note, and `.` or `-` a rest; `|` and spaces may be used to group the
+steps. It can also be a Euclidean rhythm such as `E(3,8)`, with hits that
+only play some of the time (see `rhythm`). Each lane has its own number
+of steps, so triplets can play against straight eighths.
+*/
+use crate::pattern::{create_note, Meter};
+use crate::play::TimedEvent;
+use crate::random::Random;
+use crate::rhythm::Rhythm;
+
+/// The drums play on the tenth MIDI channel, as General MIDI has it.
+pub const DRUM_CHANNEL: u8 = 9;
+
+/// The General MIDI drums: a short name, a long name and the note.
+pub const DRUMS: [(&str, &str, u8); 17] = [
+    ("BD", "kick", 36),
+    ("RS", "side-stick", 37),
+    ("SN", "snare", 38),
+    ("CP", "clap", 39),
+    ("HH", "closed-hat", 42),
+    ("PH", "pedal-hat", 44),
+    ("LT", "low-tom", 45),
+    ("OH", "open-hat", 46),
+    ("MT", "mid-tom", 47),
+    ("CR", "crash", 49),
+    ("HT", "high-tom", 50),
+    ("RD", "ride", 51),
+    ("CH", "chinese", 52),
+    ("RB", "ride-bell", 53),
+    ("TB", "tambourine", 54),
+    ("CB", "cowbell", 56),
+    ("SH", "shaker", 70),
+];
+
+/// The note of a drum, by its short or long name, or by its number.
+pub fn drum_note(name: &str) -> Option<u8> {
+    DRUMS
+        .iter()
+        .find(|(short, long, _)| {
+            short.eq_ignore_ascii_case(name) || long.eq_ignore_ascii_case(name)
+        })
+        .map(|(_, _, note)| *note)
+        .or_else(|| name.parse().ok().filter(|note| *note < 128))
+}
+
+/// A lane of a grid: a drum and its rhythm.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DrumLane {
+    pub note: u8,
+    pub rhythm: Rhythm,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DrumGrid {
+    lanes: Vec<DrumLane>,
+}
+
+impl std::str::FromStr for DrumGrid {
+    type Err = String;
+    fn from_str(s: &str) -> Result<DrumGrid, String> {
+        let mut lanes: Vec<(u8, String)> = vec![];
+        for word in s.split_whitespace() {
+            if let Some(name) = word.strip_suffix(':') {
+                let note = drum_note(name).ok_or(format!("unknown drum: {}", name))?;
+                lanes.push((note, String::new()));
+                continue;
+            }
+            let (_, rhythm) = lanes
+                .last_mut()
+                .ok_or(format!("steps without a drum: {}", word))?;
+            rhythm.push_str(word);
+        }
+        if lanes.is_empty() {
+            return Err(format!("no drums in grid: {}", s));
+        }
+        let lanes = lanes
+            .into_iter()
+            .map(|(note, rhythm)| {
+                let rhythm = rhythm
+                    .parse()
+                    .map_err(|e| format!("drum {}: {}", note, e))?;
+                Ok(DrumLane { note, rhythm })
+            })
+            .collect::<Result<Vec<DrumLane>, String>>()?;
+        Ok(DrumGrid { lanes })
+    }
+}
+
+impl DrumGrid {
+    /// The seed of the hits that only play some of the time, which are
+    /// decided independently for each drum.
+    pub fn with_seed(mut self, seed: u64) -> DrumGrid {
+        for lane in &mut self.lanes {
+            let lane_seed = Random::keyed(seed, &[lane.note as u64]).next_u64();
+            lane.rhythm = lane.rhythm.clone().with_seed(lane_seed);
+        }
+        self
+    }
+
+    /// A bar of the grid, on the drum channel. Each hit lasts half a step.
+    pub fn create_bar(&self, ticks_per_quarter: u32, meter: Meter, bar: u64) -> Vec<TimedEvent> {
+        let mut timed_events = vec![];
+        for lane in &self.lanes {
+            for hit in lane.rhythm.hits(ticks_per_quarter, meter, bar) {
+                let (on, off) = create_note(
+                    hit.timing,
+                    hit.length,
+                    DRUM_CHANNEL,
+                    lane.note,
+                    hit.velocity,
+                );
+                timed_events.push(on);
+                timed_events.push(off);
+            }
+        }
+        timed_events.sort();
+        timed_events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::COMMON_TIME;
+    use crate::play::Event;
+
+    fn note_ons(timed_events: &[TimedEvent]) -> Vec<(u32, u8, u8)> {
+        timed_events
+            .iter()
+            .filter_map(|te| match te.event {
+                Event::NoteOn {
+                    channel,
+                    note,
+                    velocity,
+                } => {
+                    assert_eq!(channel, DRUM_CHANNEL);
+                    Some((te.timing, note, velocity))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_drum_note() {
+        assert_eq!(drum_note("SN"), Some(38));
+        assert_eq!(drum_note("closed-hat"), Some(42));
+        assert_eq!(drum_note("kick"), drum_note("bd"));
+        assert_eq!(drum_note("81"), Some(81));
+        assert_eq!(drum_note("gong"), None);
+    }
+
+    #[test]
+    fn test_parse_grid() {
+        let grid: DrumGrid = "HH: x.x.x.x. SN: ..X. ..o. BD: E(3, 8)".parse().unwrap();
+        assert_eq!(
+            grid.lanes,
+            vec![
+                DrumLane {
+                    note: 42,
+                    rhythm: "x.x.x.x.".parse().unwrap()
+                },
+                DrumLane {
+                    note: 38,
+                    rhythm: "..X...o.".parse().unwrap()
+                },
+                DrumLane {
+                    note: 36,
+                    rhythm: Rhythm::euclidean(3, 8, 0)
+                },
+            ]
+        );
+        assert!("x.x.".parse::<DrumGrid>().is_err());
+        assert!("HH: x.y.".parse::<DrumGrid>().is_err());
+        assert!("GONG: x...".parse::<DrumGrid>().is_err());
+        assert!("HH: SN: x...".parse::<DrumGrid>().is_err());
+    }
+
+    #[test]
+    fn test_create_bar() {
+        let grid: DrumGrid = "BD: x...x... SN: ..x...x.".parse().unwrap();
+        for &ticks_per_quarter in &[96, 480] {
+            let eighth = ticks_per_quarter / 2;
+            let timed_events = grid.create_bar(ticks_per_quarter, COMMON_TIME, 0);
+            assert_eq!(
+                note_ons(&timed_events),
+                vec![
+                    (0, 36, 100),
+                    (2 * eighth, 38, 100),
+                    (4 * eighth, 36, 100),
+                    (6 * eighth, 38, 100)
+                ]
+            );
+            assert_eq!(timed_events[1].timing, eighth / 2);
+        }
+    }
+
+    #[test]
+    fn test_lanes_with_their_own_steps() {
+        // triplets against quarters, in 3/4
+        let grid: DrumGrid = "RD: xxxxxxxxx BD: x..".parse().unwrap();
+        let timed_events = grid.create_bar(96, Meter::new(3, 4), 0);
+        let ride: Vec<u32> = note_ons(&timed_events)
+            .iter()
+            .filter(|(_, note, _)| *note == 51)
+            .map(|(timing, _, _)| *timing)
+            .collect();
+        assert_eq!(ride, vec![0, 32, 64, 96, 128, 160, 192, 224, 256]);
+    }
+
+    #[test]
+    fn test_drums_are_seeded_apart() {
+        let grid: DrumGrid = "HH: xxxxxxxxxxxxxxxx@0.5 OH: xxxxxxxxxxxxxxxx@0.5"
+            .parse::<DrumGrid>()
+            .unwrap()
+            .with_seed(1);
+        let timed_events = grid.create_bar(96, COMMON_TIME, 0);
+        let timings = |drum: u8| -> Vec<u32> {
+            note_ons(&timed_events)
+                .iter()
+                .filter(|(_, note, _)| *note == drum)
+                .map(|(timing, _, _)| *timing)
+                .collect()
+        };
+        assert_ne!(timings(42), timings(46));
+        assert_eq!(timed_events, grid.create_bar(96, COMMON_TIME, 0));
+    }
+}
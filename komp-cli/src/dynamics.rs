@@ -0,0 +1,151 @@
+/** Following the dynamics of the player.
+
+The velocities of the notes played are smoothed into a playing intensity,
+which scales the velocities of the accompaniment, and which can switch the
+accompaniment to a busier variation when the player digs in. When the
+player rests, the intensity dies down, so that the accompaniment calms down
+too.
+*/
+// how much of each new velocity goes into the intensity
+const SMOOTHING: f64 = 0.15;
+// the intensity at which the accompaniment plays as written
+const REFERENCE_VELOCITY: f64 = 80.0;
+const MIN_VELOCITY_SCALE: f64 = 0.4;
+const MAX_VELOCITY_SCALE: f64 = 1.5;
+// how much softer than the threshold to play, to get back from busier
+const HYSTERESIS: f64 = 10.0;
+// how long (in nanoseconds) the player rests before the intensity dies down,
+// and how long it then takes to halve
+const REST: u64 = 1_000_000_000;
+const HALF_LIFE: f64 = 2_000_000_000.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Dynamics {
+    intensity: Option<f64>,
+    busier: bool,
+    // how long since the last note
+    resting: u64,
+}
+
+impl Dynamics {
+    pub fn new() -> Dynamics {
+        Dynamics {
+            intensity: None,
+            busier: false,
+            resting: 0,
+        }
+    }
+
+    pub fn play(&mut self, velocity: u8) {
+        let velocity = velocity as f64;
+        self.intensity = Some(match self.intensity {
+            Some(intensity) => intensity + SMOOTHING * (velocity - intensity),
+            None => velocity,
+        });
+        self.resting = 0;
+    }
+
+    /// Let `elapsed` nanoseconds pass without a note.
+    pub fn rest(&mut self, elapsed: u64) {
+        let dying = (self.resting + elapsed).saturating_sub(std::cmp::max(self.resting, REST));
+        self.resting += elapsed;
+        if let Some(intensity) = &mut self.intensity {
+            *intensity *= 0.5f64.powf(dying as f64 / HALF_LIFE);
+        }
+    }
+
+    pub fn intensity(&self) -> Option<f64> {
+        self.intensity
+    }
+
+    /// How much to scale the velocities of the accompaniment.
+    pub fn velocity_scale(&self) -> f64 {
+        self.intensity.map_or(1.0, |intensity| {
+            (intensity / REFERENCE_VELOCITY).clamp(MIN_VELOCITY_SCALE, MAX_VELOCITY_SCALE)
+        })
+    }
+
+    /// Whether the player digs in, playing harder than `threshold`. Once
+    /// digging in, the player needs to play a little softer to stop.
+    pub fn digs_in(&mut self, threshold: f64) -> bool {
+        let intensity = self.intensity.unwrap_or(0.0);
+        if intensity > threshold {
+            self.busier = true;
+        } else if intensity < threshold - HYSTERESIS {
+            self.busier = false;
+        }
+        self.busier
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_as_written_before_playing() {
+        let dynamics = Dynamics::new();
+        assert_eq!(dynamics.intensity(), None);
+        assert_eq!(dynamics.velocity_scale(), 1.0);
+    }
+
+    #[test]
+    fn test_smoothing() {
+        let mut dynamics = Dynamics::new();
+        dynamics.play(80);
+        assert_eq!(dynamics.velocity_scale(), 1.0);
+        // a single loud note only moves the intensity a little
+        dynamics.play(120);
+        assert_eq!(dynamics.intensity(), Some(86.0));
+        for _ in 0..50 {
+            dynamics.play(120);
+        }
+        assert!((dynamics.intensity().unwrap() - 120.0).abs() < 0.1);
+        assert!((dynamics.velocity_scale() - 1.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_velocity_scale_is_limited() {
+        let mut dynamics = Dynamics::new();
+        dynamics.play(10);
+        assert_eq!(dynamics.velocity_scale(), MIN_VELOCITY_SCALE);
+        dynamics = Dynamics::new();
+        dynamics.play(127);
+        assert_eq!(dynamics.velocity_scale(), MAX_VELOCITY_SCALE);
+    }
+
+    #[test]
+    fn test_digs_in() {
+        let mut dynamics = Dynamics::new();
+        assert!(!dynamics.digs_in(100.0));
+        dynamics.play(105);
+        assert!(dynamics.digs_in(100.0));
+        // a little softer is not enough to calm down
+        dynamics = Dynamics {
+            intensity: Some(95.0),
+            ..dynamics
+        };
+        assert!(dynamics.digs_in(100.0));
+        dynamics.play(40);
+        assert!(!dynamics.digs_in(100.0));
+    }
+
+    #[test]
+    fn test_rest() {
+        let mut dynamics = Dynamics::new();
+        dynamics.rest(10 * REST);
+        assert_eq!(dynamics.intensity(), None);
+        dynamics.play(120);
+        assert!(dynamics.digs_in(100.0));
+        // a short rest changes nothing
+        dynamics.rest(REST / 2);
+        assert_eq!(dynamics.intensity(), Some(120.0));
+        dynamics.rest(REST / 2 + HALF_LIFE as u64);
+        assert_eq!(dynamics.intensity(), Some(60.0));
+        assert!(!dynamics.digs_in(100.0));
+        // playing again starts the rest over
+        dynamics.play(60);
+        dynamics.rest(REST);
+        assert_eq!(dynamics.intensity(), Some(60.0));
+    }
+}
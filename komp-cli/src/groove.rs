@@ -0,0 +1,277 @@
+/** Grooves shift the timing and velocity of events per grid position.
+
+A groove divides each quarter into a number of steps, and gives each step
+of a cycle a shift (as a fraction of a step, positive is late) and a
+velocity scale. Every event takes the groove of its nearest step. The
+groove is applied when the pattern is scheduled, so the stored pattern
+keeps its straight timing.
+*/
+use crate::play::Event;
+use crate::smf::Smf;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GrooveStep {
+    pub shift: f64,
+    pub velocity: f64,
+}
+
+const STRAIGHT: GrooveStep = GrooveStep {
+    shift: 0.0,
+    velocity: 1.0,
+};
+
+// shuffled off-beats are played a little softer
+const SHUFFLE_VELOCITY: f64 = 0.8;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Groove {
+    steps_per_quarter: u32,
+    steps: Vec<GrooveStep>,
+}
+
+impl Groove {
+    pub fn straight() -> Groove {
+        Groove::template(1, vec![STRAIGHT])
+    }
+
+    /// A groove with explicit steps, like the groove templates of an MPC.
+    pub fn template(steps_per_quarter: u32, steps: Vec<GrooveStep>) -> Groove {
+        assert!(steps_per_quarter > 0 && !steps.is_empty());
+        Groove {
+            steps_per_quarter,
+            steps,
+        }
+    }
+
+    /// Delay every second step, so that the first step of each pair lasts
+    /// `percentage` of the pair: 50% is straight, 66% a triplet feel.
+    pub fn swing(percentage: f64, steps_per_quarter: u32) -> Groove {
+        let shift = percentage / 50.0 - 1.0;
+        Groove::template(
+            steps_per_quarter,
+            vec![
+                STRAIGHT,
+                GrooveStep {
+                    shift,
+                    velocity: 1.0,
+                },
+            ],
+        )
+    }
+
+    /// A triplet swing, with softer off-beats.
+    pub fn shuffle(steps_per_quarter: u32) -> Groove {
+        Groove::template(
+            steps_per_quarter,
+            vec![
+                STRAIGHT,
+                GrooveStep {
+                    shift: 1.0 / 3.0,
+                    velocity: SHUFFLE_VELOCITY,
+                },
+            ],
+        )
+    }
+
+    /// Extract a groove of `steps` steps from played notes, given as
+    /// (tick, velocity): each step gets the average timing deviation of the
+    /// notes nearest to it, and the velocity relative to the loudest step.
+    pub fn extract(
+        notes: &[(u32, u8)],
+        ticks_per_quarter: u32,
+        steps_per_quarter: u32,
+        steps: usize,
+    ) -> Groove {
+        let step_ticks = ticks_per_quarter as f64 / steps_per_quarter as f64;
+        let mut sums = vec![(0.0, 0.0, 0); steps];
+        for &(tick, velocity) in notes {
+            let position = tick as f64 / step_ticks;
+            let nearest = position.round();
+            let sum = &mut sums[nearest as usize % steps];
+            sum.0 += position - nearest;
+            sum.1 += velocity as f64;
+            sum.2 += 1;
+        }
+        let average_velocity = |(_, velocity, count): &(f64, f64, u32)| velocity / *count as f64;
+        let loudest = sums
+            .iter()
+            .filter(|sum| sum.2 > 0)
+            .map(average_velocity)
+            .fold(0.0, f64::max);
+        let steps = sums
+            .iter()
+            .map(|sum| match sum {
+                (_, _, 0) => STRAIGHT,
+                (shift, _, count) => GrooveStep {
+                    shift: shift / *count as f64,
+                    velocity: average_velocity(sum) / loudest,
+                },
+            })
+            .collect();
+        Groove::template(steps_per_quarter, steps)
+    }
+
+    /// Extract a groove from the note-ons of a reference MIDI file.
+    pub fn from_smf(smf: &Smf, steps_per_quarter: u32, steps: usize) -> Groove {
+        let notes: Vec<(u32, u8)> = smf
+            .note_ons()
+            .iter()
+            .map(|&(tick, _, _, velocity)| (tick, velocity))
+            .collect();
+        Groove::extract(
+            &notes,
+            smf.ticks_per_quarter as u32,
+            steps_per_quarter,
+            steps,
+        )
+    }
+
+    fn step(&self, timing: u32, ticks_per_quarter: u32) -> &GrooveStep {
+        let step_ticks = ticks_per_quarter as f64 / self.steps_per_quarter as f64;
+        let nearest = (timing as f64 / step_ticks).round() as usize;
+        &self.steps[nearest % self.steps.len()]
+    }
+
+    /// How much (in ticks) to shift an event at `timing`.
+    pub fn shift(&self, timing: u32, ticks_per_quarter: u32) -> f64 {
+        let step_ticks = ticks_per_quarter as f64 / self.steps_per_quarter as f64;
+        self.step(timing, ticks_per_quarter).shift * step_ticks
+    }
+
+    /// The event with the velocity of a note-on scaled for its step.
+    pub fn accent(&self, event: &Event, timing: u32, ticks_per_quarter: u32) -> Event {
+        let scale = self.step(timing, ticks_per_quarter).velocity;
+        event.map_velocity(|velocity| velocity * scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smf::tests::smf_data;
+
+    const TICKS_PER_QUARTER: u32 = 96;
+
+    fn note_on(velocity: u8) -> Event {
+        Event::NoteOn {
+            channel: 0,
+            note: 60,
+            velocity,
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_straight() {
+        let groove = Groove::straight();
+        assert_eq!(groove.shift(48, TICKS_PER_QUARTER), 0.0);
+        assert_eq!(
+            groove.accent(&note_on(100), 48, TICKS_PER_QUARTER),
+            note_on(100)
+        );
+    }
+
+    #[test]
+    fn test_eighth_swing() {
+        let groove = Groove::swing(66.0, 2);
+        assert_eq!(groove.shift(0, TICKS_PER_QUARTER), 0.0);
+        assert_eq!(groove.shift(96, TICKS_PER_QUARTER), 0.0);
+        // the second eighth starts 66% into the quarter
+        let shift = groove.shift(48, TICKS_PER_QUARTER);
+        assert_close(48.0 + shift, 0.66 * 96.0);
+        // events near the step go with it
+        assert_eq!(groove.shift(45, TICKS_PER_QUARTER), shift);
+    }
+
+    #[test]
+    fn test_sixteenth_shuffle() {
+        let groove = Groove::shuffle(4);
+        assert_close(groove.shift(24, TICKS_PER_QUARTER), 8.0);
+        assert_eq!(groove.shift(48, TICKS_PER_QUARTER), 0.0);
+        assert_eq!(
+            groove.accent(&note_on(100), 72, TICKS_PER_QUARTER),
+            note_on(80)
+        );
+        assert_eq!(
+            groove.accent(&note_on(100), 48, TICKS_PER_QUARTER),
+            note_on(100)
+        );
+    }
+
+    #[test]
+    fn test_template() {
+        let groove = Groove::template(
+            2,
+            vec![
+                GrooveStep {
+                    shift: -0.1,
+                    velocity: 1.5,
+                },
+                STRAIGHT,
+                STRAIGHT,
+            ],
+        );
+        // the template cycles every three eighths
+        assert_close(groove.shift(144, TICKS_PER_QUARTER), -4.8);
+        assert_eq!(
+            groove.accent(&note_on(100), 144, TICKS_PER_QUARTER),
+            note_on(127)
+        );
+        let note_off = Event::NoteOff {
+            channel: 0,
+            note: 60,
+            velocity: 64,
+        };
+        assert_eq!(groove.accent(&note_off, 144, TICKS_PER_QUARTER), note_off);
+    }
+
+    #[test]
+    fn test_extract() {
+        // late and soft off-beats, played twice
+        let notes = [
+            (0, 100),
+            (58, 60),
+            (96, 100),
+            (154, 60),
+            (192, 100),
+            (250, 60),
+        ];
+        let groove = Groove::extract(&notes, TICKS_PER_QUARTER, 2, 4);
+        assert_eq!(groove.steps.len(), 4);
+        assert_close(groove.shift(48, TICKS_PER_QUARTER), 10.0);
+        assert_eq!(groove.steps[0].velocity, 1.0);
+        assert_eq!(groove.steps[1].velocity, 0.6);
+        assert_eq!(groove.steps[2].velocity, 1.0);
+        assert_eq!(groove.steps[3].velocity, 0.6);
+
+        let notes = [(0, 100), (144, 50)];
+        let groove = Groove::extract(&notes, TICKS_PER_QUARTER, 2, 4);
+        // steps without notes are straight
+        assert_eq!(groove.steps[1], STRAIGHT);
+        assert_eq!(groove.steps[3].velocity, 0.5);
+    }
+
+    #[test]
+    fn test_from_smf() {
+        // at 480 ticks per quarter, with the second sixteenth 40 ticks late
+        let track = [
+            0x00, 0x99, 0x2A, 0x64, // hat
+            0x81, 0x20, 0x99, 0x2A, 0x32, // 160 ticks later
+        ];
+        let smf = Smf::parse(&smf_data(480, &[&track])).unwrap();
+        let groove = Groove::from_smf(&smf, 4, 2);
+        assert_close(groove.shift(24, TICKS_PER_QUARTER), 8.0);
+        assert_eq!(
+            groove.accent(&note_on(100), 24, TICKS_PER_QUARTER),
+            note_on(50)
+        );
+    }
+}
@@ -0,0 +1,250 @@
+/** Humanization of the timing and velocity of the accompaniment.
+
+Each note is moved by a random timing deviation and played with a random
+velocity deviation, with the notes of a chord spread out like a strum.
+Each channel (track) can be made tighter than the others. The random
+deviations only depend on the seed, the note and the pass through the
+pattern, so the same seed always gives the same performance, however it
+is sliced for scheduling.
+*/
+use crate::play::{Event, TimedEvent};
+use crate::random::Random;
+use std::collections::HashMap;
+
+const CHANNELS: usize = 16;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Humanizer {
+    seed: u64,
+    // the largest timing deviation, in nanoseconds
+    timing: u64,
+    // the largest velocity deviation
+    velocity: u8,
+    // the time between the notes of a chord, from the lowest note up
+    strum: u64,
+    // 0.0 for the full deviations, 1.0 for none
+    tightness: [f64; CHANNELS],
+}
+
+/// The place of an event in the pattern: the index of the note-on that
+/// starts its note, and the place of that note in its chord (0 for the
+/// lowest note).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NotePlace {
+    pub note_on: usize,
+    pub chord_rank: u32,
+}
+
+fn channel_note(event: &Event) -> (u8, u8) {
+    match *event {
+        Event::NoteOn { channel, note, .. } | Event::NoteOff { channel, note, .. } => {
+            (channel, note)
+        }
+    }
+}
+
+fn is_note_on(event: &Event) -> bool {
+    matches!(event, Event::NoteOn { velocity, .. } if *velocity > 0)
+}
+
+/// Find the note-on of each note-off, and the place of each note in its chord.
+pub fn note_places(timed_events: &[TimedEvent]) -> Vec<NotePlace> {
+    let mut places: Vec<NotePlace> = (0..timed_events.len())
+        .map(|index| NotePlace {
+            note_on: index,
+            chord_rank: 0,
+        })
+        .collect();
+    // in time order, with notes ending before notes starting at the same time
+    let mut order: Vec<usize> = (0..timed_events.len()).collect();
+    order.sort_by_key(|&index| {
+        let te = &timed_events[index];
+        (te.timing, is_note_on(&te.event))
+    });
+    let mut sounding: HashMap<(u8, u8), usize> = HashMap::new();
+    for &index in &order {
+        let te = &timed_events[index];
+        let (channel, note) = channel_note(&te.event);
+        if is_note_on(&te.event) {
+            let lower_notes = timed_events
+                .iter()
+                .filter(|other| other.timing == te.timing && is_note_on(&other.event))
+                .map(|other| channel_note(&other.event))
+                .filter(|&(other_channel, other_note)| {
+                    other_channel == channel && other_note < note
+                })
+                .count();
+            places[index].chord_rank = lower_notes as u32;
+            sounding.insert((channel, note), index);
+        } else if let Some(note_on) = sounding.remove(&(channel, note)) {
+            places[index] = places[note_on];
+        }
+    }
+    places
+}
+
+impl Humanizer {
+    /// A humanizer that changes nothing, until given some deviations.
+    pub fn new(seed: u64) -> Humanizer {
+        Humanizer {
+            seed,
+            timing: 0,
+            velocity: 0,
+            strum: 0,
+            tightness: [0.0; CHANNELS],
+        }
+    }
+    pub fn with_seed(mut self, seed: u64) -> Humanizer {
+        self.seed = seed;
+        self
+    }
+    pub fn with_timing(mut self, timing: u64) -> Humanizer {
+        self.timing = timing;
+        self
+    }
+    pub fn with_velocity(mut self, velocity: u8) -> Humanizer {
+        self.velocity = velocity;
+        self
+    }
+    pub fn with_strum(mut self, strum: u64) -> Humanizer {
+        self.strum = strum;
+        self
+    }
+    pub fn with_tightness(mut self, channel: u8, tightness: f64) -> Humanizer {
+        self.tightness[(channel & 0x0f) as usize] = tightness.clamp(0.0, 1.0);
+        self
+    }
+
+    /// How much (in nanoseconds) to shift an event in a pass of the
+    /// pattern, and the event with its velocity changed. Note-offs are
+    /// shifted as much as their note-ons, keeping the length of the notes.
+    pub fn perform(&self, place: NotePlace, event: Event, pass: u64) -> (i64, Event) {
+        let (channel, _) = channel_note(&event);
+        let looseness = 1.0 - self.tightness[(channel & 0x0f) as usize];
+        let mut random = Random::keyed(self.seed, &[place.note_on as u64, pass]);
+        let timing = random.next_centered() * self.timing as f64 * looseness;
+        let velocity_change = random.next_centered() * self.velocity as f64 * looseness;
+        let shift = timing.round() as i64 + (place.chord_rank as u64 * self.strum) as i64;
+        (
+            shift,
+            event.map_velocity(|velocity| velocity + velocity_change),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::{create_bar, create_note, COMMON_TIME};
+    use crate::play::NS_PER_MS;
+    use komp_core::{Chord, C_KEY};
+
+    fn velocity(event: Event) -> u8 {
+        match event {
+            Event::NoteOn { velocity, .. } | Event::NoteOff { velocity, .. } => velocity,
+        }
+    }
+
+    #[test]
+    fn test_note_places() {
+        let (c_on, c_off) = create_note(0, 48, 0, 60, 100);
+        let (e_on, e_off) = create_note(0, 48, 0, 64, 100);
+        // a retriggered C, and a bass C on another channel
+        let (c2_on, c2_off) = create_note(48, 48, 0, 60, 100);
+        let (bass_on, bass_off) = create_note(0, 96, 1, 36, 100);
+        let timed_events = vec![e_on, c_on, bass_on, c2_on, c_off, e_off, c2_off, bass_off];
+        let places = note_places(&timed_events);
+        let place = |note_on, chord_rank| NotePlace {
+            note_on,
+            chord_rank,
+        };
+        assert_eq!(
+            places,
+            vec![
+                place(0, 1),
+                place(1, 0),
+                place(2, 0),
+                place(3, 0),
+                place(1, 0),
+                place(0, 1),
+                place(3, 0),
+                place(2, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_no_deviations() {
+        let humanizer = Humanizer::new(1);
+        let (on, off) = create_note(0, 48, 0, 60, 100);
+        let place = NotePlace {
+            note_on: 0,
+            chord_rank: 2,
+        };
+        assert_eq!(humanizer.perform(place, on.event, 3), (0, on.event));
+        assert_eq!(humanizer.perform(place, off.event, 3), (0, off.event));
+    }
+
+    #[test]
+    fn test_deviations_are_reproducible() {
+        let humanizer = Humanizer::new(1)
+            .with_timing(10 * NS_PER_MS)
+            .with_velocity(20);
+        let timed_events = create_bar(96, COMMON_TIME, Chord::Major(C_KEY));
+        let places = note_places(&timed_events);
+        let perform = |humanizer: &Humanizer, pass| {
+            timed_events
+                .iter()
+                .enumerate()
+                .map(|(index, te)| humanizer.perform(places[index], te.event, pass))
+                .collect::<Vec<_>>()
+        };
+        let performed = perform(&humanizer, 0);
+        assert_eq!(performed, perform(&humanizer, 0));
+        assert_ne!(performed, perform(&humanizer, 1));
+        assert_ne!(
+            performed,
+            perform(&Humanizer::new(2).with_timing(10 * NS_PER_MS), 0)
+        );
+
+        for (index, (shift, event)) in performed.iter().enumerate() {
+            assert!(shift.unsigned_abs() <= 10 * NS_PER_MS);
+            // notes keep their length
+            assert_eq!(*shift, performed[places[index].note_on].0);
+            if is_note_on(event) {
+                assert!((100..=127).contains(&velocity(*event)));
+            } else {
+                assert_eq!(velocity(*event), 64);
+            }
+        }
+    }
+
+    #[test]
+    fn test_strum() {
+        let humanizer = Humanizer::new(1).with_strum(15 * NS_PER_MS);
+        let timed_events = create_bar(96, COMMON_TIME, Chord::Major(C_KEY));
+        let places = note_places(&timed_events);
+        let shifts: Vec<i64> = timed_events[..3]
+            .iter()
+            .enumerate()
+            .map(|(index, te)| humanizer.perform(places[index], te.event, 0).0)
+            .collect();
+        assert_eq!(shifts, vec![0, 15_000_000, 30_000_000]);
+    }
+
+    #[test]
+    fn test_tightness() {
+        let humanizer = Humanizer::new(1)
+            .with_timing(10 * NS_PER_MS)
+            .with_velocity(20)
+            .with_tightness(1, 1.0);
+        let (on, _) = create_note(0, 48, 1, 36, 100);
+        let place = NotePlace {
+            note_on: 0,
+            chord_rank: 0,
+        };
+        for pass in 0..10 {
+            assert_eq!(humanizer.perform(place, on.event, pass), (0, on.event));
+        }
+    }
+}
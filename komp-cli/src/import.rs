@@ -0,0 +1,398 @@
+/** Importing accompaniment patterns from MIDI clips.
+
+The notes of each track of a Standard MIDI File become a clip track of a
+style (see `style`), played over the chord the clip was recorded over, so
+that komp can move them to the chords that are played. A track with notes
+on more than one channel becomes a track for each channel. The tempo, the
+meter and the names of the tracks are taken from the file when it has
+them, and the notes may be quantized on the way.
+*/
+use crate::drums::DRUM_CHANNEL;
+use crate::pattern::{Meter, COMMON_TIME};
+use crate::smf::{Message, Smf, Track as SmfTrack};
+use crate::style::{ClipNote, Content, Section, Style, Track, Transpose};
+use komp_core::Chord;
+use std::collections::BTreeMap;
+
+const TRACK_NAME: u8 = 0x03;
+const SET_TEMPO: u8 = 0x51;
+const TIME_SIGNATURE: u8 = 0x58;
+
+pub struct ImportOptions {
+    /// The chord the clip was recorded over, read from a chord symbol.
+    pub source_chord: Chord,
+    /// Notes per whole note to quantize to, such as 16 for sixteenths.
+    pub quantize: Option<u32>,
+    /// The length of the pattern, otherwise the bars with notes.
+    pub bars: Option<u32>,
+    /// Channels (from 0) for the tracks (from 0) of the file.
+    pub channels: Vec<(usize, u8)>,
+    pub name: String,
+}
+
+impl ImportOptions {
+    pub fn new(source_chord: Chord) -> ImportOptions {
+        ImportOptions {
+            source_chord,
+            quantize: None,
+            bars: None,
+            channels: vec![],
+            name: String::new(),
+        }
+    }
+}
+
+// the first meta event of a type in any track
+fn meta(smf: &Smf, meta_type: u8) -> Option<&[u8]> {
+    smf.tracks
+        .iter()
+        .flatten()
+        .find_map(|event| match &event.message {
+            Message::Meta(t, data) if *t == meta_type => Some(&data[..]),
+            _ => None,
+        })
+}
+
+/// The first tempo of the file.
+pub fn tempo(smf: &Smf) -> Option<u32> {
+    match meta(smf, SET_TEMPO)? {
+        [a, b, c] => Some(u32::from_be_bytes([0, *a, *b, *c])).filter(|us| *us > 0),
+        _ => None,
+    }
+}
+
+/// The first time signature of the file, with a beat of a sixteenth at
+/// the shortest, as for `--meter`.
+pub fn meter(smf: &Smf) -> Result<Option<Meter>, String> {
+    match meta(smf, TIME_SIGNATURE) {
+        Some([beats, unit, ..]) if *beats > 0 && *unit <= 4 => {
+            Ok(Some(Meter::new(*beats, 1 << *unit)))
+        }
+        Some([beats, unit, ..]) => Err(format!(
+            "unsupported time signature: {}/{}",
+            beats,
+            1u64 << std::cmp::min(*unit, 63)
+        )),
+        Some(_) => Err("wrong time signature".to_string()),
+        None => Ok(None),
+    }
+}
+
+// the notes of a track by channel, pairing each note-on with the next
+// note-off of its note; a note still sounding ends with the track
+fn channel_notes(track: &SmfTrack) -> BTreeMap<u8, Vec<ClipNote>> {
+    let mut notes: BTreeMap<u8, Vec<ClipNote>> = BTreeMap::new();
+    let mut sounding: BTreeMap<(u8, u8), Vec<(u32, u8)>> = BTreeMap::new();
+    for event in track {
+        if let Message::Midi(data) = &event.message {
+            let (status, channel) = (data[0] & 0xF0, data[0] & 0x0F);
+            match (status, data.get(1), data.get(2)) {
+                (0x90, Some(&note), Some(&velocity)) if velocity > 0 => sounding
+                    .entry((channel, note))
+                    .or_default()
+                    .push((event.tick, velocity)),
+                (0x80, Some(&note), _) | (0x90, Some(&note), _) => {
+                    let started = sounding.get_mut(&(channel, note)).and_then(|started| {
+                        if started.is_empty() {
+                            None
+                        } else {
+                            Some(started.remove(0))
+                        }
+                    });
+                    if let Some((tick, velocity)) = started {
+                        notes.entry(channel).or_default().push(ClipNote {
+                            tick,
+                            length: event.tick - tick,
+                            note,
+                            velocity,
+                        });
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    let end = track.last().map_or(0, |event| event.tick);
+    for ((channel, note), started) in sounding {
+        for (tick, velocity) in started {
+            notes.entry(channel).or_default().push(ClipNote {
+                tick,
+                length: end - tick,
+                note,
+                velocity,
+            });
+        }
+    }
+    notes
+}
+
+fn program(track: &SmfTrack, channel: u8) -> Option<u8> {
+    track.iter().find_map(|event| match &event.message {
+        Message::Midi(data) if data[0] == (0xC0 | channel) => data.get(1).copied(),
+        _ => None,
+    })
+}
+
+pub fn track_name(track: &SmfTrack) -> Option<String> {
+    track.iter().find_map(|event| match &event.message {
+        Message::Meta(TRACK_NAME, data) => {
+            Some(String::from_utf8_lossy(data).trim().to_string()).filter(|name| !name.is_empty())
+        }
+        _ => None,
+    })
+}
+
+fn quantize(note: ClipNote, grid: u32) -> ClipNote {
+    let round = |ticks: u32| (ticks + grid / 2) / grid * grid;
+    ClipNote {
+        tick: round(note.tick),
+        length: round(note.length).max(grid),
+        ..note
+    }
+}
+
+/// A style with a section of the notes of `smf`, a clip track for each
+/// track and channel with notes.
+pub fn import_pattern(smf: &Smf, options: &ImportOptions) -> Result<Style, String> {
+    let resolution = smf.ticks_per_quarter as u32;
+    if resolution == 0 {
+        return Err("no ticks per quarter in the file".to_string());
+    }
+    let grid = match options.quantize {
+        Some(notes_per_whole) if notes_per_whole > 0 => {
+            Some((resolution * 4 / notes_per_whole).max(1))
+        }
+        Some(_) => return Err("wrong quantization: 0".to_string()),
+        None => None,
+    };
+    for (index, _) in &options.channels {
+        if *index >= smf.tracks.len() {
+            return Err(format!("no track {} in the file", index + 1));
+        }
+    }
+    let meter = meter(smf)?;
+    let bar_ticks = meter.unwrap_or(COMMON_TIME).ticks_per_bar(resolution);
+    if bar_ticks == 0 {
+        return Err(format!("too few ticks per quarter: {}", resolution));
+    }
+    let mut tracks = vec![];
+    for (index, smf_track) in smf.tracks.iter().enumerate() {
+        let new_channel = options
+            .channels
+            .iter()
+            .find(|(track, _)| *track == index)
+            .map(|(_, channel)| *channel);
+        let by_channel = channel_notes(smf_track);
+        let several = by_channel.len() > 1;
+        let name = track_name(smf_track).unwrap_or(format!("track {}", index + 1));
+        for (channel, notes) in by_channel {
+            let mut notes: Vec<ClipNote> = notes
+                .into_iter()
+                .map(|note| match grid {
+                    Some(grid) => quantize(note, grid),
+                    None => ClipNote {
+                        length: note.length.max(1),
+                        ..note
+                    },
+                })
+                .filter(|note| match options.bars {
+                    Some(bars) => note.tick < bars * bar_ticks,
+                    None => true,
+                })
+                .collect();
+            if notes.is_empty() {
+                continue;
+            }
+            notes.sort_by_key(|note| (note.tick, note.note));
+            let program = program(smf_track, channel);
+            // drums stay drums on another channel
+            let transpose = if channel == DRUM_CHANNEL {
+                Transpose::Fixed
+            } else {
+                Transpose::Chord
+            };
+            let channel = new_channel.unwrap_or(channel);
+            tracks.push(Track {
+                name: if several {
+                    format!("{} {}", name, channel + 1)
+                } else {
+                    name.clone()
+                },
+                channel,
+                program,
+                transpose,
+                content: Content::Clip {
+                    source: options.source_chord,
+                    resolution,
+                    notes,
+                },
+            });
+        }
+    }
+    if tracks.is_empty() {
+        return Err("no notes in the file".to_string());
+    }
+    let bars = options.bars.unwrap_or_else(|| {
+        let last_tick = tracks
+            .iter()
+            .filter_map(|track| match &track.content {
+                Content::Clip { notes, .. } => notes.iter().map(|note| note.tick).max(),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        last_tick / bar_ticks + 1
+    });
+    Ok(Style {
+        name: options.name.clone(),
+        us_per_quarter: tempo(smf),
+        meter,
+        sections: vec![Section {
+            name: "A".to_string(),
+            bars,
+            tracks,
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smf::tests::smf_data;
+    use komp_core::*;
+
+    fn note(tick: u32, length: u32, note: u8, velocity: u8) -> ClipNote {
+        ClipNote {
+            tick,
+            length,
+            note,
+            velocity,
+        }
+    }
+
+    fn clip(track: &Track) -> &[ClipNote] {
+        match &track.content {
+            Content::Clip { notes, .. } => notes,
+            _ => panic!("not a clip"),
+        }
+    }
+
+    fn piano() -> Vec<u8> {
+        vec![
+            0x00, 0xFF, 0x03, 0x05, b'p', b'i', b'a', b'n', b'o', // name
+            0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20, // 120 BPM
+            0x00, 0xFF, 0x58, 0x04, 0x03, 0x02, 0x18, 0x08, // 3/4
+            0x00, 0xC0, 0x04, // electric piano
+            0x02, 0x90, 0x3C, 0x64, // C4 on, a little late
+            0x00, 0x40, 0x50, // E4 on (running status)
+            0x5E, 0x80, 0x3C, 0x40, // C4 off
+            0x00, 0x90, 0x40, 0x00, // E4 off, as a note-on without velocity
+            0x81, 0x40, 0x43, 0x50, // G4 on in the second bar, never off
+            0x10, 0xFF, 0x2F, 0x00, // end of track
+        ]
+    }
+
+    const DRUMS: [u8; 12] = [
+        0x00, 0x99, 0x24, 0x64, // bass drum
+        0x30, 0x99, 0x24, 0x00, 0x00, 0x99, 0x26, 0x64, // snare, never off
+    ];
+
+    #[test]
+    fn test_import_pattern() {
+        let smf = Smf::parse(&smf_data(96, &[&piano(), &DRUMS])).unwrap();
+        let style = import_pattern(&smf, &ImportOptions::new(Chord::MajorMaj7(C_KEY))).unwrap();
+        assert_eq!(style.us_per_quarter, Some(500_000));
+        assert_eq!(style.meter, Some(Meter::new(3, 4)));
+        let section = &style.sections[0];
+        assert_eq!(section.bars, 2);
+        let piano = &section.tracks[0];
+        assert_eq!(piano.name, "piano");
+        assert_eq!(piano.channel, 0);
+        assert_eq!(piano.program, Some(4));
+        assert_eq!(piano.transpose, Transpose::Chord);
+        assert_eq!(
+            clip(piano),
+            [
+                note(2, 94, NOTE_C4, 100),
+                note(2, 94, NOTE_E4, 80),
+                note(288, 16, NOTE_G4, 80)
+            ]
+        );
+        let drums = &section.tracks[1];
+        assert_eq!(drums.name, "track 2");
+        assert_eq!(drums.transpose, Transpose::Fixed);
+        assert_eq!(
+            clip(drums),
+            [note(0, 48, 0x24, 100), note(48, 1, 0x26, 100)]
+        );
+    }
+
+    #[test]
+    fn test_options() {
+        let smf = Smf::parse(&smf_data(96, &[&piano(), &DRUMS])).unwrap();
+        let options = ImportOptions {
+            quantize: Some(16),
+            bars: Some(1),
+            channels: vec![(0, 2)],
+            name: "Clip".to_string(),
+            ..ImportOptions::new(Chord::Major(F_KEY))
+        };
+        let style = import_pattern(&smf, &options).unwrap();
+        assert_eq!(style.name, "Clip");
+        let section = &style.sections[0];
+        assert_eq!(section.bars, 1);
+        assert_eq!(section.tracks[0].channel, 2);
+        // the note in the second bar is left out
+        assert_eq!(
+            clip(&section.tracks[0]),
+            [note(0, 96, NOTE_C4, 100), note(0, 96, NOTE_E4, 80)]
+        );
+        assert_eq!(
+            clip(&section.tracks[1]),
+            [note(0, 48, 0x24, 100), note(48, 24, 0x26, 100)]
+        );
+        // and the style can be written and read again
+        assert_eq!(Style::parse(&style.to_toml().unwrap()), Ok(style));
+    }
+
+    #[test]
+    fn test_errors() {
+        let smf = Smf::parse(&smf_data(96, &[&piano()])).unwrap();
+        let options = |channels| ImportOptions {
+            channels,
+            ..ImportOptions::new(Chord::Major(C_KEY))
+        };
+        assert!(import_pattern(&smf, &options(vec![(1, 0)])).is_err());
+        let silent = Smf::parse(&smf_data(96, &[&[0x00, 0xFF, 0x2F, 0x00]])).unwrap();
+        assert!(import_pattern(&silent, &options(vec![])).is_err());
+        // 3/32 is not a meter komp plays
+        let mut short_beats = piano();
+        short_beats[21] = 0x05;
+        let smf = Smf::parse(&smf_data(96, &[&short_beats])).unwrap();
+        assert!(import_pattern(&smf, &options(vec![])).is_err());
+        // a sixteenth at 2 ticks to a quarter has no ticks
+        let mut sixteenths = piano();
+        sixteenths[21] = 0x04;
+        let smf = Smf::parse(&smf_data(2, &[&sixteenths])).unwrap();
+        assert!(import_pattern(&smf, &options(vec![])).is_err());
+    }
+
+    #[test]
+    fn test_drums_on_another_channel() {
+        let smf = Smf::parse(&smf_data(96, &[&piano(), &DRUMS])).unwrap();
+        let options = ImportOptions {
+            channels: vec![(0, DRUM_CHANNEL), (1, 2)],
+            ..ImportOptions::new(Chord::Major(C_KEY))
+        };
+        let style = import_pattern(&smf, &options).unwrap();
+        let tracks = &style.sections[0].tracks;
+        assert_eq!(
+            (tracks[0].channel, tracks[0].transpose),
+            (DRUM_CHANNEL, Transpose::Chord)
+        );
+        assert_eq!(
+            (tracks[1].channel, tracks[1].transpose),
+            (2, Transpose::Fixed)
+        );
+    }
+}
@@ -0,0 +1,760 @@
+/** iReal Pro charts, as exported in `irealb://` and `irealbook://` links.
+
+A link holds one song, or a playlist of songs separated by `===`, each a
+list of fields separated by `=`: the title, the composer, the style, the
+key, the chart and, in `irealb://` links, the tempo. The chart of an
+`irealb://` link follows `1r34LbKcu7` and is obfuscated: each block of 50
+characters has some of its characters swapped, and `Kcl`, `LZ` and `XyQ`
+stand for `| x`, ` |` and three spaces.
+
+A chart is a line of cells, four to a bar. A chord, a space, or a `p`
+(a slash, the chord before) takes a cell, and a chord plays from the beat
+of its cell. The chart also has:
+
+- barlines `|`, `[` and `]`, `{` and `}` for repeats, and `Z` for the end,
+- time signatures such as `T44` or `T34`, the meter of the whole chart,
+- section marks `*A`, `*B`, ..., `*i` for an intro and `*V` for a verse,
+- endings `N1`, `N2`, ... at the start of a bar,
+- `x` to repeat the bar before, and `r` for the two bars before,
+- `n` for no chord, and `W` for a chord with only a bass, played as a slash,
+- `Q` on the bar to jump to the coda from, and again where the coda starts,
+  and `S` for the segno, with `<D.C. al Coda>` or `<D.S. al Coda>` comments,
+- alternate chords in parentheses, over the cell before them, which are
+  played instead of the chords of the chart when they are asked for.
+
+Chords are written with iReal's own suffixes, `^7` for a major seventh,
+`-7` for a minor seventh, `h7` for half diminished and `o7` for
+diminished, and are played as the chord komp has that is closest.
+*/
+use crate::pattern::{Meter, COMMON_TIME};
+use crate::song::{bar_changes, Bar, Change, Song, SongSection};
+use crate::tempo::us_per_quarter_from_bpm;
+use komp_core::{Chord, Key};
+
+const MUSIC_PREFIX: &str = "1r34LbKcu7";
+
+type Kind = fn(Key) -> Chord;
+
+// the suffixes of iReal chords, with the chord komp plays for each
+const QUALITIES: &[(&str, Kind)] = &[
+    ("", Chord::Major),
+    ("5", Chord::Five),
+    ("2", Chord::MajorAdd9),
+    ("add9", Chord::MajorAdd9),
+    ("+", Chord::Aug),
+    ("o", Chord::Dim),
+    ("h", Chord::Minor7b5),
+    ("sus", Chord::Sus4),
+    ("^", Chord::MajorMaj7),
+    ("-", Chord::Minor),
+    ("^7", Chord::MajorMaj7),
+    ("-7", Chord::Minor7),
+    ("7", Chord::Major7),
+    ("7sus", Chord::SevenSus4),
+    ("h7", Chord::Minor7b5),
+    ("o7", Chord::Dim7),
+    ("^9", Chord::MajorMaj7_9),
+    ("^13", Chord::MajorMaj7_9),
+    ("6", Chord::Major6),
+    ("69", Chord::Major6_9),
+    ("^7#11", Chord::MajorMaj7Plus11),
+    ("^9#11", Chord::MajorMaj7Plus11),
+    ("^7#5", Chord::MajorMaj7Aug),
+    ("-6", Chord::Minor6),
+    ("-69", Chord::Minor6_9),
+    ("-^7", Chord::MinorMaj7),
+    ("-^9", Chord::MinorMaj7_9),
+    ("-9", Chord::Minor7_9),
+    ("-11", Chord::Minor7_11),
+    ("-7b5", Chord::Minor7b5),
+    ("h9", Chord::Minor7b5),
+    ("-b6", Chord::Minor),
+    ("-#5", Chord::Minor),
+    ("9", Chord::Major7_9),
+    ("7b9", Chord::Major7b9),
+    ("7#9", Chord::Major7Plus9),
+    ("7#11", Chord::Major7Plus11),
+    ("7b5", Chord::Major7b5),
+    ("7#5", Chord::Major7Aug),
+    ("9#11", Chord::Major7Plus11),
+    ("9b5", Chord::Major7b5),
+    ("9#5", Chord::Major7Aug),
+    ("7b13", Chord::Major7b13),
+    ("7#9#5", Chord::Major7Aug),
+    ("7#9b5", Chord::Major7b5),
+    ("7#9#11", Chord::Major7Plus9),
+    ("7b9#11", Chord::Major7b9),
+    ("7b9b5", Chord::Major7b5),
+    ("7b9#5", Chord::Major7Aug),
+    ("7b9#9", Chord::Major7b9),
+    ("7b9b13", Chord::Major7b13),
+    ("7alt", Chord::Major7Plus9),
+    ("13", Chord::Major7_13),
+    ("13#11", Chord::Major7_13),
+    ("13b9", Chord::Major7_13),
+    ("13#9", Chord::Major7_13),
+    ("7b9sus", Chord::SevenSus4),
+    ("7susadd3", Chord::SevenSus4),
+    ("9sus", Chord::SevenSus4),
+    ("13sus", Chord::SevenSus4),
+    ("7b13sus", Chord::SevenSus4),
+    ("11", Chord::SevenSus4),
+];
+
+// the characters that end a chord
+const DELIMITERS: &str = " ,|[]{}()<*TNQSZUYf";
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let hex = text
+            .get(index + 1..index + 3)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[index], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// swaps the first five characters of a block of 50 with the last five, and
+// the characters from 10 to 23 with the ones from 26 to 39, which undoes
+// itself
+fn swap_block(block: &mut [char]) {
+    for index in (0..5).chain(10..24) {
+        block.swap(index, 49 - index);
+    }
+}
+
+fn unscramble(music: &str) -> String {
+    let mut chars: Vec<char> = music.chars().collect();
+    let mut start = 0;
+    // the last 51 characters or less are left as they are
+    while chars.len() - start > 51 {
+        swap_block(&mut chars[start..start + 50]);
+        start += 50;
+    }
+    chars
+        .into_iter()
+        .collect::<String>()
+        .replace("Kcl", "| x")
+        .replace("LZ", " |")
+        .replace("XyQ", "   ")
+}
+
+fn parse_chord(symbol: &str) -> Result<Chord, String> {
+    let wrong = || format!("wrong chord: {}", symbol);
+    let root_length = match symbol.get(1..2) {
+        Some("#") | Some("b") => 2,
+        _ => 1,
+    };
+    let root: Key = symbol
+        .get(..root_length)
+        .ok_or_else(wrong)?
+        .parse()
+        .map_err(|_| wrong())?;
+    // the bass of a slash chord is left out
+    let quality = symbol[root_length..].split('/').next().unwrap_or_default();
+    QUALITIES
+        .iter()
+        .find(|(suffix, _)| *suffix == quality)
+        .map(|(_, kind)| kind(root))
+        .ok_or_else(wrong)
+}
+
+fn parse_meter(digits: &str) -> Result<Meter, String> {
+    let wrong = || format!("wrong time signature: T{}", digits);
+    if digits == "12" {
+        return Ok(Meter::new(12, 8));
+    }
+    let mut values = digits.chars().map(|digit| digit.to_digit(10));
+    match (values.next(), values.next()) {
+        (Some(Some(beats)), Some(Some(unit))) => {
+            format!("{}/{}", beats, unit).parse().map_err(|_| wrong())
+        }
+        _ => Err(wrong()),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Cell {
+    Empty,
+    Chord(Option<Chord>),
+    Hold,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Jump {
+    DaCapo,
+    DalSegno,
+}
+
+#[derive(Default)]
+struct Chart {
+    // the alternate chords are played instead
+    alternates: bool,
+    meter: Option<Meter>,
+    // the name of each section, and its bars
+    sections: Vec<(String, Vec<Bar>)>,
+    cells: Vec<Cell>,
+    // `x` or `r`, the bars repeated by the bar
+    repeat: Option<usize>,
+    bar: Bar,
+    ending: Option<u8>,
+    // the changes of the bars so far, for `x` and `r`
+    played: Vec<Vec<Change>>,
+    // the second bar of an `r`
+    pending: Option<Vec<Change>>,
+    codas: usize,
+    coda: Option<usize>,
+    segno: Option<usize>,
+    jump: Option<Jump>,
+}
+
+impl Chart {
+    fn beats(&self) -> u8 {
+        self.meter.unwrap_or(COMMON_TIME).beats
+    }
+
+    fn last_chord(&self) -> Option<Chord> {
+        self.played
+            .last()
+            .and_then(|changes| changes.last())
+            .and_then(|change| change.chord)
+    }
+
+    // the changes of the cells, each chord from the beat of its cell
+    fn lay_out(&self) -> Vec<Change> {
+        let beats = self.beats() as usize;
+        let count = self.cells.len();
+        let placed = self
+            .cells
+            .iter()
+            .enumerate()
+            .filter_map(|(index, cell)| match cell {
+                Cell::Chord(chord) => Some(((index * beats / count) as u8, *chord)),
+                _ => None,
+            });
+        bar_changes(placed, self.last_chord())
+    }
+
+    fn end_bar(&mut self, barline: char) {
+        let has_chords = self
+            .cells
+            .iter()
+            .any(|cell| matches!(cell, Cell::Chord(_) | Cell::Hold));
+        let changes = match self.repeat {
+            Some(bars) if self.played.len() >= bars => {
+                let changes = self.played[self.played.len() - bars].clone();
+                if bars == 2 {
+                    self.pending = self.played.last().cloned();
+                }
+                Some(changes)
+            }
+            Some(_) => None,
+            None if has_chords => Some(self.lay_out()),
+            // an empty bar between single barlines goes on with the chord
+            // before, or is the second bar of an `r`
+            None if !self.cells.is_empty()
+                && !self.played.is_empty()
+                && (barline == '|' || self.pending.is_some()) =>
+            {
+                Some(self.pending.take().unwrap_or_else(|| {
+                    vec![Change {
+                        beat: 0,
+                        chord: self.last_chord(),
+                    }]
+                }))
+            }
+            None => None,
+        };
+        self.cells.clear();
+        self.repeat = None;
+        if let Some(changes) = changes {
+            if self.sections.is_empty() {
+                self.sections.push(("A".to_string(), vec![]));
+            }
+            let mut bar = std::mem::take(&mut self.bar);
+            bar.changes = changes.clone();
+            bar.ending = self.ending;
+            self.played.push(changes);
+            if let Some((_, bars)) = self.sections.last_mut() {
+                bars.push(bar);
+            }
+        }
+    }
+
+    fn start_section(&mut self, name: String) {
+        match self.sections.last_mut() {
+            // a mark at the start of the chart names its first section
+            Some((last, bars)) if bars.is_empty() => *last = name,
+            _ => self.sections.push((name, vec![])),
+        }
+    }
+
+    fn last_bar(&mut self) -> Option<&mut Bar> {
+        self.sections
+            .last_mut()
+            .and_then(|(_, bars)| bars.last_mut())
+    }
+
+    fn parse(&mut self, music: &str) -> Result<(), String> {
+        let chars: Vec<char> = music.chars().collect();
+        let mut index = 0;
+        while index < chars.len() {
+            let c = chars[index];
+            index += 1;
+            match c {
+                ' ' => self.cells.push(Cell::Empty),
+                'p' => self.cells.push(Cell::Hold),
+                'n' => self.cells.push(Cell::Chord(None)),
+                'x' => self.repeat = Some(1),
+                'r' => self.repeat = Some(2),
+                '|' | '[' | ']' | '{' | '}' | 'Z' => {
+                    self.end_bar(c);
+                    match c {
+                        '{' => self.bar.repeat_start = true,
+                        '}' => {
+                            if let Some(bar) = self.last_bar() {
+                                bar.repeat_end = true;
+                            }
+                            self.ending = None;
+                        }
+                        '|' => (),
+                        _ => self.ending = None,
+                    }
+                }
+                'T' => {
+                    let digits: String = chars[index..].iter().take(2).collect();
+                    index += digits.chars().count();
+                    let meter = parse_meter(&digits)?;
+                    // the bars so far are in 4/4 unless they tell otherwise
+                    let current = self
+                        .meter
+                        .or_else(|| Some(COMMON_TIME).filter(|_| !self.played.is_empty()));
+                    match current {
+                        Some(current) if current != meter => {
+                            return Err(format!(
+                                "the meter changes from {} to {}, a song has one meter",
+                                current, meter
+                            ))
+                        }
+                        _ => self.meter = Some(meter),
+                    }
+                }
+                '*' => {
+                    let mark = chars.get(index).copied().unwrap_or(' ');
+                    index += 1;
+                    self.end_bar(c);
+                    self.ending = None;
+                    self.start_section(match mark {
+                        'i' => "Intro".to_string(),
+                        'V' => "Verse".to_string(),
+                        mark => mark.to_uppercase().to_string(),
+                    });
+                }
+                'N' => {
+                    let number = chars.get(index).and_then(|digit| digit.to_digit(10));
+                    index += 1;
+                    // N0 is an ending without a number
+                    self.ending = number.filter(|number| *number > 0).map(|n| n as u8);
+                }
+                'Q' => {
+                    self.codas += 1;
+                    if self.codas == 1 {
+                        self.bar.to_coda = true;
+                    } else if self.coda.is_none() {
+                        self.end_bar(c);
+                        self.ending = None;
+                        self.start_section("Coda".to_string());
+                        self.coda = Some(self.sections.len() - 1);
+                    }
+                }
+                'S' => self.segno = Some(self.sections.len().saturating_sub(1)),
+                '<' => {
+                    let comment: String =
+                        chars[index..].iter().take_while(|c| **c != '>').collect();
+                    index += comment.chars().count() + 1;
+                    let comment = comment.to_uppercase().replace(' ', "");
+                    if comment.contains("D.C.") {
+                        self.jump = Some(Jump::DaCapo);
+                    } else if comment.contains("D.S.") {
+                        self.jump = Some(Jump::DalSegno);
+                    }
+                }
+                '(' => {
+                    let symbol: String = chars[index..].iter().take_while(|c| **c != ')').collect();
+                    index += symbol.chars().count() + 1;
+                    // an alternate chord, over the cell before it
+                    if self.alternates {
+                        let chord = match symbol.trim() {
+                            "n" => None,
+                            symbol => Some(parse_chord(symbol)?),
+                        };
+                        match self.cells.last_mut() {
+                            Some(cell) => *cell = Cell::Chord(chord),
+                            None => self.cells.push(Cell::Chord(chord)),
+                        }
+                    }
+                }
+                'A'..='G' | 'W' => {
+                    let start = index - 1;
+                    while index < chars.len() && !DELIMITERS.contains(chars[index]) {
+                        index += 1;
+                    }
+                    let symbol: String = chars[start..index].iter().collect();
+                    if c == 'W' {
+                        self.cells.push(Cell::Hold);
+                    } else {
+                        self.cells.push(Cell::Chord(Some(parse_chord(&symbol)?)));
+                    }
+                }
+                // commas, spacers, the sizes of the chords, fermatas
+                _ => (),
+            }
+        }
+        self.end_bar('Z');
+        Ok(())
+    }
+
+    fn into_song(self, title: String, us_per_quarter: Option<u32>) -> Result<Song, String> {
+        let mut sections: Vec<SongSection> = vec![];
+        // the mark of each section in the chart
+        let mut marks: Vec<String> = vec![];
+        // the section played for each section of the chart
+        let mut played = vec![];
+        for (index, (mark, bars)) in self.sections.into_iter().enumerate() {
+            if bars.is_empty() {
+                played.push(None);
+                continue;
+            }
+            // a section written again the same is played again
+            if let Some(same) = sections
+                .iter()
+                .zip(marks.iter())
+                .position(|(section, same)| section.bars == bars && *same == mark)
+            {
+                played.push(Some((index, same)));
+                continue;
+            }
+            let count = marks.iter().filter(|same| **same == mark).count();
+            let name = if count == 0 {
+                mark.clone()
+            } else {
+                format!("{}{}", mark, count + 1)
+            };
+            sections.push(SongSection { name, bars });
+            marks.push(mark);
+            played.push(Some((index, sections.len() - 1)));
+        }
+        let coda = self
+            .coda
+            .and_then(|coda| played.get(coda).copied().flatten());
+        let form_of = |from: usize| -> Vec<String> {
+            played
+                .iter()
+                .flatten()
+                .filter(|(index, section)| *index >= from && Some((*index, *section)) != coda)
+                .map(|(_, section)| sections[*section].name.clone())
+                .collect()
+        };
+        let mut form = form_of(0);
+        match self.jump {
+            Some(Jump::DaCapo) => form.extend(form_of(0)),
+            Some(Jump::DalSegno) => form.extend(form_of(self.segno.unwrap_or(0))),
+            None => (),
+        }
+        if let Some((_, coda)) = coda {
+            let jumps = form.iter().any(|name| {
+                sections.iter().any(|section| {
+                    section.name == *name && section.bars.iter().any(|bar| bar.to_coda)
+                })
+            });
+            if !jumps {
+                form.push(sections[coda].name.clone());
+            }
+        }
+        if form.is_empty() {
+            return Err(format!("no bars in {}", title));
+        }
+        Ok(Song {
+            title,
+            us_per_quarter,
+            meter: self.meter,
+            sections,
+            form,
+        })
+    }
+}
+
+fn parse_song(fields: &[&str], obfuscated: bool, alternates: bool) -> Result<Option<Song>, String> {
+    let (music, tempo) = if obfuscated {
+        match fields
+            .iter()
+            .position(|field| field.starts_with(MUSIC_PREFIX))
+        {
+            Some(index) => (
+                unscramble(&fields[index][MUSIC_PREFIX.len()..]),
+                fields.get(index + 2),
+            ),
+            // the name of a playlist
+            None => return Ok(None),
+        }
+    } else {
+        let fields: Vec<&&str> = fields.iter().filter(|field| !field.is_empty()).collect();
+        match fields.get(4) {
+            Some(music) => (music.to_string(), None),
+            None => return Ok(None),
+        }
+    };
+    let title = fields.first().map_or("", |title| title.trim()).to_string();
+    let us_per_quarter = tempo
+        .and_then(|tempo| tempo.trim().parse::<f64>().ok())
+        .filter(|bpm| *bpm > 0.0)
+        .map(us_per_quarter_from_bpm);
+    let mut chart = Chart {
+        alternates,
+        ..Chart::default()
+    };
+    chart
+        .parse(&music)
+        .map_err(|e| format!("{}: {}", title, e))?;
+    chart.into_song(title, us_per_quarter).map(Some)
+}
+
+/// The songs of an `irealb://` or `irealbook://` link, with the alternate
+/// chords played instead if `alternates`.
+pub fn parse_ireal(link: &str, alternates: bool) -> Result<Vec<Song>, String> {
+    let link = link.trim();
+    let (text, obfuscated) = if let Some(text) = link.strip_prefix("irealb://") {
+        (text, true)
+    } else if let Some(text) = link.strip_prefix("irealbook://") {
+        (text, false)
+    } else {
+        return Err("not an iReal Pro link".to_string());
+    };
+    let text = percent_decode(text);
+    let mut songs = vec![];
+    for song in text.split("===") {
+        let fields: Vec<&str> = song.split('=').collect();
+        if let Some(song) = parse_song(&fields, obfuscated, alternates)? {
+            songs.push(song);
+        }
+    }
+    if songs.is_empty() {
+        return Err("no songs in the link".to_string());
+    }
+    Ok(songs)
+}
+
+/// The songs of the first iReal Pro link in a text, such as an exported
+/// HTML page.
+pub fn find_ireal(text: &str, alternates: bool) -> Result<Vec<Song>, String> {
+    let start = text
+        .find("irealb://")
+        .or_else(|| text.find("irealbook://"))
+        .ok_or_else(|| "no iReal Pro link".to_string())?;
+    let link = &text[start..];
+    let end = link
+        .find(|c: char| c == '"' || c == '\'' || c == '<' || c.is_whitespace())
+        .unwrap_or(link.len());
+    parse_ireal(&link[..end], alternates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use komp_core::*;
+
+    fn change(beat: u8, chord: Chord) -> Change {
+        Change {
+            beat,
+            chord: Some(chord),
+        }
+    }
+
+    fn chart(music: &str) -> Song {
+        let mut chart = Chart::default();
+        chart.parse(music).unwrap();
+        chart.into_song("Test".to_string(), None).unwrap()
+    }
+
+    // the chord on the first beat of each bar
+    fn roots(song: &Song) -> Vec<String> {
+        song.progression()
+            .iter()
+            .map(|changes| match changes[0].chord {
+                Some(chord) => chord.to_string(),
+                None => "N.C.".to_string(),
+            })
+            .collect()
+    }
+
+    fn scramble(music: &str) -> String {
+        let mut chars: Vec<char> = music.chars().collect();
+        let mut start = 0;
+        while chars.len() - start > 51 {
+            swap_block(&mut chars[start..start + 50]);
+            start += 50;
+        }
+        chars.into_iter().collect()
+    }
+
+    #[test]
+    fn test_parse_chord() {
+        assert_eq!(parse_chord("C^7"), Ok(Chord::MajorMaj7(C_KEY)));
+        assert_eq!(parse_chord("Bb-7"), Ok(Chord::Minor7(ASHARP_KEY)));
+        assert_eq!(parse_chord("F#h7"), Ok(Chord::Minor7b5(FSHARP_KEY)));
+        assert_eq!(parse_chord("Eo7"), Ok(Chord::Dim7(E_KEY)));
+        assert_eq!(parse_chord("G7alt"), Ok(Chord::Major7Plus9(G_KEY)));
+        assert_eq!(parse_chord("F/A"), Ok(Chord::Major(F_KEY)));
+        assert!(parse_chord("C7xyz").is_err());
+        assert!(parse_chord("H7").is_err());
+    }
+
+    #[test]
+    fn test_cells() {
+        let song = chart("[T34C^7  |D-7 G7 |E-7,A7,D-7|  F^7 |p  nZ");
+        assert_eq!(song.meter, Some(Meter::new(3, 4)));
+        let bars: Vec<Vec<Change>> = song.sections[0]
+            .bars
+            .iter()
+            .map(|bar| bar.changes.clone())
+            .collect();
+        assert_eq!(
+            bars,
+            vec![
+                vec![change(0, Chord::MajorMaj7(C_KEY))],
+                // four cells over three beats
+                vec![
+                    change(0, Chord::Minor7(D_KEY)),
+                    change(1, Chord::Major7(G_KEY))
+                ],
+                vec![
+                    change(0, Chord::Minor7(E_KEY)),
+                    change(1, Chord::Major7(A_KEY)),
+                    change(2, Chord::Minor7(D_KEY))
+                ],
+                // the chord before goes on
+                vec![
+                    change(0, Chord::Minor7(D_KEY)),
+                    change(1, Chord::MajorMaj7(F_KEY))
+                ],
+                vec![
+                    change(0, Chord::MajorMaj7(F_KEY)),
+                    Change {
+                        beat: 2,
+                        chord: None
+                    }
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sections_and_repeats() {
+        let song = chart(
+            "{*AT44C^7   | x  |N1D-7   |G7   }|N2G7   |C^7   ][*BF^7   |G7   |r|   ]{*AC^7   | x  |N1D-7   |G7   }|N2G7   |C^7   Z",
+        );
+        assert_eq!(song.form, vec!["A", "B", "A"]);
+        assert_eq!(song.sections.len(), 2);
+        // and the song can be written as a chart in TOML
+        assert_eq!(Song::parse(&song.to_toml().unwrap()).as_ref(), Ok(&song));
+        assert_eq!(
+            roots(&song)[..14],
+            [
+                "Cmaj7", "Cmaj7", "Dm7", "G7", "Cmaj7", "Cmaj7", "G7", "Cmaj7", "Fmaj7", "G7",
+                "Fmaj7", "G7", "Cmaj7", "Cmaj7"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_coda() {
+        let song = chart("[*AC^7   |D-7 G7 Q|E-7   <D.C. al Coda>   Z  Y[QF^7   |F^7   Z");
+        assert_eq!(song.form, vec!["A", "A"]);
+        assert_eq!(song.sections[1].name, "Coda");
+        assert_eq!(
+            roots(&song),
+            ["Cmaj7", "Dm7", "Em7", "Cmaj7", "Dm7", "Fmaj7", "Fmaj7"]
+        );
+        // a section marked C is not the coda
+        let song = chart("[*AD-7 Q  |G7   Z  Y[QC^7   Z[*CC^7   Z");
+        let names: Vec<&str> = song
+            .sections
+            .iter()
+            .map(|section| section.name.as_str())
+            .collect();
+        assert_eq!(names, ["A", "Coda", "C"]);
+    }
+
+    #[test]
+    fn test_parse_ireal() {
+        let music = "*AT44C^7XyQ|A-7XyQ|D-7 G7LZC^7 (E7) Kcl  |F^7XyQ|E-7XyQ|A7XyQZ";
+        let link = format!(
+            "irealb://Test%20Song=Composer==Medium%20Swing=C=n={}{}=Jazz-Medium%20Swing=160=0===Playlist",
+            MUSIC_PREFIX,
+            scramble(music)
+        );
+        let songs = parse_ireal(&link, false).unwrap();
+        assert_eq!(songs.len(), 1);
+        let song = &songs[0];
+        assert_eq!(song.title, "Test Song");
+        assert_eq!(song.us_per_quarter, Some(us_per_quarter_from_bpm(160.0)));
+        assert_eq!(
+            roots(song),
+            ["Cmaj7", "Am7", "Dm7", "Cmaj7", "Cmaj7", "Fmaj7", "Em7", "A7"]
+        );
+        assert!(parse_ireal("http://example.com", false).is_err());
+        // the alternate chord over the second of the three cells of the
+        // fourth bar
+        let songs = parse_ireal(&link, true).unwrap();
+        assert_eq!(
+            songs[0].sections[0].bars[3].changes,
+            vec![
+                change(0, Chord::MajorMaj7(C_KEY)),
+                change(1, Chord::Major7(E_KEY))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_meter() {
+        assert_eq!(parse_meter("68"), Ok(Meter::new(6, 8)));
+        assert_eq!(parse_meter("12"), Ok(Meter::new(12, 8)));
+        assert!(parse_meter("43").is_err());
+        assert!(parse_meter("40").is_err());
+        assert!(parse_meter("4").is_err());
+        assert!(Chart::default().parse("[T44C^7   |T34D-7  |G7  Z").is_err());
+        assert!(Chart::default().parse("[C^7   |T34D-7  |G7  Z").is_err());
+        let song = chart("[*AT44C^7   |D-7   ][*BT44G7   Z");
+        assert_eq!(song.meter, Some(COMMON_TIME));
+    }
+
+    #[test]
+    fn test_old_format() {
+        let songs = find_ireal(
+            "<a href=\"irealbook://Old=Composer=Style=F=[T44F7%20%20%20|Bb7%20%20%20|F7%20%20%20Z\">Old</a>",
+            false,
+        )
+        .unwrap();
+        assert_eq!(roots(&songs[0]), ["F7", "Bb7", "F7"]);
+    }
+
+    #[test]
+    fn test_unscramble() {
+        let music: String = (0..120).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+        assert_ne!(scramble(&music), music);
+        assert_eq!(unscramble(&scramble(&music)), music);
+        assert_eq!(unscramble("C7KclLZXyQ"), "C7| x |   ");
+    }
+}
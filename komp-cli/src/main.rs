@@ -1,8 +1,15 @@
 use komp_core::*;
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
+// in place of coremidi, so that komp builds and its tests run elsewhere
+#[cfg(not(target_os = "macos"))]
+extern crate coremidi_stub as coremidi;
+
+#[cfg(target_os = "macos")]
 mod external {
     #[link(name = "CoreAudio", kind = "framework")]
     extern "C" {
@@ -10,27 +17,145 @@ mod external {
         pub fn AudioGetCurrentHostTime() -> u64;
     }
 }
+#[cfg(target_os = "macos")]
 use crate::external::{AudioConvertHostTimeToNanos, AudioGetCurrentHostTime};
+mod arpeggio;
+mod bass;
+mod chordpro;
+mod clock;
 mod detect;
+mod drums;
+mod dynamics;
+mod groove;
+mod humanize;
+mod import;
+mod ireal;
+mod musicxml;
 mod pattern;
 mod play;
+mod progression;
+mod random;
+mod rhythm;
+#[cfg(test)]
+mod scenario;
 mod setup;
+mod smf;
+mod song;
+mod strum;
+mod style;
+mod tempo;
+mod xf;
 
+use crate::bass::{Bass, BassNote};
+use crate::clock::ClockFollower;
+use crate::drums::DRUM_CHANNEL;
+use crate::dynamics::Dynamics;
+use crate::pattern::Comping;
 use crate::play::*;
+use crate::progression::Progression;
 use crate::setup::*;
+use crate::smf::Smf;
+use crate::tempo::{bpm, Tap, TapTempo, TempoChange};
 use std::env;
+use std::path::Path;
+
+fn import_pattern(command: ImportCommand) {
+    let style = Smf::read(Path::new(&command.clip))
+        .and_then(|smf| import::import_pattern(&smf, &command.options))
+        .and_then(|style| style.to_toml());
+    match (style, &command.output) {
+        (Ok(text), Some(output)) => {
+            if let Err(e) = std::fs::write(output, text) {
+                println!("Cannot write {}: {}", output, e);
+                std::process::exit(-1);
+            }
+            println!("Imported {} to {}", command.clip, output);
+        }
+        (Ok(text), None) => print!("{}", text),
+        (Err(e), _) => {
+            println!("Cannot import {}: {}", command.clip, e);
+            std::process::exit(-1);
+        }
+    }
+}
+
+fn render_dsl(command: RenderCommand) {
+    match Smf::read(Path::new(&command.file)) {
+        Ok(smf) => {
+            let ticks_per_unit = 4 * smf.ticks_per_quarter as u32 / command.unit;
+            // the drums are instruments rather than notes
+            let timed_events: Vec<TimedEvent> = smf
+                .timed_events()
+                .into_iter()
+                .filter(|te| match command.channel {
+                    Some(channel) => te.event.channel() == channel,
+                    None => te.event.channel() != DRUM_CHANNEL,
+                })
+                .collect();
+            println!("{}", detect::render_dsl(&timed_events, ticks_per_unit));
+        }
+        Err(e) => {
+            println!("Cannot read {}: {}", command.file, e);
+            std::process::exit(-1);
+        }
+    }
+}
+
+fn import_ireal(command: IrealCommand) {
+    let text = std::fs::read_to_string(&command.file).map_err(|e| e.to_string());
+    let songs = text.and_then(|text| ireal::find_ireal(&text, command.alternates));
+    let song = songs.and_then(|songs| match &command.title {
+        Some(title) => songs
+            .into_iter()
+            .find(|song| song.title.eq_ignore_ascii_case(title))
+            .ok_or(format!("no song {}", title)),
+        None => Ok(songs.into_iter().next().unwrap()),
+    });
+    match (song.and_then(|song| song.to_toml()), &command.output) {
+        (Ok(text), Some(output)) => {
+            if let Err(e) = std::fs::write(output, text) {
+                println!("Cannot write {}: {}", output, e);
+                std::process::exit(-1);
+            }
+            println!("Imported {} to {}", command.file, output);
+        }
+        (Ok(text), None) => print!("{}", text),
+        (Err(e), _) => {
+            println!("Cannot import {}: {}", command.file, e);
+            std::process::exit(-1);
+        }
+    }
+}
 
 fn main() {
+    let mut args_iter = env::args();
+    let tool_name = tool_name(&mut args_iter);
+
+    // before anything is printed, the style may be written to stdout
+    if env::args().nth(1).as_deref() == Some("import-pattern") {
+        args_iter.next();
+        import_pattern(get_import_command(&mut args_iter, &tool_name));
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("import-ireal") {
+        args_iter.next();
+        import_ireal(get_ireal_command(&mut args_iter, &tool_name));
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("render-dsl") {
+        args_iter.next();
+        render_dsl(get_render_command(&mut args_iter, &tool_name));
+        return;
+    }
+
     println!("komp");
 
     let current_chord: Arc<Mutex<Option<Chord>>> = Arc::new(Mutex::new(None));
     let read_current_chord = Arc::clone(&current_chord);
 
-    let mut args_iter = env::args();
-    let tool_name = tool_name(&mut args_iter);
-
     let source_index = get_source_index(&mut args_iter, &tool_name);
     let destination_index = get_destination_index(&mut args_iter, &tool_name);
+    let options = get_options(&mut args_iter, &tool_name);
 
     let source = coremidi::Source::from_index(source_index)
         .expect(&format!("cannot get coremidi source[{}]", source_index));
@@ -53,16 +178,73 @@ fn main() {
 
     let client = coremidi::Client::new("komp-client").expect("cannot create coremidi client");
 
+    let follower = Arc::new(Mutex::new(ClockFollower::new()));
+    let read_follower = Arc::clone(&follower);
+
+    let tempo_change: Arc<Mutex<Option<TempoChange>>> = Arc::new(Mutex::new(None));
+    let tapped_tempo_change = Arc::clone(&tempo_change);
+    let read_tempo_change = Arc::clone(&tempo_change);
+    let tap = options.tap;
+    let mut tap_tempo = TapTempo::new();
+    let mut pedal = 0;
+
+    let dynamics = Arc::new(Mutex::new(Dynamics::new()));
+    let player_dynamics = Arc::clone(&dynamics);
+
+    // the notes being played, in the order they were pressed
+    let played_order: Arc<Mutex<Vec<u8>>> = Arc::new(Mutex::new(vec![]));
+    let player_played_order = Arc::clone(&played_order);
+
+    // the chords heard, with the time they were played, to write them down
+    let recording =
+        options.musicxml.is_some() || options.chordpro.is_some() || options.xf.is_some();
+    let heard_chords: Arc<Mutex<Vec<(u64, Chord)>>> = Arc::new(Mutex::new(vec![]));
+    let read_heard_chords = Arc::clone(&heard_chords);
+    let mut last_heard = None;
+
     let mut playing: Playing = HashSet::new();
     let receive_midi = move |packet_list: &coremidi::PacketList| {
         let mut was_playing = playing.clone();
+        let mut heard_at = now();
 
         for packet in packet_list.iter() {
-            let data = packet.data();
-            process_midi(data, &mut playing);
+            let time = host_time_to_nanos(packet.timestamp());
+            heard_at = time;
+            for data in midi_messages(packet.data()) {
+                follower.lock().unwrap().receive(time, data);
+                if let Some(tap) = tap {
+                    if is_tap(data, tap, &mut pedal) {
+                        if let Some(us_per_quarter) = tap_tempo.tap(time) {
+                            *tapped_tempo_change.lock().unwrap() =
+                                Some(TempoChange::Set(us_per_quarter));
+                        }
+                    }
+                    // the tap key is not part of any chord
+                    if is_tap_note(data, tap) {
+                        continue;
+                    }
+                }
+                if let Some(velocity) = process_midi(data, &mut playing) {
+                    player_dynamics.lock().unwrap().play(velocity);
+                    player_played_order.lock().unwrap().push(data[1]);
+                }
+            }
         }
+        player_played_order
+            .lock()
+            .unwrap()
+            .retain(|note| playing.iter().any(|(_, playing_note)| playing_note == note));
 
         detect_chord(&mut was_playing, &mut playing, &current_chord);
+        if recording {
+            let chord = *current_chord.lock().unwrap();
+            if chord != last_heard {
+                last_heard = chord;
+                if let Some(chord) = chord {
+                    heard_chords.lock().unwrap().push((heard_at, chord));
+                }
+            }
+        }
     };
 
     let input_port = client
@@ -72,15 +254,145 @@ fn main() {
         .connect_source(&source)
         .expect("cannot connect input port to source");
     let output_port = client.output_port("komp-port").unwrap();
+    if let Some(section) = &options.section {
+        println!("style section {}", section.name);
+        output_port
+            .send(&destination, &play::program_changes(&section.programs()))
+            .expect("cannot send MIDI packet");
+    }
 
-    let _handle = thread::spawn(move || {
+    let running = Arc::new(AtomicBool::new(true));
+    let keep_running = Arc::clone(&running);
+    let jump_to_bar: Arc<Mutex<Option<u32>>> = Arc::new(Mutex::new(None));
+    let read_jump_to_bar = Arc::clone(&jump_to_bar);
+
+    let follow_clock = options.follow_clock;
+    let send_clock = options.send_clock;
+    let us_per_quarter = options.us_per_quarter;
+    let meter = options.meter;
+    let groove = options.groove;
+    let humanizer = options.humanizer;
+    let register = options.register;
+    let voicing_style = options.voicing;
+    let bass_style = options.bass;
+    let bass_rhythm = options.bass_rhythm;
+    let drums = options.drums;
+    let section = RefCell::new(options.section);
+    let mut style_watcher = options.style_watcher;
+    if let Some(song) = &options.song {
+        println!("song {}", song.title);
+    }
+    let progression = options.song.map(|song| song.progression());
+    let musicxml = options.musicxml;
+    let chordpro = options.chordpro;
+    let xf = options.xf;
+    let comping = options.comping;
+    let read_played_order = Arc::clone(&played_order);
+    let seed = options.seed;
+    let follow_dynamics = options.follow_dynamics;
+    let busier_above = options.busier_above;
+    let read_dynamics = Arc::clone(&dynamics);
+    let handle = thread::spawn(move || {
         let ticks_per_quarter = 96;
-        let us_per_quarter = 500_000;
         let mut last_key = None;
         let mut timestamp = now();
-        let timed_events = pattern::create_bar(ticks_per_quarter, Chord::MajorMaj7(C_KEY));
+        // the rhythms that play some of the time play differently in each
+        // new pattern
+        let patterns = Cell::new(0);
+        // a section of a style may be a loop of several bars
+        let bars = || section.borrow().as_ref().map_or(1, |section| section.bars);
+        // a song is played whole, whatever the length of the section
+        let pattern_bars = || match &progression {
+            Some(progression) => progression.len() as u32,
+            None => bars(),
+        };
+        let bar_ticks = meter.ticks_per_bar(ticks_per_quarter);
+        let create_pattern = |busier,
+                              chord: Chord,
+                              notes: &[u8],
+                              bass_line: &[BassNote],
+                              played: &[u8],
+                              us_per_quarter: u32| {
+            let bars = bars();
+            let first_bar = patterns.get() * bars as u64;
+            patterns.set(patterns.get() + 1);
+            let mut timed_events = match &*section.borrow() {
+                Some(section) => {
+                    section.create_pattern(ticks_per_quarter, meter, &chord, first_bar)
+                }
+                None => match &comping {
+                    Comping::Chords if busier => {
+                        pattern::create_busier_bar(ticks_per_quarter, meter, notes)
+                    }
+                    Comping::Chords => pattern::create_voiced_bar(ticks_per_quarter, meter, notes),
+                    Comping::Arpeggio(arpeggiator) => {
+                        arpeggiator.create_bar(ticks_per_quarter, meter, notes, played)
+                    }
+                    Comping::Strum(strummer) => {
+                        strummer.create_bar(ticks_per_quarter, meter, notes, us_per_quarter)
+                    }
+                    Comping::Rhythm(rhythm) => {
+                        rhythm.create_bar(ticks_per_quarter, meter, first_bar, 0, notes)
+                    }
+                },
+            };
+            for bar in 0..bars {
+                let mut bar_events = match &bass_rhythm {
+                    Some(rhythm) => bass::create_rhythmic_bass_bar(
+                        ticks_per_quarter,
+                        meter,
+                        first_bar + bar as u64,
+                        bass_line,
+                        rhythm,
+                    ),
+                    None => bass::create_bass_bar(ticks_per_quarter, meter, 0, bass_line),
+                };
+                if let Some(drums) = &drums {
+                    bar_events.append(&mut drums.create_bar(
+                        ticks_per_quarter,
+                        meter,
+                        first_bar + bar as u64,
+                    ));
+                }
+                timed_events.extend(bar_events.into_iter().map(|te| TimedEvent {
+                    timing: te.timing + bar * bar_ticks,
+                    event: te.event,
+                }));
+            }
+            timed_events.sort();
+            timed_events
+        };
+        let (low, high) = register;
+        let voicer = RefCell::new(Voicer::new(low, high).with_style(voicing_style));
+        let bass = RefCell::new(bass_style.map(|style| Bass::new(style).with_seed(seed)));
+        // the bass leads into the next chord, which is not known while
+        // playing live, when it leads back to the root of the chord
+        let play_chord = |chord: Chord, next: Chord| {
+            let bass_line = match &mut *bass.borrow_mut() {
+                Some(bass) => bass.bar(&chord, &next, meter),
+                None => vec![],
+            };
+            (voicer.borrow_mut().voice(&chord), bass_line)
+        };
+        // each chord of a song as it would be played live
+        let create_song_pattern = |busier, us_per_quarter| {
+            progression.as_ref().map(|progression| {
+                song::create_song_pattern(ticks_per_quarter, meter, progression, |chord, next| {
+                    let (voicing, bass_line) = play_chord(chord, next);
+                    create_pattern(busier, chord, &voicing, &bass_line, &[], us_per_quarter)
+                })
+            })
+        };
+        let mut chord = Chord::MajorMaj7(C_KEY);
+        let (mut voicing, mut bass_line) = play_chord(chord, chord);
+        let mut busier = false;
+        let timed_events = create_song_pattern(busier, us_per_quarter).unwrap_or_else(|| {
+            create_pattern(busier, chord, &voicing, &bass_line, &[], us_per_quarter)
+        });
         let slice_length = 200 * NS_PER_MS;
-        let pattern_length = 4 * us_per_quarter as u64 * NS_PER_US;
+        let pattern_length =
+            (pattern_bars() * bar_ticks) as u64 * us_per_quarter as u64 * NS_PER_US
+                / ticks_per_quarter as u64;
         let scheduling_deadline_margin = 50 * NS_PER_MS;
 
         let mut scheduler = play::Scheduler::new(
@@ -91,30 +403,208 @@ fn main() {
             pattern_length,
             us_per_quarter,
             ticks_per_quarter,
-        );
+        )
+        .with_clock(send_clock)
+        .with_meter(meter)
+        .with_groove(groove)
+        .with_humanizer(humanizer);
+        let mut heard = Progression::new(ticks_per_quarter, meter, us_per_quarter);
 
         let mut slice_start = 0;
+        let mut next_style_poll = timestamp;
+        let mut rested_at = timestamp;
+        let mut strummed_at = us_per_quarter;
         let mut playing: Playing = hashset![];
-        loop {
+        while keep_running.load(Ordering::SeqCst) {
             timestamp = now();
+            if let Some(bar) = read_jump_to_bar.lock().unwrap().take() {
+                let position = pattern::TimeCode::with_meter(ticks_per_quarter, meter, bar, 0, 0);
+                match scheduler.jump_to(timestamp, &mut slice_start, position) {
+                    Ok(packet_buf) => {
+                        silence(&output_port, &destination, &mut playing);
+                        output_port
+                            .send(&destination, &packet_buf)
+                            .expect("cannot send MIDI packet");
+                        println!("jumped to bar {}", bar + 1);
+                    }
+                    Err(e) => println!("cannot jump to bar {}: {}", bar + 1, e),
+                }
+            }
+            for (time, chord) in read_heard_chords.lock().unwrap().drain(..) {
+                heard.record(scheduler.position(time), chord);
+            }
+            if let Some(tempo_change) = read_tempo_change.lock().unwrap().take() {
+                if follow_clock {
+                    println!("the tempo follows the external clock");
+                } else {
+                    match tempo_change {
+                        TempoChange::Set(us_per_quarter) => {
+                            println!(
+                                "tempo {:.1} BPM, was {:.1} BPM",
+                                bpm(us_per_quarter),
+                                bpm(scheduler.us_per_quarter())
+                            );
+                            scheduler.set_tempo(us_per_quarter);
+                        }
+                        TempoChange::Ramp(us_per_quarter, bars) => {
+                            println!(
+                                "tempo from {:.1} to {:.1} BPM in {} bars",
+                                bpm(scheduler.us_per_quarter()),
+                                bpm(us_per_quarter),
+                                bars
+                            );
+                            scheduler.ramp_tempo(us_per_quarter, bars);
+                        }
+                    }
+                }
+            }
+            if follow_clock {
+                let follower = *read_follower.lock().unwrap();
+                match (follower.beat(), follower.us_per_quarter()) {
+                    (Some((beat, beat_time)), Some(us_per_quarter)) => {
+                        if scheduler.follow(
+                            timestamp,
+                            &mut slice_start,
+                            beat,
+                            beat_time,
+                            us_per_quarter,
+                        ) {
+                            silence(&output_port, &destination, &mut playing);
+                        }
+                    }
+                    _ => {
+                        // wait for the master to start
+                        if !playing.is_empty() {
+                            silence(&output_port, &destination, &mut playing);
+                        }
+                        thread::sleep(Duration::from_nanos(slice_length));
+                        continue;
+                    }
+                }
+            }
+            {
+                let mut dynamics = read_dynamics.lock().unwrap();
+                dynamics.rest(timestamp - rested_at);
+                rested_at = timestamp;
+                if follow_dynamics {
+                    scheduler.set_velocity_scale(dynamics.velocity_scale());
+                }
+                if let Some(threshold) = busier_above {
+                    if dynamics.digs_in(threshold as f64) != busier {
+                        busier = !busier;
+                        let us_per_quarter = scheduler.us_per_quarter();
+                        scheduler.queue_pattern(
+                            create_song_pattern(busier, us_per_quarter).unwrap_or_else(|| {
+                                create_pattern(
+                                    busier,
+                                    chord,
+                                    &voicing,
+                                    &bass_line,
+                                    &read_played_order.lock().unwrap(),
+                                    us_per_quarter,
+                                )
+                            }),
+                            pattern_bars() * bar_ticks,
+                        );
+                        println!(
+                            "{} from the next bar (intensity {:.0})",
+                            if busier { "busier" } else { "calmer" },
+                            dynamics.intensity().unwrap_or(0.0)
+                        );
+                    }
+                }
+            }
+            // the strings of a strum are staggered in ticks, for the tempo
+            let is_strum = matches!(comping, Comping::Strum(_)) && section.borrow().is_none();
+            if is_strum && scheduler.us_per_quarter() != strummed_at {
+                strummed_at = scheduler.us_per_quarter();
+                scheduler.queue_pattern(
+                    create_song_pattern(busier, strummed_at).unwrap_or_else(|| {
+                        create_pattern(
+                            busier,
+                            chord,
+                            &voicing,
+                            &bass_line,
+                            &read_played_order.lock().unwrap(),
+                            strummed_at,
+                        )
+                    }),
+                    pattern_bars() * bar_ticks,
+                );
+            }
+            if let Some(watcher) = &mut style_watcher {
+                if timestamp >= next_style_poll {
+                    next_style_poll = timestamp + STYLE_POLL_INTERVAL;
+                    match watcher.poll() {
+                        Some(Ok(reload)) => {
+                            let reloaded = reload.section;
+                            println!("style section {} reloaded", reloaded.name);
+                            if let Some(us_per_quarter) = reload.us_per_quarter {
+                                if follow_clock {
+                                    println!("the tempo follows the external clock");
+                                } else {
+                                    println!("tempo {:.1} BPM", bpm(us_per_quarter));
+                                    scheduler.set_tempo(us_per_quarter);
+                                }
+                            }
+                            if let Some(meter) = reload.meter {
+                                println!("the meter changes to {} once komp is restarted", meter);
+                            }
+                            output_port
+                                .send(&destination, &play::program_changes(&reloaded.programs()))
+                                .expect("cannot send MIDI packet");
+                            *section.borrow_mut() = Some(reloaded);
+                            let us_per_quarter = scheduler.us_per_quarter();
+                            scheduler.queue_pattern(
+                                create_song_pattern(busier, us_per_quarter).unwrap_or_else(|| {
+                                    create_pattern(
+                                        busier,
+                                        chord,
+                                        &voicing,
+                                        &bass_line,
+                                        &read_played_order.lock().unwrap(),
+                                        us_per_quarter,
+                                    )
+                                }),
+                                pattern_bars() * bar_ticks,
+                            );
+                        }
+                        // keep playing what was there
+                        Some(Err(e)) => println!("cannot reload the style: {}", e),
+                        None => (),
+                    }
+                }
+            }
             let current_key = *read_current_chord.lock().unwrap();
-            if last_key != current_key {
-                // flush (drop) the rest of the scheduled slice, if any
-                coremidi::flush().expect("cannot flush scheduled MIDI packets");
-                let packet_buf = play::mute_playing(&playing);
-                output_port
-                    .send(&destination, &packet_buf)
-                    .expect("cannot send MIDI packet");
+            // a song plays its own chords, whatever is played along
+            if last_key != current_key && progression.is_none() {
+                silence(&output_port, &destination, &mut playing);
                 println!("T: {:?}", current_key);
                 last_key = current_key;
+                chord = current_key.unwrap_or(Chord::MajorMaj7(C_KEY));
+                let (chord_voicing, chord_bass_line) = play_chord(chord, chord);
+                voicing = chord_voicing;
+                bass_line = chord_bass_line;
+                let timed_events = create_pattern(
+                    busier,
+                    chord,
+                    &voicing,
+                    &bass_line,
+                    &read_played_order.lock().unwrap(),
+                    scheduler.us_per_quarter(),
+                );
+                // a reloaded section of another length waits for its turn
+                if scheduler.pattern_ticks() == bars() * bar_ticks {
+                    scheduler.set_pattern(timed_events);
+                } else {
+                    scheduler.queue_pattern(timed_events, bars() * bar_ticks);
+                }
             } else {
                 print!(".")
             }
-            let (sleep_time, packet_buf) = scheduler.schedule_slice(
-                timestamp,
-                &mut slice_start,
-                current_key.map_or_else(|| C_KEY, |ch| *ch.key()),
-            );
+            // the pattern is voiced in the key of the chord
+            let (sleep_time, packet_buf) =
+                scheduler.schedule_slice(timestamp, &mut slice_start, C_KEY);
             // Keep track of notes playing in this slice.
             // As we don't really know how much of the slice has been sent
             // already if we need to abort it (due to a key change) just
@@ -123,8 +613,8 @@ fn main() {
             // at key change than strictly necessary, but it should not be
             // noticeable and is very simple to do.
             for packet in packet_buf.iter() {
-                for chunk in packet.data().chunks(3) {
-                    crate::extract_playing_notes(chunk, &mut playing, true);
+                for message in midi_messages(packet.data()) {
+                    crate::extract_playing_notes(message, &mut playing, true);
                 }
             }
             output_port
@@ -136,14 +626,45 @@ fn main() {
                 println!("unexpected delay {}", sleep_time);
             }
         }
+        silence(&output_port, &destination, &mut playing);
+        output_port
+            .send(&destination, &scheduler.stop())
+            .expect("cannot send MIDI packet");
+        heard
     });
 
-    let mut input_line = String::new();
-    println!("Press [Enter] to finish ...");
-    std::io::stdin()
-        .read_line(&mut input_line)
-        .ok()
-        .expect("cannot read line");
+    println!("Type a bar number and [Enter] to jump there,");
+    println!("\"tempo <bpm>\" or \"ramp <bpm> <bars>\" to change the tempo,");
+    println!("or just [Enter] to finish ...");
+    loop {
+        let mut input_line = String::new();
+        std::io::stdin()
+            .read_line(&mut input_line)
+            .expect("cannot read line");
+        match parse_command(&input_line) {
+            Some(Command::Finish) => break,
+            Some(Command::JumpToBar(bar)) => *jump_to_bar.lock().unwrap() = Some(bar),
+            Some(Command::ChangeTempo(change)) => *tempo_change.lock().unwrap() = Some(change),
+            None => println!("Unknown command: {}", input_line.trim()),
+        }
+    }
+    running.store(false, Ordering::SeqCst);
+    let heard = handle.join().expect("cannot stop the accompaniment");
+    if let Some(path) = musicxml {
+        write_heard(&path, &heard, |title, heard| {
+            musicxml::write_lead_sheet(title, heard.meter(), heard.us_per_quarter(), &heard.bars())
+        });
+    }
+    if let Some(path) = chordpro {
+        write_heard(&path, &heard, |title, heard| {
+            chordpro::write_chordpro(title, heard.meter(), heard.us_per_quarter(), &heard.bars())
+        });
+    }
+    if let Some(path) = xf {
+        write_heard(&path, &heard, |title, heard| {
+            xf::write_xf(title, heard.meter(), heard.us_per_quarter(), &heard.bars())
+        });
+    }
 
     input_port
         .disconnect_source(&source)
@@ -151,10 +672,105 @@ fn main() {
     println!("disconnected from source <{}>", source_name);
 }
 
+// the chords heard written to `path`, titled after the file
+fn write_heard<T: AsRef<[u8]>>(
+    path: &str,
+    heard: &Progression,
+    write: impl Fn(&str, &Progression) -> T,
+) {
+    if heard.is_empty() {
+        println!("no chords heard to write to {}", path);
+        return;
+    }
+    let title = Path::new(path)
+        .file_stem()
+        .map_or(String::new(), |stem| stem.to_string_lossy().to_string());
+    match std::fs::write(path, write(&title, heard)) {
+        Ok(()) => println!("wrote the chords heard to {}", path),
+        Err(e) => println!("Cannot write {}: {}", path, e),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Finish,
+    // the bar counted from 0, typed counted from 1 as on a chart
+    JumpToBar(u32),
+    ChangeTempo(TempoChange),
+}
+
+fn parse_command(line: &str) -> Option<Command> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let tempo = |word: &str| {
+        word.parse::<f64>()
+            .ok()
+            .filter(|bpm| bpm.is_finite() && *bpm > 0.0)
+            .map(|bpm| tempo::us_per_quarter_from_bpm(bpm.clamp(tempo::MIN_BPM, tempo::MAX_BPM)))
+    };
+    match words.as_slice() {
+        [] => Some(Command::Finish),
+        [bar] => bar
+            .parse::<u32>()
+            .ok()
+            .and_then(|bar| bar.checked_sub(1))
+            .map(Command::JumpToBar),
+        ["tempo", bpm] => tempo(bpm).map(|t| Command::ChangeTempo(TempoChange::Set(t))),
+        ["ramp", bpm, bars] => match (tempo(bpm), bars.parse()) {
+            (Some(t), Ok(bars)) if bars <= tempo::MAX_RAMP_BARS => {
+                Some(Command::ChangeTempo(TempoChange::Ramp(t, bars)))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(target_os = "macos")]
 fn now() -> u64 {
     unsafe { AudioConvertHostTimeToNanos(AudioGetCurrentHostTime()) }
 }
 
+#[cfg(target_os = "macos")]
+fn host_time_to_nanos(host_time: u64) -> u64 {
+    // a zero timestamp means "now"
+    if host_time == 0 {
+        now()
+    } else {
+        unsafe { AudioConvertHostTimeToNanos(host_time) }
+    }
+}
+
+// without CoreAudio, the host time is in nanoseconds since the epoch
+#[cfg(not(target_os = "macos"))]
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos() as u64)
+}
+
+#[cfg(not(target_os = "macos"))]
+fn host_time_to_nanos(host_time: u64) -> u64 {
+    if host_time == 0 {
+        now()
+    } else {
+        host_time
+    }
+}
+
+// flush (drop) the rest of the scheduled slice, if any, and mute whatever is playing
+fn silence(
+    output_port: &coremidi::OutputPort,
+    destination: &coremidi::Destination,
+    playing: &mut Playing,
+) {
+    coremidi::flush().expect("cannot flush scheduled MIDI packets");
+    let packet_buf = play::mute_playing(playing);
+    output_port
+        .send(destination, &packet_buf)
+        .expect("cannot send MIDI packet");
+    playing.clear();
+}
+
 fn detect_chord(
     was_playing: &mut Playing,
     playing: &mut Playing,
@@ -190,7 +806,16 @@ fn detect_chord(
     }
 }
 
-const ACTIVE_SENSE: u8 = 0xFE;
+// how often the file of the style is looked at for changes
+const STYLE_POLL_INTERVAL: u64 = 1_000 * NS_PER_MS;
+
+const SYSTEM_REAL_TIME: u8 = 0xF8;
+const SYSTEM_COMMON: u8 = 0xF0;
+const SONG_POSITION_POINTER: u8 = 0xF2;
+const SONG_SELECT: u8 = 0xF3;
+const TIME_CODE_QUARTER_FRAME: u8 = 0xF1;
+const PROGRAM_CHANGE: u8 = 0xC0;
+const CHANNEL_PRESSURE: u8 = 0xD0;
 const COMMAND_MASK: u8 = 0xF0;
 const CHANNEL_MASK: u8 = 0x0F;
 const CONTROLLER: u8 = 0xB0;
@@ -201,18 +826,72 @@ use std::collections::HashSet;
 type ChannelNote = (u8, u8);
 type Playing = HashSet<ChannelNote>;
 
-fn process_midi<'a>(data: &[u8], playing: &'a mut Playing) {
+/// Split the data of a packet into separate MIDI messages, as CoreMIDI
+/// may merge several messages with the same timestamp into a single packet.
+/// Running status and system exclusive messages are not supported.
+fn midi_messages(data: &[u8]) -> Vec<&[u8]> {
+    let mut messages = vec![];
+    let mut start = 0;
+    while start < data.len() {
+        let status = data[start];
+        let length = match status {
+            _ if status >= SYSTEM_REAL_TIME => 1,
+            SONG_POSITION_POINTER => 3,
+            TIME_CODE_QUARTER_FRAME | SONG_SELECT => 2,
+            _ if status >= SYSTEM_COMMON => 1,
+            _ if status & COMMAND_MASK == PROGRAM_CHANGE => 2,
+            _ if status & COMMAND_MASK == CHANNEL_PRESSURE => 2,
+            _ => 3,
+        };
+        let end = std::cmp::min(start + length, data.len());
+        messages.push(&data[start..end]);
+        start = end;
+    }
+    messages
+}
+
+// a pressed tap key, or a tap controller going down, that is from below 64
+// in `pedal`, the value it had, to 64 or more
+fn is_tap(data: &[u8], tap: Tap, pedal: &mut u8) -> bool {
+    match (tap, data) {
+        (Tap::Note(tap_note), &[status, note, velocity]) => {
+            status & COMMAND_MASK == NOTE_ON && note == tap_note && velocity > 0
+        }
+        (Tap::Controller(tap_controller), &[status, controller, value])
+            if status & COMMAND_MASK == CONTROLLER && controller == tap_controller =>
+        {
+            let down = value >= 64 && *pedal < 64;
+            *pedal = value;
+            down
+        }
+        _ => false,
+    }
+}
+
+fn is_tap_note(data: &[u8], tap: Tap) -> bool {
+    match (tap, data) {
+        (Tap::Note(tap_note), &[status, note, _]) => {
+            (status & COMMAND_MASK == NOTE_ON || status & COMMAND_MASK == NOTE_OFF)
+                && note == tap_note
+        }
+        _ => false,
+    }
+}
+
+fn process_midi(data: &[u8], playing: &mut Playing) -> Option<u8> {
     extract_playing_notes(data, playing, false)
 }
 
-fn extract_playing_notes<'a>(data: &[u8], playing: &'a mut Playing, accumulate_notes: bool) {
-    if data.len() == 1 && data[0] == ACTIVE_SENSE {
-        return;
+// returns the velocity of a note-on
+fn extract_playing_notes(data: &[u8], playing: &mut Playing, accumulate_notes: bool) -> Option<u8> {
+    // real time messages, such as clock and active sensing, carry no notes
+    if data.len() == 1 && data[0] >= SYSTEM_REAL_TIME {
+        return None;
     }
 
     if data.len() != 3 {
         println!("packet not length 3; {:?})", data);
-        return;
+        return None;
     }
 
     let channel = data[0] & CHANNEL_MASK;
@@ -223,6 +902,7 @@ fn extract_playing_notes<'a>(data: &[u8], playing: &'a mut Playing, accumulate_n
         CONTROLLER => (), // controller
         NOTE_ON if velocity > 0 => {
             playing.insert((channel, note));
+            return Some(velocity);
         }
         NOTE_ON | NOTE_OFF if command != NOTE_ON || velocity == 0 => {
             if !accumulate_notes {
@@ -231,6 +911,7 @@ fn extract_playing_notes<'a>(data: &[u8], playing: &'a mut Playing, accumulate_n
         }
         _ => println!("Unknown command {} in packet {:?}", command, data),
     };
+    None
 }
 
 #[macro_export]
@@ -257,6 +938,14 @@ mod tests {
         assert_eq!(playing, hashset![(0x03, 0x3c)]);
     }
 
+    #[test]
+    fn test_note_on_velocity() {
+        let mut playing = HashSet::new();
+        assert_eq!(process_midi(&[0x93, 0x3c, 0x40], &mut playing), Some(0x40));
+        assert_eq!(process_midi(&[0x83, 0x3c, 0x40], &mut playing), None);
+        assert_eq!(process_midi(&[0xb0, 0x40, 0x7f], &mut playing), None);
+    }
+
     #[test]
     fn test_note_off() {
         let data = vec![0x83, 0x3c, 0x40];
@@ -264,6 +953,27 @@ mod tests {
         process_midi(&data, &mut playing);
         assert_eq!(playing, hashset![(0x03, 0x40), (0x04, 0x3c)]);
     }
+    #[test]
+    fn test_merged_packet_messages() {
+        let data = vec![0x90, 0x3c, 0x40, 0xf8, 0xc0, 0x05, 0x80, 0x3c, 0x40];
+        assert_eq!(
+            midi_messages(&data),
+            vec![
+                &[0x90, 0x3c, 0x40][..],
+                &[0xf8][..],
+                &[0xc0, 0x05][..],
+                &[0x80, 0x3c, 0x40][..]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_clock_is_ignored() {
+        let mut playing = hashset![(0x03, 0x3c)];
+        process_midi(&[0xf8], &mut playing);
+        assert_eq!(playing, hashset![(0x03, 0x3c)]);
+    }
+
     #[test]
     fn test_note_on_velocity_0() {
         let data = vec![0x93, 0x3c, 0x00];
@@ -271,4 +981,52 @@ mod tests {
         process_midi(&data, &mut playing);
         assert_eq!(playing, hashset![(0x03, 0x40), (0x04, 0x3c)]);
     }
+
+    #[test]
+    fn test_tap_note() {
+        let tap = Tap::Note(0x24);
+        let mut pedal = 0;
+        assert!(is_tap(&[0x90, 0x24, 0x40], tap, &mut pedal));
+        assert!(!is_tap(&[0x90, 0x24, 0x00], tap, &mut pedal));
+        assert!(!is_tap(&[0x90, 0x3c, 0x40], tap, &mut pedal));
+        assert!(is_tap_note(&[0x80, 0x24, 0x40], tap));
+        assert!(!is_tap_note(&[0x80, 0x3c, 0x40], tap));
+    }
+
+    #[test]
+    fn test_tap_controller() {
+        let tap = Tap::Controller(64);
+        let mut pedal = 0;
+        assert!(is_tap(&[0xb0, 64, 127], tap, &mut pedal));
+        // a half pedal moving while down
+        assert!(!is_tap(&[0xb0, 64, 100], tap, &mut pedal));
+        assert!(!is_tap(&[0xb0, 64, 0], tap, &mut pedal));
+        assert!(!is_tap(&[0xb0, 1, 127], tap, &mut pedal));
+        assert!(is_tap(&[0xb0, 64, 64], tap, &mut pedal));
+        assert!(!is_tap_note(&[0x90, 64, 127], tap));
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("\n"), Some(Command::Finish));
+        assert_eq!(parse_command("12\n"), Some(Command::JumpToBar(11)));
+        assert_eq!(parse_command("1\n"), Some(Command::JumpToBar(0)));
+        assert_eq!(parse_command("0\n"), None);
+        assert_eq!(
+            parse_command("tempo 150\n"),
+            Some(Command::ChangeTempo(TempoChange::Set(400_000)))
+        );
+        assert_eq!(
+            parse_command("ramp 60 4\n"),
+            Some(Command::ChangeTempo(TempoChange::Ramp(1_000_000, 4)))
+        );
+        assert_eq!(
+            parse_command("tempo 1e9\n"),
+            Some(Command::ChangeTempo(TempoChange::Set(150_000)))
+        );
+        assert_eq!(parse_command("ramp 60 100000000\n"), None);
+        assert_eq!(parse_command("tempo 0\n"), None);
+        assert_eq!(parse_command("ramp 60\n"), None);
+        assert_eq!(parse_command("twelve\n"), None);
+    }
 }
@@ -0,0 +1,742 @@
+/** MusicXML lead sheets: the chord symbols of a score, and a chart of chord
+symbols over slashes, as notation software reads it.
+
+The `<harmony>` elements of the first part with any are read with the
+measure they are in and their position in it, following the notes, the
+backups and the forwards of the measure. The root, the kind, the degrees
+added, altered or taken away and the bass note make the chord, as the
+closest komp knows when there is none with exactly its notes. The kind
+`none` is no chord. The song is played at the first tempo of the part, and
+in its time signature, which may not change.
+
+A lead sheet is written with a slash for each beat, and a chord symbol on
+the beat of each change, with its degrees when komp has no kind for it in
+MusicXML.
+*/
+use crate::pattern::Meter;
+use crate::song::{bar_changes, Bar, Change, Song, SongSection};
+use crate::tempo::bpm;
+use komp_core::{detect_chord, Chord, Key};
+use roxmltree::{Document, Node, ParsingOptions};
+
+type Kind = fn(Key) -> Chord;
+
+// the kinds of MusicXML with their notes, in semitones above the root,
+// and the closest chord
+const KINDS: &[(&str, &[u8], Kind)] = &[
+    ("major", &[0, 4, 7], Chord::Major),
+    ("minor", &[0, 3, 7], Chord::Minor),
+    ("augmented", &[0, 4, 8], Chord::Aug),
+    ("diminished", &[0, 3, 6], Chord::Dim),
+    ("dominant", &[0, 4, 7, 10], Chord::Major7),
+    ("major-seventh", &[0, 4, 7, 11], Chord::MajorMaj7),
+    ("minor-seventh", &[0, 3, 7, 10], Chord::Minor7),
+    ("diminished-seventh", &[0, 3, 6, 9], Chord::Dim7),
+    ("augmented-seventh", &[0, 4, 8, 10], Chord::Major7Aug),
+    ("half-diminished", &[0, 3, 6, 10], Chord::Minor7b5),
+    ("major-minor", &[0, 3, 7, 11], Chord::MinorMaj7),
+    ("major-sixth", &[0, 4, 7, 9], Chord::Major6),
+    ("minor-sixth", &[0, 3, 7, 9], Chord::Minor6),
+    ("dominant-ninth", &[0, 2, 4, 7, 10], Chord::Major7_9),
+    ("major-ninth", &[0, 2, 4, 7, 11], Chord::MajorMaj7_9),
+    ("minor-ninth", &[0, 2, 3, 7, 10], Chord::Minor7_9),
+    ("dominant-11th", &[0, 2, 4, 5, 7, 10], Chord::SevenSus4),
+    ("major-11th", &[0, 2, 4, 5, 7, 11], Chord::MajorMaj7_9),
+    ("minor-11th", &[0, 2, 3, 5, 7, 10], Chord::Minor7_11),
+    ("dominant-13th", &[0, 2, 4, 7, 9, 10], Chord::Major7_13),
+    ("major-13th", &[0, 2, 4, 7, 9, 11], Chord::MajorMaj7_9),
+    ("minor-13th", &[0, 2, 3, 7, 9, 10], Chord::Minor7_9),
+    ("suspended-second", &[0, 2, 7], Chord::Sus2),
+    ("suspended-fourth", &[0, 5, 7], Chord::Sus4),
+    ("Neapolitan", &[0, 4, 7], Chord::Major),
+    ("Italian", &[0, 4, 10], Chord::Major7),
+    ("French", &[0, 4, 6, 10], Chord::Major7b5),
+    ("German", &[0, 4, 7, 10], Chord::Major7),
+    ("Tristan", &[0, 3, 6, 10], Chord::Minor7b5),
+    ("pedal", &[0], Chord::None),
+    ("power", &[0, 7], Chord::Five),
+];
+
+// the degrees added to a chord are above a dominant thirteenth
+const ADDED_DEGREES: [(u8, u8); 10] = [
+    (1, 0),
+    (2, 2),
+    (3, 4),
+    (4, 5),
+    (5, 7),
+    (6, 9),
+    (7, 10),
+    (9, 2),
+    (11, 5),
+    (13, 9),
+];
+
+// the notes that may be a degree of a chord, the usual one first
+fn degree_notes(degree: u8) -> &'static [u8] {
+    match degree {
+        1 => &[0],
+        3 => &[4, 3],
+        5 => &[7, 6, 8],
+        7 => &[10, 11, 9],
+        2 | 9 => &[2, 1, 3],
+        4 | 11 => &[5, 6],
+        6 | 13 => &[9, 8],
+        _ => &[],
+    }
+}
+
+/// A chord symbol of a score, at a tick of a measure, both from 0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Harmony {
+    pub measure: u32,
+    pub tick: u32,
+    /// `None` for no chord.
+    pub chord: Option<Chord>,
+    /// The bass note, when it is not the root.
+    pub bass: Option<Key>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LeadSheet {
+    pub title: Option<String>,
+    pub us_per_quarter: Option<u32>,
+    pub meter: Option<Meter>,
+    pub ticks_per_quarter: u32,
+    /// The number of measures of the part.
+    pub measures: u32,
+    pub harmonies: Vec<Harmony>,
+}
+
+fn child<'a>(node: Node<'a, 'a>, name: &str) -> Option<Node<'a, 'a>> {
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+fn child_text<'a>(node: Node<'a, 'a>, name: &str) -> Option<&'a str> {
+    child(node, name)
+        .and_then(|child| child.text())
+        .map(str::trim)
+}
+
+fn child_number<T: std::str::FromStr>(node: Node, name: &str) -> Option<T> {
+    child_text(node, name).and_then(|text| text.parse().ok())
+}
+
+// a step, such as B, and its alteration in semitones, such as -1 for Bb
+fn read_key(node: Node, step: &str, alter: &str) -> Result<Key, String> {
+    let step = child_text(node, step).ok_or_else(|| "a step is missing".to_string())?;
+    let natural: Key = step.parse().map_err(|_| format!("wrong step: {}", step))?;
+    let alter = child_text(node, alter).map_or(Ok(0.0), |alter| {
+        alter
+            .parse::<f64>()
+            .map_err(|_| format!("wrong alter: {}", alter))
+    })?;
+    Ok(Key(
+        (natural.0 as i32 + alter.round() as i32).rem_euclid(12) as u8,
+    ))
+}
+
+// the chord of the notes above `root`, when komp knows it
+fn chord_of(root: Key, notes: &[u8]) -> Option<Chord> {
+    let sounding = notes.iter().map(|note| 48 + root.0 + note).collect();
+    detect_chord(&sounding)
+        .into_iter()
+        .find(|chord| *chord.key() == root)
+}
+
+fn read_harmony(harmony: Node) -> Result<(Option<Chord>, Option<Key>), String> {
+    let kind = child(harmony, "kind").ok_or_else(|| "a kind is missing".to_string())?;
+    let name = kind.text().map_or("", str::trim);
+    if name == "none" {
+        return Ok((None, None));
+    }
+    let root = match child(harmony, "root") {
+        Some(root) => read_key(root, "root-step", "root-alter")?,
+        None => return Err("a root is missing".to_string()),
+    };
+    let bass = match child(harmony, "bass") {
+        Some(bass) => Some(read_key(bass, "bass-step", "bass-alter")?).filter(|bass| *bass != root),
+        None => None,
+    };
+    let (notes, closest) = match KINDS.iter().find(|(kind, _, _)| *kind == name) {
+        Some((_, notes, closest)) => (*notes, *closest),
+        // as written, otherwise the triad
+        None => match kind
+            .attribute("text")
+            .and_then(|text| format!("{}{}", root, text).parse::<Chord>().ok())
+        {
+            Some(chord) => (chord.tones(), chord_kind(chord)),
+            None if name == "other" => (&[0, 4, 7][..], Chord::Major as Kind),
+            None => return Err(format!("unknown kind: {}", name)),
+        },
+    };
+    let mut notes = notes.to_vec();
+    for degree in harmony
+        .children()
+        .filter(|child| child.has_tag_name("degree"))
+    {
+        let value: u8 = child_number(degree, "degree-value")
+            .ok_or_else(|| "a degree value is missing".to_string())?;
+        let alter: i8 = child_text(degree, "degree-alter")
+            .map_or(Some(0.0), |alter| alter.parse::<f64>().ok())
+            .map(|alter| alter.round() as i8)
+            .ok_or_else(|| "wrong degree alter".to_string())?;
+        let present = degree_notes(value)
+            .iter()
+            .find(|note| notes.contains(note))
+            .copied();
+        match child_text(degree, "degree-type") {
+            Some("add") => {
+                let (_, note) = ADDED_DEGREES
+                    .iter()
+                    .find(|(added, _)| *added == value)
+                    .ok_or_else(|| format!("wrong degree: {}", value))?;
+                notes.push((*note as i8 + alter).rem_euclid(12) as u8);
+            }
+            Some("alter") => {
+                if let Some(note) = present {
+                    notes.retain(|n| *n != note);
+                    notes.push((note as i8 + alter).rem_euclid(12) as u8);
+                }
+            }
+            Some("subtract") => notes.retain(|note| Some(*note) != present),
+            other => return Err(format!("wrong degree type: {}", other.unwrap_or(""))),
+        }
+    }
+    notes.sort_unstable();
+    notes.dedup();
+    let chord = chord_of(root, &notes).unwrap_or_else(|| closest(root));
+    Ok((Some(chord), bass))
+}
+
+// the kind of a chord, to make it over another root
+fn chord_kind(chord: Chord) -> Kind {
+    KINDS
+        .iter()
+        .map(|(_, _, kind)| *kind)
+        .chain(EXTENDED.iter().map(|(kind, _, _)| *kind))
+        .find(|kind| kind(*chord.key()) == chord)
+        .unwrap_or(Chord::Major)
+}
+
+// a degree added to a kind or altered, its number and its alteration
+type Degree = (&'static str, u8, i8);
+
+// how the chords without a kind of their own are written: a kind, and its
+// degrees
+const EXTENDED: &[(Kind, &str, &[Degree])] = &[
+    (Chord::SevenSus4, "suspended-fourth", &[("add", 7, 0)]),
+    (Chord::Major6_9, "major-sixth", &[("add", 9, 0)]),
+    (Chord::Minor6_9, "minor-sixth", &[("add", 9, 0)]),
+    (Chord::Major7b9, "dominant", &[("add", 9, -1)]),
+    (Chord::Major7Plus9, "dominant", &[("add", 9, 1)]),
+    (Chord::Major7Plus11, "dominant", &[("add", 11, 1)]),
+    (Chord::Major7b13, "dominant", &[("add", 13, -1)]),
+    (Chord::Major7_13, "dominant", &[("add", 13, 0)]),
+    (Chord::Minor7_11, "minor-seventh", &[("add", 11, 0)]),
+    (Chord::Major7b5, "dominant", &[("alter", 5, -1)]),
+    (Chord::MajorMaj7Plus11, "major-seventh", &[("add", 11, 1)]),
+    (Chord::MajorMaj7Aug, "major-seventh", &[("alter", 5, 1)]),
+    (Chord::MinorMaj7_9, "major-minor", &[("add", 9, 0)]),
+    (Chord::MajorAdd9, "major", &[("add", 9, 0)]),
+    (Chord::MinorAdd9, "minor", &[("add", 9, 0)]),
+];
+
+// the kind of MusicXML of a chord, and its degrees
+fn kind_name(chord: Chord) -> (&'static str, &'static [Degree]) {
+    let key = *chord.key();
+    if let Some((_, name, degrees)) = EXTENDED.iter().find(|(kind, _, _)| kind(key) == chord) {
+        return (name, degrees);
+    }
+    KINDS
+        .iter()
+        .find(|(_, notes, kind)| kind(key) == chord && *notes == chord.tones())
+        .map_or(("major", &[]), |(name, _, _)| (name, &[]))
+}
+
+impl LeadSheet {
+    /// The chord symbols of `xml`, at `ticks_per_quarter`.
+    pub fn parse(xml: &str, ticks_per_quarter: u32) -> Result<LeadSheet, String> {
+        // scores written by notation software declare their DTD
+        let options = ParsingOptions {
+            allow_dtd: true,
+            ..ParsingOptions::default()
+        };
+        let document = Document::parse_with_options(xml, options).map_err(|e| e.to_string())?;
+        let score = document.root_element();
+        if !score.has_tag_name("score-partwise") {
+            return Err(format!("not a partwise score: {}", score.tag_name().name()));
+        }
+        let title = child(score, "work")
+            .and_then(|work| child_text(work, "work-title"))
+            .or_else(|| child_text(score, "movement-title"))
+            .filter(|title| !title.is_empty())
+            .map(str::to_string);
+        let parts: Vec<Node> = score
+            .children()
+            .filter(|child| child.has_tag_name("part"))
+            .collect();
+        let part = parts
+            .iter()
+            .find(|part| part.descendants().any(|node| node.has_tag_name("harmony")))
+            .or_else(|| parts.first())
+            .ok_or_else(|| "no part".to_string())?;
+        let us_per_quarter = part
+            .descendants()
+            .filter(|node| node.has_tag_name("sound"))
+            .find_map(|sound| sound.attribute("tempo"))
+            .and_then(|tempo| tempo.parse::<f64>().ok())
+            .filter(|bpm| bpm.is_finite() && *bpm > 0.0)
+            .map(crate::tempo::us_per_quarter_from_bpm);
+        let mut meter: Option<Meter> = None;
+        for time in part.descendants().filter(|node| node.has_tag_name("time")) {
+            if let (Some(beats), Some(unit)) =
+                (child_text(time, "beats"), child_text(time, "beat-type"))
+            {
+                let time_signature = format!("{}/{}", beats, unit);
+                let read: Meter = time_signature
+                    .parse()
+                    .map_err(|_| format!("unsupported time signature: {}", time_signature))?;
+                match meter {
+                    Some(meter) if meter != read => {
+                        return Err(format!(
+                            "the meter changes from {} to {}, a song has one meter",
+                            meter, read
+                        ))
+                    }
+                    _ => meter = Some(read),
+                }
+            }
+        }
+
+        let mut divisions = 1;
+        let mut harmonies = vec![];
+        let measures: Vec<Node> = part
+            .children()
+            .filter(|child| child.has_tag_name("measure"))
+            .collect();
+        for (index, measure) in measures.iter().enumerate() {
+            // in divisions of a quarter
+            let mut position: i64 = 0;
+            let too_long = || format!("measure {} is too long", index + 1);
+            for node in measure.children() {
+                let duration = child_number::<i64>(node, "duration").unwrap_or(0);
+                match node.tag_name().name() {
+                    "attributes" => {
+                        if let Some(value) = child_number(node, "divisions") {
+                            divisions = std::cmp::max(value, 1);
+                        }
+                    }
+                    "note" if child(node, "chord").is_none() && child(node, "grace").is_none() => {
+                        position = position.checked_add(duration).ok_or_else(too_long)?
+                    }
+                    "backup" => position = position.checked_sub(duration).ok_or_else(too_long)?,
+                    "forward" => position = position.checked_add(duration).ok_or_else(too_long)?,
+                    "harmony" => {
+                        let offset = child_number::<i64>(node, "offset").unwrap_or(0);
+                        let at = position.checked_add(offset).ok_or_else(too_long)?;
+                        let tick = std::cmp::max(at, 0)
+                            .checked_mul(ticks_per_quarter as i64)
+                            .map(|ticks| ticks / divisions)
+                            .filter(|tick| *tick <= u32::MAX as i64)
+                            .ok_or_else(too_long)?;
+                        let (chord, bass) = read_harmony(node)
+                            .map_err(|e| format!("measure {}: {}", index + 1, e))?;
+                        harmonies.push(Harmony {
+                            measure: index as u32,
+                            tick: tick as u32,
+                            chord,
+                            bass,
+                        });
+                    }
+                    _ => (),
+                }
+            }
+        }
+        Ok(LeadSheet {
+            title,
+            us_per_quarter,
+            meter,
+            ticks_per_quarter,
+            measures: measures.len() as u32,
+            harmonies,
+        })
+    }
+
+    /// A song of a section with the measures, each chord from its beat. The
+    /// measures before the first chord play no chord.
+    pub fn to_song(&self, title: &str) -> Song {
+        let meter = self.meter.unwrap_or(crate::pattern::COMMON_TIME);
+        let beat_ticks = meter.ticks_per_beat(self.ticks_per_quarter);
+        let mut last = None;
+        let bars = (0..self.measures)
+            .map(|measure| {
+                let placed =
+                    self.harmonies
+                        .iter()
+                        .filter(|h| h.measure == measure)
+                        .map(|harmony| {
+                            let beat =
+                                std::cmp::min(harmony.tick / beat_ticks, meter.beats as u32 - 1);
+                            (beat as u8, harmony.chord)
+                        });
+                let changes = bar_changes(placed, last);
+                last = changes.last().and_then(|change| change.chord);
+                Bar {
+                    changes,
+                    ..Bar::default()
+                }
+            })
+            .collect();
+        Song {
+            title: title.to_string(),
+            us_per_quarter: self.us_per_quarter,
+            meter: self.meter,
+            sections: vec![SongSection {
+                name: "A".to_string(),
+                bars,
+            }],
+            form: vec!["A".to_string()],
+        }
+    }
+}
+
+const DIVISIONS: u32 = 24;
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn key_xml(key: Key, step: &str, alter: &str) -> String {
+    let name = key.to_string();
+    let alter = match &name[1..] {
+        "b" => format!("<{0}>-1</{0}>", alter),
+        "#" => format!("<{0}>1</{0}>", alter),
+        _ => String::new(),
+    };
+    format!("<{0}>{1}</{0}>{2}", step, &name[..1], alter)
+}
+
+fn harmony_xml(chord: Option<Chord>) -> String {
+    let chord = match chord {
+        Some(chord) => chord,
+        None => {
+            return "<harmony><root><root-step>C</root-step></root>\
+                    <kind text=\"N.C.\">none</kind></harmony>"
+                .to_string()
+        }
+    };
+    let (kind, degrees) = kind_name(chord);
+    let mut xml = format!(
+        "<harmony><root>{}</root><kind text=\"{}\">{}</kind>",
+        key_xml(*chord.key(), "root-step", "root-alter"),
+        escape(chord.suffix()),
+        kind
+    );
+    for (degree_type, value, alter) in degrees.iter() {
+        xml.push_str(&format!(
+            "<degree><degree-value>{}</degree-value><degree-alter>{}</degree-alter>\
+             <degree-type>{}</degree-type></degree>",
+            value, alter, degree_type
+        ));
+    }
+    xml.push_str("</harmony>");
+    xml
+}
+
+/// A lead sheet of the changes of each bar, a slash for each beat.
+pub fn write_lead_sheet(
+    title: &str,
+    meter: Meter,
+    us_per_quarter: u32,
+    bars: &[Vec<Change>],
+) -> String {
+    let beat_divisions = DIVISIONS * 4 / meter.unit as u32;
+    let beat_type = match meter.unit {
+        1 => "whole",
+        2 => "half",
+        8 => "eighth",
+        16 => "16th",
+        _ => "quarter",
+    };
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n\
+         <!DOCTYPE score-partwise PUBLIC \"-//Recordare//DTD MusicXML 4.0 Partwise//EN\" \
+         \"http://www.musicxml.org/dtds/partwise.dtd\">\n\
+         <score-partwise version=\"4.0\">\n",
+    );
+    xml.push_str(&format!(
+        "  <work><work-title>{}</work-title></work>\n\
+         \x20 <part-list><score-part id=\"P1\"><part-name>Chords</part-name></score-part></part-list>\n\
+         \x20 <part id=\"P1\">\n",
+        escape(title)
+    ));
+    for (index, changes) in bars.iter().enumerate() {
+        xml.push_str(&format!("    <measure number=\"{}\">\n", index + 1));
+        if index == 0 {
+            xml.push_str(&format!(
+                "      <attributes><divisions>{}</divisions><key><fifths>0</fifths></key>\
+                 <time><beats>{}</beats><beat-type>{}</beat-type></time>\
+                 <clef><sign>G</sign><line>2</line></clef>\
+                 <measure-style><slash type=\"start\" use-stems=\"no\"/></measure-style>\
+                 </attributes>\n",
+                DIVISIONS, meter.beats, meter.unit
+            ));
+            let tempo = bpm(us_per_quarter).round();
+            xml.push_str(&format!(
+                "      <direction placement=\"above\"><direction-type><metronome>\
+                 <beat-unit>quarter</beat-unit><per-minute>{}</per-minute></metronome>\
+                 </direction-type><sound tempo=\"{}\"/></direction>\n",
+                tempo, tempo
+            ));
+        }
+        for beat in 0..meter.beats {
+            if let Some(change) = changes.iter().find(|change| change.beat == beat) {
+                xml.push_str(&format!("      {}\n", harmony_xml(change.chord)));
+            }
+            xml.push_str(&format!(
+                "      <note><pitch><step>B</step><octave>4</octave></pitch>\
+                 <duration>{}</duration><type>{}</type><stem>none</stem>\
+                 <notehead>slash</notehead></note>\n",
+                beat_divisions, beat_type
+            ));
+        }
+        if index + 1 == bars.len() {
+            xml.push_str(
+                "      <barline location=\"right\"><bar-style>light-heavy</bar-style></barline>\n",
+            );
+        }
+        xml.push_str("    </measure>\n");
+    }
+    xml.push_str("  </part>\n</score-partwise>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::COMMON_TIME;
+    use komp_core::*;
+
+    const SCORE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<score-partwise version="3.1">
+  <work><work-title>Minor Blues</work-title></work>
+  <part-list><score-part id="P1"><part-name>Lead</part-name></score-part></part-list>
+  <part id="P1">
+    <measure number="1">
+      <attributes><divisions>2</divisions><time><beats>4</beats><beat-type>4</beat-type></time></attributes>
+      <direction><sound tempo="90"/></direction>
+      <harmony><root><root-step>C</root-step></root><kind>minor-seventh</kind></harmony>
+      <note><pitch><step>C</step><octave>5</octave></pitch><duration>4</duration></note>
+      <harmony><root><root-step>B</root-step><root-alter>-1</root-alter></root><kind>dominant</kind>
+        <bass><bass-step>D</bass-step></bass></harmony>
+      <note><pitch><step>E</step><alter>-1</alter><octave>5</octave></pitch><duration>3</duration></note>
+      <note><chord/><pitch><step>G</step><octave>5</octave></pitch><duration>3</duration></note>
+      <note><rest/><duration>1</duration></note>
+    </measure>
+    <measure number="2">
+      <note><rest/><duration>8</duration></note>
+      <backup><duration>8</duration></backup>
+      <forward><duration>6</duration></forward>
+      <harmony><root><root-step>G</root-step></root><kind>dominant</kind>
+        <degree><degree-value>9</degree-value><degree-alter>1</degree-alter><degree-type>add</degree-type></degree>
+        <offset>-2</offset></harmony>
+    </measure>
+    <measure number="3">
+      <harmony><root><root-step>F</root-step><root-alter>1</root-alter></root><kind text="ø7">half-diminished</kind></harmony>
+      <harmony><root><root-step>C</root-step></root><kind>none</kind><offset>4</offset></harmony>
+      <note><rest/><duration>8</duration></note>
+    </measure>
+    <measure number="4">
+      <note><rest/><duration>8</duration></note>
+    </measure>
+  </part>
+</score-partwise>"#;
+
+    fn harmony(measure: u32, tick: u32, chord: Option<Chord>, bass: Option<Key>) -> Harmony {
+        Harmony {
+            measure,
+            tick,
+            chord,
+            bass,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let sheet = LeadSheet::parse(SCORE, 96).unwrap();
+        assert_eq!(sheet.title.as_deref(), Some("Minor Blues"));
+        assert_eq!(sheet.us_per_quarter, Some(666_667));
+        assert_eq!(sheet.meter, Some(COMMON_TIME));
+        assert_eq!(sheet.measures, 4);
+        assert_eq!(
+            sheet.harmonies,
+            vec![
+                harmony(0, 0, Some(Chord::Minor7(C_KEY)), None),
+                harmony(0, 192, Some(Chord::Major7(ASHARP_KEY)), Some(D_KEY)),
+                harmony(1, 192, Some(Chord::Major7Plus9(G_KEY)), None),
+                harmony(2, 0, Some(Chord::Minor7b5(FSHARP_KEY)), None),
+                harmony(2, 192, None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_degrees() {
+        let chord = |kind: &str, degrees: &[(&str, u8, i8)]| {
+            let degrees: String = degrees
+                .iter()
+                .map(|(degree_type, value, alter)| {
+                    format!(
+                        "<degree><degree-value>{}</degree-value><degree-alter>{}</degree-alter>\
+                         <degree-type>{}</degree-type></degree>",
+                        value, alter, degree_type
+                    )
+                })
+                .collect();
+            let xml = format!(
+                "<harmony><root><root-step>D</root-step></root><kind>{}</kind>{}</harmony>",
+                kind, degrees
+            );
+            let document = Document::parse(&xml).unwrap();
+            read_harmony(document.root_element()).map(|(chord, _)| chord)
+        };
+        assert_eq!(
+            chord("dominant", &[("alter", 5, 1)]),
+            Ok(Some(Chord::Major7Aug(D_KEY)))
+        );
+        assert_eq!(
+            chord("minor-seventh", &[("subtract", 5, 0)]),
+            Ok(Some(Chord::Minor7(D_KEY)))
+        );
+        assert_eq!(
+            chord("major", &[("add", 6, 0), ("add", 9, 0)]),
+            Ok(Some(Chord::Major6_9(D_KEY)))
+        );
+        // the closest chord when there is none with these notes
+        assert_eq!(
+            chord("dominant-11th", &[]),
+            Ok(Some(Chord::SevenSus4(D_KEY)))
+        );
+        assert!(chord("dominant", &[("add", 8, 0)]).is_err());
+        assert!(chord("seventh", &[]).is_err());
+    }
+
+    #[test]
+    fn test_to_song() {
+        let song = LeadSheet::parse(SCORE, 96).unwrap().to_song("Minor Blues");
+        assert_eq!(song.us_per_quarter, Some(666_667));
+        assert_eq!(
+            song.to_toml().unwrap(),
+            "title = \"Minor Blues\"\n\
+             tempo = 90.0\n\
+             meter = \"4/4\"\n\
+             form = \"A\"\n\
+             \n\
+             [[section]]\n\
+             name = \"A\"\n\
+             bars = \"Cm7 Bb7 | Bb7 G7#9 | F#m7b5 N.C. | N.C.\"\n"
+        );
+    }
+
+    #[test]
+    fn test_lead_sheet_round_trip() {
+        let change = |beat, chord| Change { beat, chord };
+        let chords = [
+            Chord::None(E_KEY),
+            Chord::Aug(F_KEY),
+            Chord::Dim7(B_KEY),
+            Chord::SevenSus4(D_KEY),
+            Chord::Major6_9(F_KEY),
+            Chord::Minor6_9(ASHARP_KEY),
+            Chord::Major7b9(A_KEY),
+            Chord::Major7Plus9(G_KEY),
+            Chord::Major7Plus11(DSHARP_KEY),
+            Chord::Major7b13(CSHARP_KEY),
+            Chord::Major7_13(C_KEY),
+            Chord::Major7Aug(E_KEY),
+            Chord::Minor7_9(D_KEY),
+            Chord::Minor7_11(A_KEY),
+            Chord::Major7b5(GSHARP_KEY),
+            Chord::MajorMaj7_9(F_KEY),
+            Chord::MajorMaj7Plus11(C_KEY),
+            Chord::MajorMaj7Aug(G_KEY),
+            Chord::MinorMaj7_9(E_KEY),
+            Chord::MajorAdd9(D_KEY),
+            Chord::MinorAdd9(FSHARP_KEY),
+            Chord::Five(B_KEY),
+        ];
+        let mut bars: Vec<Vec<Change>> = chords
+            .chunks(2)
+            .map(|two| vec![change(0, Some(two[0])), change(2, Some(two[1]))])
+            .collect();
+        bars.push(vec![]);
+        bars.push(vec![change(1, None)]);
+        let xml = write_lead_sheet("Tom & Jerry", COMMON_TIME, 500_000, &bars);
+        let sheet = LeadSheet::parse(&xml, 96).unwrap();
+        assert_eq!(sheet.title.as_deref(), Some("Tom & Jerry"));
+        assert_eq!(sheet.us_per_quarter, Some(500_000));
+        assert_eq!(sheet.meter, Some(COMMON_TIME));
+        assert_eq!(sheet.measures, bars.len() as u32);
+        let read: Vec<Harmony> = bars
+            .iter()
+            .enumerate()
+            .flat_map(|(measure, changes)| {
+                changes.iter().map(move |change| {
+                    harmony(measure as u32, change.beat as u32 * 96, change.chord, None)
+                })
+            })
+            .collect();
+        assert_eq!(sheet.harmonies, read);
+        assert_eq!(
+            xml.matches("<notehead>slash</notehead>").count(),
+            4 * bars.len()
+        );
+    }
+
+    #[test]
+    fn test_lead_sheet_in_six_eight() {
+        let bars = vec![vec![Change {
+            beat: 3,
+            chord: Some(Chord::Minor(A_KEY)),
+        }]];
+        let xml = write_lead_sheet("Jig", Meter::new(6, 8), 500_000, &bars);
+        assert_eq!(xml.matches("<type>eighth</type>").count(), 6);
+        let sheet = LeadSheet::parse(&xml, 96).unwrap();
+        assert_eq!(
+            sheet.harmonies,
+            vec![harmony(0, 144, Some(Chord::Minor(A_KEY)), None)]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(LeadSheet::parse("<score-partwise>", 96).is_err());
+        assert!(LeadSheet::parse("<score-timewise/>", 96).is_err());
+        assert!(LeadSheet::parse("<score-partwise/>", 96).is_err());
+        let missing_root = SCORE.replace("<root><root-step>C</root-step></root>", "");
+        assert_eq!(
+            LeadSheet::parse(&missing_root, 96),
+            Err("measure 1: a root is missing".to_string())
+        );
+        let three = SCORE.replace("<beat-type>4</beat-type>", "<beat-type>3</beat-type>");
+        assert_eq!(
+            LeadSheet::parse(&three, 96),
+            Err("unsupported time signature: 4/3".to_string())
+        );
+        let waltz = SCORE.replacen(
+            "<note><rest/><duration>8</duration></note>",
+            "<attributes><time><beats>3</beats><beat-type>4</beat-type></time></attributes>\
+             <note><rest/><duration>8</duration></note>",
+            1,
+        );
+        assert!(LeadSheet::parse(&waltz, 96).is_err());
+        let long = SCORE.replace(
+            "<forward><duration>6</duration></forward>",
+            "<forward><duration>9223372036854775807</duration></forward>",
+        );
+        assert!(LeadSheet::parse(&long, 96).is_err());
+    }
+}
@@ -27,50 +27,133 @@ pub fn create_note(
     )
 }
 
+/// A time signature, such as 3/4 or 6/8: the number of beats to a bar,
+/// and the note value of a beat (4 for quarters, 8 for eighths).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Meter {
+    pub beats: u8,
+    pub unit: u8,
+}
+
+pub const COMMON_TIME: Meter = Meter { beats: 4, unit: 4 };
+
+impl Meter {
+    pub fn new(beats: u8, unit: u8) -> Meter {
+        Meter { beats, unit }
+    }
+    pub fn ticks_per_beat(&self, ticks_per_quarter: u32) -> u32 {
+        ticks_per_quarter * 4 / self.unit as u32
+    }
+    pub fn ticks_per_bar(&self, ticks_per_quarter: u32) -> u32 {
+        self.beats as u32 * self.ticks_per_beat(ticks_per_quarter)
+    }
+}
+
+impl std::fmt::Display for Meter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}/{}", self.beats, self.unit)
+    }
+}
+
+impl std::str::FromStr for Meter {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Meter, String> {
+        let wrong = || format!("wrong meter: {}", s);
+        let mut parts = s.trim().splitn(2, '/');
+        let beats = parts.next().and_then(|b| b.parse::<u8>().ok());
+        let unit = parts.next().and_then(|u| u.parse::<u8>().ok());
+        match (beats, unit) {
+            (Some(beats), Some(unit)) if beats > 0 && [1, 2, 4, 8, 16].contains(&unit) => {
+                Ok(Meter::new(beats, unit))
+            }
+            _ => Err(wrong()),
+        }
+    }
+}
+
+/// A position (or a duration) in bars, beats and ticks of a meter, at a
+/// number of ticks per quarter. Ticks past the beat are carried into the
+/// beats and bars, also by an addition, and a subtraction borrows from
+/// them. A subtraction that ends before the first bar saturates to its
+/// start.
 #[derive(Clone, Copy, Debug)]
 pub struct TimeCode {
+    meter: Meter,
+    ticks_per_quarter: u32,
     bar: u32,
     beat: u8,
     tick: u32,
 }
 
 impl TimeCode {
+    #[cfg(test)]
     pub fn new(bar: u32, beat: u8, tick: u32) -> TimeCode {
-        TimeCode { bar, beat, tick }
+        TimeCode::with_meter(96, COMMON_TIME, bar, beat, tick)
+    }
+    pub fn with_meter(
+        ticks_per_quarter: u32,
+        meter: Meter,
+        bar: u32,
+        beat: u8,
+        tick: u32,
+    ) -> TimeCode {
+        let start = TimeCode {
+            meter,
+            ticks_per_quarter,
+            bar: 0,
+            beat: 0,
+            tick: 0,
+        };
+        start.with_ticks(start.ticks_to(bar, beat, tick as i64))
+    }
+
+    fn ticks_per_beat(&self) -> i64 {
+        std::cmp::max(self.meter.ticks_per_beat(self.ticks_per_quarter), 1) as i64
     }
+
+    // the ticks from the start to a bar, a beat and a tick of the meter
+    fn ticks_to(&self, bar: u32, beat: u8, tick: i64) -> i64 {
+        (bar as i64 * self.meter.beats as i64 + beat as i64) * self.ticks_per_beat() + tick
+    }
+
+    // the time code `ticks` from the start
+    fn with_ticks(&self, ticks: i64) -> TimeCode {
+        let ticks = std::cmp::max(ticks, 0);
+        let beats = ticks / self.ticks_per_beat();
+        TimeCode {
+            bar: (beats / self.meter.beats as i64).min(u32::MAX as i64) as u32,
+            beat: (beats % self.meter.beats as i64) as u8,
+            tick: (ticks % self.ticks_per_beat()) as u32,
+            ..*self
+        }
+    }
+
+    /// The ticks from the start, at `ticks_per_quarter`, at most `u32::MAX`.
     pub fn ticks(&self, ticks_per_quarter: u32) -> u32 {
-        (self.bar * 4 + self.beat as u32) * ticks_per_quarter + self.tick
+        let ticks = self.ticks_to(self.bar, self.beat, self.tick as i64) as i128
+            * ticks_per_quarter as i128
+            / self.ticks_per_quarter as i128;
+        std::cmp::min(ticks, u32::MAX as i128) as u32
     }
 }
 
+// The right hand side is counted in the meter and the ticks of the left
+// hand side.
 impl std::ops::Add for TimeCode {
     type Output = Self;
     fn add(self, rhs: TimeCode) -> TimeCode {
-        TimeCode {
-            bar: self.bar + rhs.bar,
-            beat: self.beat + rhs.beat,
-            tick: self.tick + rhs.tick,
-        }
+        let ticks = self.ticks_to(self.bar, self.beat, self.tick as i64)
+            + self.ticks_to(rhs.bar, rhs.beat, rhs.tick as i64);
+        self.with_ticks(ticks)
     }
 }
 
 impl std::ops::Sub for TimeCode {
     type Output = Self;
     fn sub(self, rhs: TimeCode) -> TimeCode {
-        let TimeCode {
-            mut bar,
-            mut beat,
-            tick,
-        } = self;
-        while beat < rhs.beat && bar > 0 {
-            bar -= 1;
-            beat += 4;
-        }
-        TimeCode {
-            bar: bar - rhs.bar,
-            beat: beat - rhs.beat,
-            tick: tick - rhs.tick,
-        }
+        let ticks = self.ticks_to(self.bar, self.beat, self.tick as i64)
+            - self.ticks_to(rhs.bar, rhs.beat, rhs.tick as i64);
+        self.with_ticks(ticks)
     }
 }
 
@@ -90,18 +173,37 @@ pub fn create_note_part(
     create_note(offset.ticks(ticks_per_quarter), length, 0, note, 120)
 }
 
-use komp_core::Chord;
+use crate::arpeggio::Arpeggiator;
+use crate::rhythm::Rhythm;
+#[cfg(test)]
+use crate::song::{create_song_pattern, Change};
+use crate::strum::Strummer;
+#[cfg(test)]
+use komp_core::{Chord, Voicer};
+use komp_core::{NOTE_C3, NOTE_C5};
 use std::collections::BinaryHeap;
 
+/// The register of the comping chords, unless configured otherwise.
+pub const COMPING_REGISTER: (u8, u8) = (NOTE_C3, NOTE_C5);
+
+/// What the comping track plays of each chord.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Comping {
+    Chords,
+    Arpeggio(Arpeggiator),
+    Strum(Strummer),
+    Rhythm(Rhythm),
+}
+
 pub fn create_chord_part(
     ticks_per_quarter: u32,
     offset: TimeCode,
     part: u8,
-    chord: Chord,
+    notes: &[u8],
 ) -> Vec<TimedEvent> {
     let mut heap = BinaryHeap::with_capacity(10);
-    for note in chord.notes(3, 0) {
-        let (on, off) = create_note_part(ticks_per_quarter, offset, part, note);
+    for note in notes {
+        let (on, off) = create_note_part(ticks_per_quarter, offset, part, *note);
 
         heap.push(on);
         heap.push(off);
@@ -110,21 +212,58 @@ pub fn create_chord_part(
     heap.into_sorted_vec()
 }
 
-pub fn create_bar(ticks_per_quarter: u32, chord: Chord) -> Vec<TimedEvent> {
+#[cfg(test)]
+pub fn create_bar(ticks_per_quarter: u32, meter: Meter, chord: Chord) -> Vec<TimedEvent> {
+    create_bars(ticks_per_quarter, meter, &[chord])
+}
+
+/// Bars of chords, voice led from one bar to the next.
+#[cfg(test)]
+pub fn create_bars(ticks_per_quarter: u32, meter: Meter, chords: &[Chord]) -> Vec<TimedEvent> {
+    let (low, high) = COMPING_REGISTER;
+    let mut voicer = Voicer::new(low, high);
+    let progression: Vec<Vec<Change>> = chords
+        .iter()
+        .map(|chord| {
+            vec![Change {
+                beat: 0,
+                chord: Some(*chord),
+            }]
+        })
+        .collect();
+    create_song_pattern(ticks_per_quarter, meter, &progression, |chord, _| {
+        create_voiced_bar(ticks_per_quarter, meter, &voicer.voice(&chord))
+    })
+}
+
+/// A bar of a chord voiced as `notes`, on each beat.
+pub fn create_voiced_bar(ticks_per_quarter: u32, meter: Meter, notes: &[u8]) -> Vec<TimedEvent> {
     let mut timed_events = vec![];
-    for beat in 0..4 {
-        let offset = TimeCode::new(0, beat, 0);
-        timed_events.append(&mut create_chord_part(ticks_per_quarter, offset, 4, chord));
+    for beat in 0..meter.beats {
+        let offset = TimeCode::with_meter(ticks_per_quarter, meter, 0, beat, 0);
+        timed_events.append(&mut create_chord_part(
+            ticks_per_quarter,
+            offset,
+            meter.unit,
+            notes,
+        ));
     }
     timed_events
 }
 
-pub fn create_bars(ticks_per_quarter: u32, chords: &[Chord]) -> Vec<TimedEvent> {
+/// A busier bar, with chords on the off-beats too.
+pub fn create_busier_bar(ticks_per_quarter: u32, meter: Meter, notes: &[u8]) -> Vec<TimedEvent> {
+    let half_beat = meter.ticks_per_beat(ticks_per_quarter) / 2;
     let mut timed_events = vec![];
-    for (bar, chord) in chords.iter().enumerate() {
-        for beat in 0..4 {
-            let offset = TimeCode::new(bar as u32, beat, 0);
-            timed_events.append(&mut create_chord_part(ticks_per_quarter, offset, 4, *chord));
+    for beat in 0..meter.beats {
+        for tick in [0, half_beat].iter() {
+            let offset = TimeCode::with_meter(ticks_per_quarter, meter, 0, beat, *tick);
+            timed_events.append(&mut create_chord_part(
+                ticks_per_quarter,
+                offset,
+                meter.unit * 2,
+                notes,
+            ));
         }
     }
     timed_events
@@ -133,13 +272,15 @@ pub fn create_bars(ticks_per_quarter: u32, chords: &[Chord]) -> Vec<TimedEvent>
 #[cfg(test)]
 mod tests {
     use super::*;
-    use komp_core::C_KEY;
+    use komp_core::{C_KEY, F_KEY, NOTE_A3, NOTE_E3, NOTE_F3, NOTE_G3};
+
+    const C_MAJOR: [u8; 3] = [NOTE_C3, NOTE_E3, NOTE_G3];
 
     #[test]
     fn test_chord_part_4_timing() {
         let ticks_per_quarter = 96;
         let offset = TimeCode::new(1, 0, 0);
-        let notes = create_chord_part(ticks_per_quarter, offset, 4, Chord::Major(C_KEY));
+        let notes = create_chord_part(ticks_per_quarter, offset, 4, &C_MAJOR);
         let (t1, t2, t3) = (notes[0].timing, notes[1].timing, notes[2].timing);
         assert_eq!(t1, ticks_per_quarter * 4);
         assert!(t1 == t2 && t2 == t3);
@@ -153,7 +294,7 @@ mod tests {
     fn test_chord_part_4_events() {
         let ticks_per_quarter = 96;
         let offset = TimeCode::new(1, 0, 0);
-        let notes = create_chord_part(ticks_per_quarter, offset, 4, Chord::Major(C_KEY));
+        let notes = create_chord_part(ticks_per_quarter, offset, 4, &C_MAJOR);
         if let (
             Event::NoteOn { note: n1, .. },
             Event::NoteOn { note: n2, .. },
@@ -194,6 +335,128 @@ mod tests {
         assert_eq!(second_bar - one_quarter, one_three_quarters);
     }
 
+    #[test]
+    fn test_timecode_add_carries_beats() {
+        let waltz = Meter::new(3, 4);
+        let sum = TimeCode::with_meter(96, waltz, 1, 2, 0) + TimeCode::new(0, 2, 0);
+        assert_eq!((sum.bar, sum.beat), (2, 1));
+    }
+
+    #[test]
+    fn test_timecode_add_carries_ticks() {
+        let sum = TimeCode::new(0, 3, 90) + TimeCode::new(0, 0, 10);
+        assert_eq!((sum.bar, sum.beat, sum.tick), (1, 0, 4));
+        let six_eight = Meter::new(6, 8);
+        // an eighth is 48 ticks
+        let code = TimeCode::with_meter(96, six_eight, 0, 5, 100);
+        assert_eq!((code.bar, code.beat, code.tick), (1, 1, 4));
+    }
+
+    #[test]
+    fn test_timecode_sub_borrows_ticks() {
+        let difference = TimeCode::new(2, 0, 0) - TimeCode::new(0, 0, 10);
+        assert_eq!(difference.ticks(96), 8 * 96 - 10);
+        assert_eq!(
+            (difference.bar, difference.beat, difference.tick),
+            (1, 3, 86)
+        );
+    }
+
+    #[test]
+    fn test_timecode_sub_before_first_bar() {
+        let start = TimeCode::new(0, 0, 0);
+        assert_eq!(TimeCode::new(0, 1, 0) - TimeCode::new(0, 2, 0), start);
+        assert_eq!(TimeCode::new(0, 1, 0) - TimeCode::new(0, 0, 200), start);
+    }
+
+    #[test]
+    fn test_timecode_ticks_at_another_resolution() {
+        let code = TimeCode::new(1, 1, 48);
+        assert_eq!(code.ticks(480), 5 * 480 + 240);
+        let far = TimeCode::new(u32::MAX, 0, 0);
+        assert_eq!(far.ticks(96), u32::MAX);
+    }
+
+    #[test]
+    fn test_timecode_ticks_in_meters() {
+        let ticks_per_quarter = 96;
+        let bar = |meter| {
+            TimeCode::with_meter(ticks_per_quarter, meter, 1, 0, 0).ticks(ticks_per_quarter)
+        };
+        assert_eq!(bar(Meter::new(3, 4)), 3 * 96);
+        assert_eq!(bar(Meter::new(5, 4)), 5 * 96);
+        assert_eq!(bar(Meter::new(6, 8)), 3 * 96);
+        assert_eq!(bar(Meter::new(7, 8)), 7 * 48);
+    }
+
+    #[test]
+    fn test_meter_from_str() {
+        assert_eq!("7/8".parse::<Meter>(), Ok(Meter::new(7, 8)));
+        assert_eq!(Meter::new(6, 8).to_string(), "6/8");
+        assert!("7/7".parse::<Meter>().is_err());
+        assert!("0/4".parse::<Meter>().is_err());
+        assert!("4".parse::<Meter>().is_err());
+    }
+
+    #[test]
+    fn test_bar_in_meters() {
+        let ticks_per_quarter = 96;
+        let chord = Chord::Major(C_KEY);
+        let note_ons = |meter| {
+            let timed_events = create_bar(ticks_per_quarter, meter, chord);
+            let mut timings: Vec<u32> = timed_events
+                .iter()
+                .filter(|te| matches!(te.event, Event::NoteOn { .. }))
+                .map(|te| te.timing)
+                .collect();
+            timings.dedup();
+            timings
+        };
+        assert_eq!(note_ons(Meter::new(3, 4)), vec![0, 96, 192]);
+        assert_eq!(note_ons(Meter::new(6, 8)), vec![0, 48, 96, 144, 192, 240]);
+        // the notes of an eighth are shorter than those of a quarter
+        let timed_events = create_bar(ticks_per_quarter, Meter::new(7, 8), chord);
+        assert_eq!(timed_events.last().unwrap().timing, 6 * 48 + 36);
+    }
+
+    #[test]
+    fn test_bars_are_voice_led() {
+        let ticks_per_quarter = 96;
+        let progression = [Chord::Major(C_KEY), Chord::Major(F_KEY)];
+        let timed_events = create_bars(ticks_per_quarter, COMMON_TIME, &progression);
+        let notes_at = |timing| {
+            let mut notes: Vec<u8> = timed_events
+                .iter()
+                .filter(|te| te.timing == timing)
+                .filter_map(|te| match te.event {
+                    Event::NoteOn { note, .. } => Some(note),
+                    _ => None,
+                })
+                .collect();
+            notes.sort();
+            notes
+        };
+        assert_eq!(notes_at(0), C_MAJOR);
+        // the F major chord keeps the C
+        assert_eq!(notes_at(4 * 96), vec![NOTE_C3, NOTE_F3, NOTE_A3]);
+    }
+
+    #[test]
+    fn test_busier_bar() {
+        let ticks_per_quarter = 96;
+        let timed_events = create_busier_bar(ticks_per_quarter, Meter::new(3, 4), &C_MAJOR);
+        let mut note_ons: Vec<u32> = timed_events
+            .iter()
+            .filter(|te| matches!(te.event, Event::NoteOn { .. }))
+            .map(|te| te.timing)
+            .collect();
+        assert_eq!(note_ons.len(), 6 * 3);
+        note_ons.dedup();
+        assert_eq!(note_ons, vec![0, 48, 96, 144, 192, 240]);
+        // the notes end before the next chord
+        assert_eq!(timed_events.last().unwrap().timing, 240 + 36);
+    }
+
     #[test]
     fn test_timecode_ticks_zero() {
         let ticks_per_quarter = 96;
@@ -10,7 +10,38 @@ pub enum Event {
     NoteOff { channel: u8, note: u8, velocity: u8 },
 }
 
+impl Event {
+    pub fn channel(&self) -> u8 {
+        match *self {
+            Event::NoteOn { channel, .. } | Event::NoteOff { channel, .. } => channel,
+        }
+    }
+
+    /// The event with the velocity of a note-on changed, staying within
+    /// 1 to 127 so that it is not taken for a note-off.
+    pub fn map_velocity(self, change: impl FnOnce(f64) -> f64) -> Event {
+        match self {
+            Event::NoteOn {
+                channel,
+                note,
+                velocity,
+            } if velocity > 0 => Event::NoteOn {
+                channel,
+                note,
+                velocity: change(velocity as f64).round().clamp(1.0, 127.0) as u8,
+            },
+            _ => self,
+        }
+    }
+}
+
+use crate::drums::DRUM_CHANNEL;
+use crate::groove::Groove;
+use crate::humanize::{note_places, Humanizer, NotePlace};
+use crate::pattern::{Meter, TimeCode, COMMON_TIME};
+use crate::tempo::{bpm, us_per_quarter_from_bpm};
 use komp_core::*;
+use std::collections::VecDeque;
 pub const NS_PER_MS: u64 = 1_000_000;
 pub const NS_PER_US: u64 = 1_000;
 
@@ -18,6 +49,13 @@ fn ticks_to_time(offset: u64, ticks: u32, us_per_quarter: u32, ticks_per_quarter
     offset + (NS_PER_US * ticks as u64 * us_per_quarter as u64 / ticks_per_quarter as u64)
 }
 
+type Message = (u64, Vec<u8>);
+
+// How the event at an index of the pattern is performed in a pass through
+// the pattern (counted from the pattern start): the shift of its time, in
+// nanoseconds, and the event to send.
+type Performance<'a> = &'a dyn Fn(usize, i64) -> (i64, Event);
+
 fn schedule_timeslice(
     pattern_start: u64,
     now: u64,
@@ -27,27 +65,85 @@ fn schedule_timeslice(
     key: Key,
     us_per_quarter: u32,
     ticks_per_quarter: u32,
-) -> coremidi::PacketBuffer {
-    let mut packet_buf = coremidi::PacketBuffer::with_capacity(512);
-    for te in timed_events.iter() {
-        let mut event_time =
-            ticks_to_time(pattern_start, te.timing, us_per_quarter, ticks_per_quarter);
-        if now + timeslice >= pattern_start + pattern_length {
-            while event_time < now {
-                event_time += pattern_length;
+    perform: Performance,
+) -> Vec<Message> {
+    let mut messages = vec![];
+    let (start, length) = (pattern_start as i64, pattern_length as i64);
+    let (now, end) = (now as i64, (now + timeslice) as i64);
+    // shifted events may end up in the pass before or after their own
+    let first_pass = (now - start).div_euclid(length) - 1;
+    let last_pass = (end - start).div_euclid(length) + 1;
+    for (index, te) in timed_events.iter().enumerate() {
+        let time = ticks_to_time(0, te.timing, us_per_quarter, ticks_per_quarter) as i64;
+        for pass in first_pass..=last_pass {
+            let (shift, event) = perform(index, pass);
+            let event_time = start + pass * length + time + shift;
+            if event_time < now || event_time >= end {
+                continue;
             }
+            let data = midi_encode_event(&event, key);
+            messages.push((event_time as u64, data.to_vec()));
         }
-        if event_time < now || event_time >= now + timeslice {
-            continue;
-        }
-        let data = midi_encode_event(&te.event, key);
-        packet_buf.push_data(event_time, &data);
     }
 
+    messages
+}
+
+fn packet_buffer(mut messages: Vec<Message>) -> coremidi::PacketBuffer {
+    // CoreMIDI expects the packets in a list to be in time order,
+    // the sort is stable so simultaneous messages keep their order
+    messages.sort_by_key(|(time, _)| *time);
+    let mut packet_buf = coremidi::PacketBuffer::with_capacity(512);
+    for (time, data) in messages.iter() {
+        packet_buf.push_data(*time, data);
+    }
     packet_buf
 }
 
+const PROGRAM_CHANGE: u8 = 0xC0;
+const SONG_POSITION_POINTER: u8 = 0xF2;
+const TIMING_CLOCK: u8 = 0xF8;
+const START: u8 = 0xFA;
+const CONTINUE: u8 = 0xFB;
+const STOP: u8 = 0xFC;
+const CLOCKS_PER_QUARTER: u64 = 24;
+// the pointer has 14 bits, further positions are sent as the last one
+const MAX_SONG_POSITION: u32 = 0x3fff;
+
+// Each clock time is calculated from the origin, rather than by adding
+// up the clock period, so that rounding errors do not accumulate.
+fn clock_messages(origin: u64, now: u64, timeslice: u64, us_per_quarter: u32) -> Vec<Message> {
+    let ns_per_quarter = us_per_quarter as u64 * NS_PER_US;
+    let clock_time = |clock: u64| origin + clock * ns_per_quarter / CLOCKS_PER_QUARTER;
+    // the first clock at or after now
+    let elapsed = now - origin;
+    let mut clock = (elapsed * CLOCKS_PER_QUARTER).div_ceil(ns_per_quarter);
+    let mut messages = vec![];
+    while clock_time(clock) < now + timeslice {
+        messages.push((clock_time(clock), vec![TIMING_CLOCK]));
+        clock += 1;
+    }
+    messages
+}
+
+fn midi_song_position(sixteenths: u32) -> [u8; 3] {
+    let sixteenths = std::cmp::min(sixteenths, MAX_SONG_POSITION);
+    [
+        SONG_POSITION_POINTER,
+        (sixteenths & 0x7f) as u8,
+        ((sixteenths >> 7) & 0x7f) as u8,
+    ]
+}
+
+// The drums are not transposed, their notes are instruments.
 fn midi_encode_event(event: &Event, key: Key) -> [u8; 3] {
+    let transpose = |channel: u8, note: u8| {
+        if channel == DRUM_CHANNEL {
+            note & 0x7f
+        } else {
+            (key.0 + note) & 0x7f
+        }
+    };
     match event {
         Event::NoteOn {
             channel,
@@ -55,7 +151,7 @@ fn midi_encode_event(event: &Event, key: Key) -> [u8; 3] {
             velocity,
         } => [
             0x90 | (channel & 0x0f),
-            (key.0 + note) & 0x7f,
+            transpose(*channel, *note),
             velocity & 0x7f,
         ],
         Event::NoteOff {
@@ -64,12 +160,22 @@ fn midi_encode_event(event: &Event, key: Key) -> [u8; 3] {
             velocity,
         } => [
             0x80 | (channel & 0x0f),
-            (key.0 + note) & 0x7f,
+            transpose(*channel, *note),
             velocity & 0x7f,
         ],
     }
 }
 
+/// Program changes, to pick the instruments of the channels, as
+/// (channel, program).
+pub fn program_changes(programs: &[(u8, u8)]) -> coremidi::PacketBuffer {
+    let mut packet_buf = coremidi::PacketBuffer::with_capacity(512);
+    for &(channel, program) in programs {
+        packet_buf.push_data(0, &[PROGRAM_CHANGE | (channel & 0x0f), program & 0x7f]);
+    }
+    packet_buf
+}
+
 pub fn mute_playing(playing: &crate::Playing) -> coremidi::PacketBuffer {
     let mut packet_buf = coremidi::PacketBuffer::with_capacity(512);
     for &(channel, note) in playing {
@@ -84,14 +190,37 @@ pub fn mute_playing(playing: &crate::Playing) -> coremidi::PacketBuffer {
     packet_buf
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Transport {
+    Stopped,
+    // from a song position, in sixteenths
+    Starting(u32),
+    Running,
+}
+
 pub struct Scheduler {
     pattern_start: u64,
     slice_length: u64,
     scheduling_deadline_margin: u64,
     timed_events: Vec<TimedEvent>,
     pattern_length: u64,
+    pattern_ticks: u32,
     us_per_quarter: u32,
     ticks_per_quarter: u32,
+    meter: Meter,
+    groove: Groove,
+    humanizer: Humanizer,
+    note_places: Vec<NotePlace>,
+    // the number of passes through the pattern so far
+    pass: u64,
+    // the ticks played before the pattern start, through any pattern changes
+    played_ticks: u64,
+    next_pattern: Option<(Vec<TimedEvent>, u32)>,
+    velocity_scale: f64,
+    send_clock: bool,
+    transport: Transport,
+    // tempo changes waiting for the coming beats, one per beat
+    tempo_changes: VecDeque<u32>,
 }
 
 impl Scheduler {
@@ -104,17 +233,56 @@ impl Scheduler {
         us_per_quarter: u32,
         ticks_per_quarter: u32,
     ) -> Scheduler {
+        let note_places = note_places(&timed_events);
         Scheduler {
             pattern_start,
             slice_length,
             scheduling_deadline_margin,
             timed_events,
             pattern_length,
+            pattern_ticks: (pattern_length as u128 * ticks_per_quarter as u128
+                / (us_per_quarter as u64 * NS_PER_US) as u128) as u32,
             us_per_quarter,
             ticks_per_quarter,
+            meter: COMMON_TIME,
+            groove: Groove::straight(),
+            humanizer: Humanizer::new(0),
+            note_places,
+            pass: 0,
+            played_ticks: 0,
+            next_pattern: None,
+            velocity_scale: 1.0,
+            send_clock: false,
+            transport: Transport::Starting(0),
+            tempo_changes: VecDeque::new(),
         }
     }
 
+    /// Also send MIDI beat clock, preceded by Start, so that other
+    /// devices can follow the tempo of the accompaniment.
+    pub fn with_clock(mut self, send_clock: bool) -> Scheduler {
+        self.send_clock = send_clock;
+        self
+    }
+
+    /// Count bars and beats in `meter`, rather than in 4/4.
+    pub fn with_meter(mut self, meter: Meter) -> Scheduler {
+        self.meter = meter;
+        self
+    }
+
+    /// Shift the timing and velocity of the events by a groove.
+    pub fn with_groove(mut self, groove: Groove) -> Scheduler {
+        self.groove = groove;
+        self
+    }
+
+    /// Humanize the timing and velocity of the events.
+    pub fn with_humanizer(mut self, humanizer: Humanizer) -> Scheduler {
+        self.humanizer = humanizer;
+        self
+    }
+
     pub fn schedule_slice(
         &mut self,
         now: u64,
@@ -123,33 +291,313 @@ impl Scheduler {
     ) -> (i64, coremidi::PacketBuffer) {
         *slice_start = std::cmp::max(*slice_start, self.pattern_start);
 
-        let packet_buf = schedule_timeslice(
+        let slice_end = *slice_start + self.slice_length;
+        let mut messages = vec![];
+        if self.send_clock && self.transport != Transport::Stopped {
+            match self.transport {
+                Transport::Starting(0) => messages.push((*slice_start, vec![START])),
+                Transport::Starting(sixteenths) => {
+                    messages.push((*slice_start, midi_song_position(sixteenths).to_vec()));
+                    messages.push((*slice_start, vec![CONTINUE]));
+                }
+                _ => (),
+            }
+            self.transport = Transport::Running;
+        }
+        // tempo and pattern changes split the slice where they take effect,
+        // at the next beat and at the end of the pattern
+        let mut from = *slice_start;
+        let mut after = from;
+        loop {
+            let tempo_change = self.tempo_changes.front().map(|_| self.next_beat(after));
+            let pattern_change = self
+                .next_pattern
+                .as_ref()
+                .map(|_| self.next_pattern_start(from));
+            let at = match (tempo_change, pattern_change) {
+                (Some(beat), Some(end)) => std::cmp::min(beat, end),
+                (Some(at), None) | (None, Some(at)) => at,
+                (None, None) => break,
+            };
+            if at >= slice_end {
+                break;
+            }
+            messages.append(&mut self.slice_messages(from, at - from, key));
+            if tempo_change == Some(at) {
+                let us_per_quarter = self.tempo_changes.pop_front().unwrap();
+                self.retempo(at, us_per_quarter);
+                // one change per beat
+                after = at + 1;
+            }
+            if pattern_change == Some(at) {
+                self.change_pattern(at);
+            }
+            from = at;
+        }
+        messages.append(&mut self.slice_messages(from, slice_end - from, key));
+        let packet_buf = packet_buffer(messages);
+
+        *slice_start += self.slice_length;
+        let next_slice_due = *slice_start;
+        if next_slice_due >= self.pattern_start + self.pattern_length {
+            self.pattern_start += self.pattern_length;
+            self.pass += 1;
+            self.played_ticks += self.pattern_ticks as u64;
+        }
+        let sleep_time: i64 = ((next_slice_due - self.scheduling_deadline_margin) - (now)) as i64;
+
+        (sleep_time, packet_buf)
+    }
+
+    // the time of the first pattern start at or after `at`
+    fn next_pattern_start(&self, at: u64) -> u64 {
+        let passes = (at - self.pattern_start).div_ceil(self.pattern_length);
+        self.pattern_start + passes * self.pattern_length
+    }
+
+    /// Play `timed_events`, a pattern of `pattern_ticks`, from the end of
+    /// the pattern that is playing.
+    pub fn queue_pattern(&mut self, timed_events: Vec<TimedEvent>, pattern_ticks: u32) {
+        self.next_pattern = Some((timed_events, pattern_ticks));
+    }
+
+    /// Play `timed_events` instead of the pattern that is playing, from the
+    /// next slice on, and drop any queued pattern. The new pattern keeps
+    /// the length of the old one.
+    pub fn set_pattern(&mut self, timed_events: Vec<TimedEvent>) {
+        self.next_pattern = None;
+        self.note_places = note_places(&timed_events);
+        self.timed_events = timed_events;
+    }
+
+    fn change_pattern(&mut self, at: u64) {
+        if let Some((timed_events, pattern_ticks)) = self.next_pattern.take() {
+            let passes = (at - self.pattern_start) / self.pattern_length;
+            self.pass += passes;
+            self.played_ticks += passes * self.pattern_ticks as u64;
+            self.pattern_start = at;
+            self.note_places = note_places(&timed_events);
+            self.timed_events = timed_events;
+            self.pattern_ticks = pattern_ticks;
+            self.pattern_length = ticks_to_time(
+                0,
+                pattern_ticks,
+                self.us_per_quarter,
+                self.ticks_per_quarter,
+            );
+        }
+    }
+
+    /// Scale the velocity of the notes, to follow the dynamics of the player.
+    pub fn set_velocity_scale(&mut self, velocity_scale: f64) {
+        self.velocity_scale = velocity_scale;
+    }
+
+    fn slice_messages(&self, now: u64, timeslice: u64, key: Key) -> Vec<Message> {
+        let mut messages = vec![];
+        if self.send_clock && self.transport == Transport::Running {
+            messages = clock_messages(self.pattern_start, now, timeslice, self.us_per_quarter);
+        }
+        let ns_per_tick =
+            (self.us_per_quarter as u64 * NS_PER_US) as f64 / self.ticks_per_quarter as f64;
+        let perform = |index: usize, pass: i64| {
+            let te = &self.timed_events[index];
+            // a note-off is shifted as much as its note-on, so that a late
+            // off-beat cannot end before it starts
+            let note_on = &self.timed_events[self.note_places[index].note_on];
+            let groove_shift =
+                self.groove.shift(note_on.timing, self.ticks_per_quarter) * ns_per_tick;
+            let event = self
+                .groove
+                .accent(&te.event, te.timing, self.ticks_per_quarter);
+            let (shift, event) = self.humanizer.perform(
+                self.note_places[index],
+                event,
+                (self.pass as i64 + pass) as u64,
+            );
+            let event = event.map_velocity(|velocity| velocity * self.velocity_scale);
+            (groove_shift.round() as i64 + shift, event)
+        };
+        messages.append(&mut schedule_timeslice(
             self.pattern_start,
-            *slice_start,
-            self.slice_length,
+            now,
+            timeslice,
             &self.timed_events,
             self.pattern_length,
             key,
             self.us_per_quarter,
             self.ticks_per_quarter,
+            &perform,
+        ));
+        messages
+    }
+
+    // the time of the first beat of the meter at or after `at`
+    fn next_beat(&self, at: u64) -> u64 {
+        let ns_per_beat = self.us_per_quarter as u64 * NS_PER_US * 4 / self.meter.unit as u64;
+        let beats = (at - self.pattern_start).div_ceil(ns_per_beat);
+        self.pattern_start + beats * ns_per_beat
+    }
+
+    /// Change the tempo on the next beat that is not yet scheduled.
+    pub fn set_tempo(&mut self, us_per_quarter: u32) {
+        self.tempo_changes.clear();
+        self.tempo_changes.push_back(us_per_quarter);
+    }
+
+    /// Gradually change the tempo during the coming `bars`, with the
+    /// tempo (in beats per minute) changing linearly from beat to beat.
+    pub fn ramp_tempo(&mut self, us_per_quarter: u32, bars: u32) {
+        let from = self
+            .tempo_changes
+            .back()
+            .map_or(self.us_per_quarter, |t| *t);
+        let (from_bpm, to_bpm) = (bpm(from), bpm(us_per_quarter));
+        let beats = std::cmp::max(1, bars * self.meter.beats as u32);
+        self.tempo_changes.clear();
+        for beat in 1..=beats {
+            let beat_bpm = from_bpm + (to_bpm - from_bpm) * beat as f64 / beats as f64;
+            self.tempo_changes
+                .push_back(us_per_quarter_from_bpm(beat_bpm));
+        }
+    }
+
+    pub fn us_per_quarter(&self) -> u32 {
+        self.us_per_quarter
+    }
+
+    /// The length of the pattern that is playing, in ticks.
+    pub fn pattern_ticks(&self) -> u32 {
+        self.pattern_ticks
+    }
+
+    /// Restart the pattern at `position` (counted from the start of the
+    /// song) from the time `at`. Clock followers are stopped right away,
+    /// and sent the new Song Position Pointer and Continue on the next slice.
+    /// A position past the last one a Song Position Pointer can tell is
+    /// refused.
+    pub fn jump_to(
+        &mut self,
+        at: u64,
+        slice_start: &mut u64,
+        position: TimeCode,
+    ) -> Result<coremidi::PacketBuffer, String> {
+        let ticks = position.ticks(self.ticks_per_quarter);
+        let sixteenths = ticks as u64 * 4 / self.ticks_per_quarter as u64;
+        if sixteenths > MAX_SONG_POSITION as u64 {
+            return Err(format!(
+                "past the {} sixteenths a Song Position Pointer can tell",
+                MAX_SONG_POSITION + 1
+            ));
+        }
+        self.pattern_start = at
+            - ticks_to_time(
+                0,
+                ticks % self.pattern_ticks,
+                self.us_per_quarter,
+                self.ticks_per_quarter,
+            );
+        self.played_ticks = (ticks - ticks % self.pattern_ticks) as u64;
+        *slice_start = at;
+        let packet_buf = self.stop();
+        self.transport = Transport::Starting(sixteenths as u32);
+        Ok(packet_buf)
+    }
+
+    /// Lock the pattern to an external clock, where the quarter `beat`
+    /// (counted from the start of the song) started at `beat_time`.
+    /// Small phase errors are corrected by slightly adjusting the tempo of
+    /// the coming quarter, larger ones by relocating the pattern. Returns
+    /// true when relocated, as anything already scheduled is then invalid.
+    pub fn follow(
+        &mut self,
+        now: u64,
+        slice_start: &mut u64,
+        beat: u64,
+        beat_time: u64,
+        us_per_quarter: u32,
+    ) -> bool {
+        let ticks_per_quarter = self.ticks_per_quarter as f64;
+        let pattern_ticks = self.pattern_ticks as f64;
+        let ns_per_tick = (us_per_quarter as u64 * NS_PER_US) as f64 / ticks_per_quarter;
+        let beat_ticks = (beat * self.ticks_per_quarter as u64 % self.pattern_ticks as u64) as f64;
+        // the position of the master in the pattern at the given time, in ticks
+        let master = |at: u64| {
+            (beat_ticks + (at as f64 - beat_time as f64) / ns_per_tick).rem_euclid(pattern_ticks)
+        };
+
+        let at = std::cmp::max(*slice_start, self.pattern_start);
+        let ours = (at - self.pattern_start) as f64 * ticks_per_quarter
+            / (self.us_per_quarter as u64 * NS_PER_US) as f64;
+        let mut error = (master(at) - ours).rem_euclid(pattern_ticks);
+        if error > pattern_ticks / 2.0 {
+            error -= pattern_ticks;
+        }
+
+        if *slice_start < now || error.abs() > ticks_per_quarter / 8.0 {
+            self.us_per_quarter = us_per_quarter;
+            self.pattern_length = ticks_to_time(
+                0,
+                self.pattern_ticks,
+                us_per_quarter,
+                self.ticks_per_quarter,
+            );
+            self.pattern_start = now - (master(now) * ns_per_tick) as u64;
+            let beat_ticks = beat * self.ticks_per_quarter as u64;
+            self.played_ticks = beat_ticks - beat_ticks % self.pattern_ticks as u64;
+            *slice_start = now;
+            return true;
+        }
+        // catch up with the master during the coming quarter
+        let correction = (ticks_per_quarter / (ticks_per_quarter + error)).clamp(0.95, 1.05);
+        self.retempo(at, (us_per_quarter as f64 * correction).round() as u32);
+        false
+    }
+
+    // Change the tempo from the time `at`, keeping the position in the pattern.
+    fn retempo(&mut self, at: u64, us_per_quarter: u32) {
+        let elapsed = (at - self.pattern_start) as u128 * us_per_quarter as u128
+            / self.us_per_quarter as u128;
+        self.pattern_start = at - elapsed as u64;
+        self.us_per_quarter = us_per_quarter;
+        self.pattern_length = ticks_to_time(
+            0,
+            self.pattern_ticks,
+            us_per_quarter,
+            self.ticks_per_quarter,
         );
+    }
 
-        *slice_start += self.slice_length;
-        let next_slice_due = *slice_start;
-        if next_slice_due >= self.pattern_start + self.pattern_length {
-            self.pattern_start += self.pattern_length;
+    /// Stop the clock, returning the Stop message for any clock followers.
+    pub fn stop(&mut self) -> coremidi::PacketBuffer {
+        let mut messages = vec![];
+        if self.send_clock && self.transport == Transport::Running {
+            messages.push((0, vec![STOP]));
         }
-        let sleep_time: i64 = ((next_slice_due - self.scheduling_deadline_margin) - (now)) as i64;
+        self.transport = Transport::Stopped;
+        packet_buffer(messages)
+    }
 
-        (sleep_time, packet_buf)
+    /// The position of the time `at` from the start of the song, in ticks,
+    /// through the tempo and pattern changes so far.
+    pub fn position(&self, at: u64) -> u64 {
+        // slices are scheduled ahead, the pattern may have started again
+        // after `at`
+        let elapsed = at as i128 - self.pattern_start as i128;
+        let ticks = elapsed * self.ticks_per_quarter as i128
+            / (self.us_per_quarter as u64 * NS_PER_US) as i128;
+        (self.played_ticks as i128 + ticks).max(0) as u64
     }
 
+    #[cfg(test)]
     pub fn pattern_start(&self) -> u64 {
         self.pattern_start
     }
+    #[cfg(test)]
     pub fn pattern_length(&self) -> u64 {
         self.pattern_length
     }
+    #[cfg(test)]
     pub fn slice_length(&self) -> u64 {
         self.slice_length
     }
@@ -158,6 +606,7 @@ impl Scheduler {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::groove::GrooveStep;
     use crate::pattern::*;
     use crate::Playing;
     use crate::*;
@@ -212,6 +661,22 @@ mod tests {
         assert_eq!(data, [0x8c, NOTE_E3, velocity]);
     }
 
+    #[test]
+    fn test_midi_encoding_drums_are_not_transposed() {
+        let event = Event::NoteOn {
+            channel: DRUM_CHANNEL,
+            note: 38,
+            velocity: 100,
+        };
+        assert_eq!(midi_encode_event(&event, F_KEY), [0x99, 38, 100]);
+        let event = Event::NoteOff {
+            channel: 0,
+            note: NOTE_C3,
+            velocity: 64,
+        };
+        assert_eq!(midi_encode_event(&event, F_KEY), [0x80, NOTE_F3, 64]);
+    }
+
     #[test]
     fn test_note_accumulation() {
         let pattern_length = 4_000 * NS_PER_MS;
@@ -224,8 +689,7 @@ mod tests {
             (0u8, NOTE_E3),
             (0u8, NOTE_G3),
             (0u8, NOTE_F3),
-            (0u8, NOTE_A3),
-            (0u8, NOTE_C4)
+            (0u8, NOTE_A3)
         ];
         let mut slice_start = now;
 
@@ -248,7 +712,7 @@ mod tests {
         let ticks_per_quarter = 96;
         let us_per_quarter = 500_000;
         let progression = [Chord::Major(C_KEY), Chord::Major(F_KEY)];
-        let timed_events = create_bars(ticks_per_quarter, &progression);
+        let timed_events = create_bars(ticks_per_quarter, COMMON_TIME, &progression);
         // two bars at this tempo is exactly 4 seconds
         let pattern_length = 4_000 * NS_PER_MS;
         let scheduling_deadline_margin = 50 * NS_PER_MS;
@@ -320,20 +784,24 @@ mod tests {
         assert_eq!(slice_start, scheduler.pattern_start());
         assert_eq!(playing, hashset![]);
 
-        let f_major = vec![(0, NOTE_F3), (0, NOTE_A3), (0, NOTE_C4)];
+        let f_major = vec![(0, NOTE_C3), (0, NOTE_F3), (0, NOTE_A3)];
         let c_major = vec![(0, NOTE_C3), (0, NOTE_E3), (0, NOTE_G3)];
         assert!(played.contains(&vec![]));
         assert!(played.contains(&c_major));
         assert!(played.contains(&f_major));
     }
 
+    fn as_written(timed_events: &[TimedEvent]) -> impl Fn(usize, i64) -> (i64, Event) + '_ {
+        move |index, _| (0, timed_events[index].event)
+    }
+
     #[test]
     fn test_continual_scheduling() {
         let pattern_start = 200_000_000_000_000;
         let ticks_per_quarter = 96;
         let us_per_quarter = 500_000;
         let progression = [Chord::Major(C_KEY), Chord::Major(F_KEY)];
-        let timed_events = create_bars(ticks_per_quarter, &progression);
+        let timed_events = create_bars(ticks_per_quarter, COMMON_TIME, &progression);
         let slice_length = 200 * NS_PER_MS;
         // two bars at this tempo is exactly 4 seconds
         let pattern_length = 4_000 * NS_PER_MS;
@@ -346,7 +814,7 @@ mod tests {
         let wake_up_jitter = [-10_000_123, 20_123_234, -30_000_123, 10_456_234, 70_000_001];
         let mut slices = 0;
         while slice_start + slice_length <= pattern_start + 2 * pattern_length {
-            let packet_buf = schedule_timeslice(
+            let packet_buf = packet_buffer(schedule_timeslice(
                 pattern_start,
                 slice_start,
                 slice_length,
@@ -355,7 +823,8 @@ mod tests {
                 C_KEY,
                 us_per_quarter,
                 ticks_per_quarter,
-            );
+                &as_written(&timed_events),
+            ));
 
             for packet in packet_buf.iter() {
                 for chunk in packet.data().chunks(3) {
@@ -377,7 +846,7 @@ mod tests {
         assert!(slice_start == pattern_start + 2 * pattern_length);
         assert_eq!(playing, hashset![]);
 
-        let f_major = vec![(0, NOTE_F3), (0, NOTE_A3), (0, NOTE_C4)];
+        let f_major = vec![(0, NOTE_C3), (0, NOTE_F3), (0, NOTE_A3)];
         let c_major = vec![(0, NOTE_C3), (0, NOTE_E3), (0, NOTE_G3)];
         assert!(played.contains(&vec![]));
         println!("{:?}", played);
@@ -400,11 +869,11 @@ mod tests {
         let ticks_per_quarter = 96;
         let us_per_quarter = 500_000;
         let progression = [Chord::Major(C_KEY), Chord::Major(F_KEY)];
-        let timed_events = create_bars(ticks_per_quarter, &progression);
+        let timed_events = create_bars(ticks_per_quarter, COMMON_TIME, &progression);
         let two_hundred_and_fifty_ms = 250 * NS_PER_MS;
         // two bars at this tempo is exactly 4 seconds
         let pattern_length = 4_000 * NS_PER_MS;
-        schedule_timeslice(
+        let messages = schedule_timeslice(
             pattern_start,
             now,
             two_hundred_and_fifty_ms,
@@ -413,7 +882,9 @@ mod tests {
             C_KEY,
             us_per_quarter,
             ticks_per_quarter,
-        )
+            &as_written(&timed_events),
+        );
+        packet_buffer(messages)
     }
 
     fn verify_playing(
@@ -438,9 +909,10 @@ mod tests {
         let packet_buf = package_pattern_timeslice(pattern_start, now);
 
         // pretend that a C Major chord is playing in octave 3
-        // afterwards the currently playing notes should be a F Major chord
+        // afterwards the currently playing notes should be a F Major chord,
+        // voice led from the C Major chord
         let playing = hashset![(0, NOTE_C3), (0, NOTE_E3), (0, NOTE_G3)];
-        let afterwards = hashset![(0, NOTE_F3), (0, NOTE_A3), (0, NOTE_C4)];
+        let afterwards = hashset![(0, NOTE_C3), (0, NOTE_F3), (0, NOTE_A3)];
 
         verify_playing(&packet_buf, playing, afterwards);
     }
@@ -470,7 +942,7 @@ mod tests {
 
         // pretend that a F Major chord is playing in octave 3
         // afterwards the currently playing notes should be a C Major chord
-        let playing = hashset![(0, NOTE_F3), (0, NOTE_A3), (0, NOTE_C4)];
+        let playing = hashset![(0, NOTE_C3), (0, NOTE_F3), (0, NOTE_A3)];
         let afterwards = hashset![(0, NOTE_C3), (0, NOTE_E3), (0, NOTE_G3)];
 
         verify_playing(&packet_buf, playing, afterwards);
@@ -499,7 +971,7 @@ mod tests {
         ticks_per_quarter: u32,
     ) -> coremidi::PacketBuffer {
         let one_bar = us_per_quarter as u64 * 4 * NS_PER_US;
-        schedule_timeslice(
+        let messages = schedule_timeslice(
             offset,
             offset,
             one_bar,
@@ -508,14 +980,16 @@ mod tests {
             key,
             us_per_quarter,
             ticks_per_quarter,
-        )
+            &as_written(&timed_events),
+        );
+        packet_buffer(messages)
     }
 
     fn create_packets(
         ticks_per_quarter: u32,
         us_per_quarter: u32,
     ) -> (u64, coremidi::PacketBuffer) {
-        let timed_events = create_bar(ticks_per_quarter, Chord::Major(C_KEY));
+        let timed_events = create_bar(ticks_per_quarter, COMMON_TIME, Chord::Major(C_KEY));
         let timestamp = crate::now();
         let packet_buf = schedule_bar(
             timestamp,
@@ -584,4 +1058,615 @@ mod tests {
 
         assert_timings(packet_buf, timestamp, us_per_quarter)
     }
+
+    fn extract_clock_timings(packet_buf: &coremidi::PacketBuffer) -> Vec<u64> {
+        let mut timings = vec![];
+        for packet in packet_buf.iter() {
+            for message in crate::midi_messages(packet.data()) {
+                if message == [TIMING_CLOCK] {
+                    timings.push(packet.timestamp());
+                }
+            }
+        }
+        timings
+    }
+
+    #[test]
+    fn test_clock_messages_per_quarter() {
+        let origin = 200_000_000_000_000;
+        let us_per_quarter = 500_000;
+        let one_quarter = us_per_quarter as u64 * NS_PER_US;
+        let messages = clock_messages(origin, origin, one_quarter, us_per_quarter);
+        assert_eq!(messages.len(), 24);
+        assert_eq!(messages[0], (origin, vec![TIMING_CLOCK]));
+        assert_eq!(messages[1].0 - origin, one_quarter / 24);
+    }
+
+    #[test]
+    fn test_clock_messages_across_slices() {
+        let origin = 200_000_000_000_000;
+        // a clock period that is not a whole number of nanoseconds
+        let us_per_quarter = 500_001;
+        let slice_length = 7 * NS_PER_MS;
+        let one_bar = 4 * us_per_quarter as u64 * NS_PER_US;
+        let mut timings = vec![];
+        let mut now = origin;
+        while now < origin + one_bar {
+            for (time, _) in clock_messages(origin, now, slice_length, us_per_quarter) {
+                timings.push(time);
+            }
+            now += slice_length;
+        }
+        timings.retain(|time| *time < origin + one_bar);
+        assert_eq!(timings.len(), 96);
+        timings.dedup();
+        assert_eq!(timings.len(), 96);
+    }
+
+    #[test]
+    fn test_scheduler_without_clock() {
+        let mut scheduler = create_scheduler();
+        let mut slice_start = scheduler.pattern_start();
+        let now = slice_start;
+        let (_, packet_buf) = scheduler.schedule_slice(now, &mut slice_start, C_KEY);
+        assert_eq!(extract_clock_timings(&packet_buf).len(), 0);
+        assert_eq!(scheduler.stop().len(), 0);
+    }
+
+    #[test]
+    fn test_scheduler_clock_start_and_stop() {
+        let mut scheduler = create_scheduler().with_clock(true);
+        let initial_start = scheduler.pattern_start();
+        let mut slice_start = initial_start;
+        let mut now = initial_start;
+        let mut clocks = vec![];
+        let mut first_messages = vec![];
+
+        while slice_start + scheduler.slice_length() <= initial_start + scheduler.pattern_length() {
+            let (sleep_time, packet_buf) = scheduler.schedule_slice(now, &mut slice_start, C_KEY);
+            if first_messages.is_empty() {
+                for packet in packet_buf.iter() {
+                    for message in crate::midi_messages(packet.data()) {
+                        first_messages.push(message.to_vec());
+                    }
+                }
+            }
+            clocks.append(&mut extract_clock_timings(&packet_buf));
+            now = now.wrapping_add(sleep_time as u64);
+        }
+
+        assert_eq!(first_messages[0], vec![START]);
+        assert_eq!(first_messages[1], vec![TIMING_CLOCK]);
+        // 24 clocks per quarter during two bars
+        assert_eq!(clocks.len(), 2 * 4 * 24);
+        assert_eq!(clocks[0], initial_start);
+        assert_eq!(clocks[24], initial_start + 500 * NS_PER_MS);
+
+        let packet_buf = scheduler.stop();
+        let stop: Vec<&[u8]> = packet_buf.iter().map(|packet| packet.data()).collect();
+        assert_eq!(stop, vec![&[STOP][..]]);
+        // stopping again sends nothing
+        assert_eq!(scheduler.stop().len(), 0);
+    }
+
+    #[test]
+    fn test_song_position_pointer() {
+        assert_eq!(midi_song_position(0), [super::SONG_POSITION_POINTER, 0, 0]);
+        assert_eq!(
+            midi_song_position(16),
+            [super::SONG_POSITION_POINTER, 16, 0]
+        );
+        // 40 bars of 4/4 is 640 sixteenths
+        assert_eq!(
+            midi_song_position(640),
+            [super::SONG_POSITION_POINTER, 0, 5]
+        );
+        assert_eq!(
+            midi_song_position(16_384),
+            [super::SONG_POSITION_POINTER, 0x7f, 0x7f]
+        );
+    }
+
+    #[test]
+    fn test_scheduler_jump_to() {
+        let mut scheduler = create_scheduler().with_clock(true);
+        let mut slice_start = scheduler.pattern_start();
+        let now = slice_start;
+        scheduler.schedule_slice(now, &mut slice_start, C_KEY);
+
+        let at = slice_start + 10 * NS_PER_MS;
+        let packet_buf = scheduler
+            .jump_to(at, &mut slice_start, TimeCode::new(1, 1, 0))
+            .unwrap();
+        let stop: Vec<&[u8]> = packet_buf.iter().map(|packet| packet.data()).collect();
+        assert_eq!(stop, vec![&[STOP][..]]);
+        assert_eq!(slice_start, at);
+        // the second beat of the second bar of the pattern is now playing
+        assert_eq!(scheduler.pattern_start(), at - 5 * 500 * NS_PER_MS);
+
+        let (_, packet_buf) = scheduler.schedule_slice(at, &mut slice_start, C_KEY);
+        let packet = packet_buf.iter().next().unwrap();
+        assert_eq!(packet.timestamp(), at);
+        assert_eq!(
+            crate::midi_messages(packet.data())[..2],
+            [&midi_song_position(20)[..], &[CONTINUE][..]]
+        );
+    }
+
+    #[test]
+    fn test_scheduler_jump_to_in_seven_eight() {
+        let seven_eight = Meter::new(7, 8);
+        let ticks_per_quarter = 96;
+        let timed_events = create_bars(
+            ticks_per_quarter,
+            seven_eight,
+            &[Chord::Major(C_KEY), Chord::Major(F_KEY)],
+        );
+        // two bars of seven eighths at 120 BPM
+        let pattern_length = 3_500 * NS_PER_MS;
+        let mut scheduler = Scheduler::new(
+            200_000_000_000_000,
+            200 * NS_PER_MS,
+            50 * NS_PER_MS,
+            timed_events,
+            pattern_length,
+            500_000,
+            ticks_per_quarter,
+        )
+        .with_clock(true)
+        .with_meter(seven_eight);
+        let mut slice_start = scheduler.pattern_start();
+        scheduler.schedule_slice(slice_start, &mut slice_start, C_KEY);
+
+        let at = slice_start;
+        let position = TimeCode::with_meter(ticks_per_quarter, seven_eight, 3, 0, 0);
+        scheduler.jump_to(at, &mut slice_start, position).unwrap();
+        // the fourth bar is the second bar of the pattern
+        assert_eq!(scheduler.pattern_start(), at - 1_750 * NS_PER_MS);
+
+        let (_, packet_buf) = scheduler.schedule_slice(at, &mut slice_start, C_KEY);
+        let packet = packet_buf.iter().next().unwrap();
+        assert_eq!(
+            crate::midi_messages(packet.data())[..5],
+            [
+                &midi_song_position(42)[..],
+                &[CONTINUE][..],
+                &[TIMING_CLOCK][..],
+                &[0x90, NOTE_C3, 120][..],
+                &[0x90, NOTE_F3, 120][..]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scheduler_ramp_tempo_in_three_four() {
+        let mut scheduler = create_scheduler().with_meter(Meter::new(3, 4));
+        let initial_start = scheduler.pattern_start();
+        let mut slice_start = initial_start;
+        scheduler.schedule_slice(initial_start, &mut slice_start, C_KEY);
+
+        // a bar of 3/4 has three beats to get from 120 to 90 BPM
+        scheduler.ramp_tempo(666_667, 1);
+        schedule_note_ons(
+            &mut scheduler,
+            &mut slice_start,
+            initial_start + 1_000 * NS_PER_MS,
+        );
+        assert_eq!(scheduler.us_per_quarter(), 545_455);
+        schedule_note_ons(
+            &mut scheduler,
+            &mut slice_start,
+            initial_start + 3_000 * NS_PER_MS,
+        );
+        assert_eq!(scheduler.us_per_quarter(), 666_667);
+    }
+
+    // a bar of eighth notes at 120 BPM
+    fn create_eighths_scheduler(groove: Groove) -> Scheduler {
+        let ticks_per_quarter = 96;
+        let mut timed_events = vec![];
+        for eighth in 0..8 {
+            let (on, off) = create_note(eighth * 48, 24, 0, NOTE_C3, 100);
+            timed_events.push(on);
+            timed_events.push(off);
+        }
+        Scheduler::new(
+            200_000_000_000_000,
+            200 * NS_PER_MS,
+            50 * NS_PER_MS,
+            timed_events,
+            2_000 * NS_PER_MS,
+            500_000,
+            ticks_per_quarter,
+        )
+        .with_groove(groove)
+    }
+
+    fn note_on_messages(packet_buf: &coremidi::PacketBuffer, origin: u64) -> Vec<(u64, u8)> {
+        let mut note_ons = vec![];
+        for packet in packet_buf.iter() {
+            for message in crate::midi_messages(packet.data()) {
+                if message[0] & 0xF0 == 0x90 {
+                    note_ons.push(((packet.timestamp() - origin) / NS_PER_MS, message[2]));
+                }
+            }
+        }
+        note_ons
+    }
+
+    #[test]
+    fn test_scheduler_swing() {
+        let mut scheduler = create_eighths_scheduler(Groove::swing(60.0, 2));
+        let pattern_start = scheduler.pattern_start();
+        let mut note_ons = vec![];
+        let mut slice_start = pattern_start;
+        while slice_start < pattern_start + scheduler.pattern_length() {
+            let (_, packet_buf) = scheduler.schedule_slice(slice_start, &mut slice_start, C_KEY);
+            note_ons.append(&mut note_on_messages(&packet_buf, pattern_start));
+        }
+        // off-beats are a fifth of an eighth (50ms) late
+        let times: Vec<u64> = note_ons.iter().map(|(time, _)| *time).collect();
+        assert_eq!(times, vec![0, 300, 500, 800, 1_000, 1_300, 1_500, 1_800]);
+    }
+
+    #[test]
+    fn test_scheduler_swing_keeps_the_note_lengths() {
+        let mut scheduler = create_eighths_scheduler(Groove::swing(90.0, 2));
+        let pattern_start = scheduler.pattern_start();
+        let mut slice_start = pattern_start;
+        let mut notes = vec![];
+        while slice_start < pattern_start + scheduler.pattern_length() {
+            let (_, packet_buf) = scheduler.schedule_slice(slice_start, &mut slice_start, C_KEY);
+            for packet in packet_buf.iter() {
+                for message in crate::midi_messages(packet.data()) {
+                    notes.push(((packet.timestamp() - pattern_start) / NS_PER_MS, message[0]));
+                }
+            }
+        }
+        // each sixteenth lasts 125ms, however late it starts, and the last
+        // one ends in the next pass
+        let starts: Vec<u64> = notes.iter().filter(|n| n.1 == 0x90).map(|n| n.0).collect();
+        let ends: Vec<u64> = notes.iter().filter(|n| n.1 == 0x80).map(|n| n.0).collect();
+        assert_eq!(starts, vec![0, 450, 500, 950, 1_000, 1_450, 1_500, 1_950]);
+        assert_eq!(ends, vec![75, 125, 575, 625, 1_075, 1_125, 1_575, 1_625]);
+    }
+
+    #[test]
+    fn test_scheduler_groove_wraps_around_the_pattern() {
+        // the first eighth is pushed ahead of the beat, and accented
+        let groove = Groove::template(
+            2,
+            vec![
+                GrooveStep {
+                    shift: -0.2,
+                    velocity: 1.2,
+                },
+                GrooveStep {
+                    shift: 0.0,
+                    velocity: 0.5,
+                },
+            ],
+        );
+        let mut scheduler = create_eighths_scheduler(groove);
+        let pattern_start = scheduler.pattern_start();
+        let mut slice_start = pattern_start;
+        let mut note_ons = vec![];
+        while slice_start < pattern_start + 2 * scheduler.pattern_length() {
+            let (_, packet_buf) = scheduler.schedule_slice(slice_start, &mut slice_start, C_KEY);
+            note_ons.append(&mut note_on_messages(&packet_buf, pattern_start));
+        }
+        assert_eq!(note_ons.len(), 16);
+        assert_eq!(note_ons[0], (250, 50));
+        assert_eq!(note_ons[1], (450, 120));
+        // the first eighth of the second pass comes at the end of the first
+        assert_eq!(note_ons[7], (1_950, 120));
+        assert_eq!(note_ons[15], (3_950, 120));
+    }
+
+    #[test]
+    fn test_scheduler_humanizer() {
+        let humanizer = Humanizer::new(31)
+            .with_timing(10 * NS_PER_MS)
+            .with_velocity(10);
+        let schedule = || {
+            let mut scheduler = create_scheduler().with_humanizer(humanizer);
+            let pattern_start = scheduler.pattern_start();
+            let mut slice_start = pattern_start;
+            let mut note_ons = vec![];
+            let mut note_offs = hashset![];
+            while slice_start < pattern_start + 3 * scheduler.pattern_length() {
+                let (_, packet_buf) =
+                    scheduler.schedule_slice(slice_start, &mut slice_start, C_KEY);
+                for packet in packet_buf.iter() {
+                    let time = packet.timestamp() - pattern_start;
+                    for message in crate::midi_messages(packet.data()) {
+                        match message[0] {
+                            0x90 => note_ons.push((time, message[1], message[2])),
+                            _ => {
+                                note_offs.insert((time, message[1]));
+                            }
+                        }
+                    }
+                }
+            }
+            (note_ons, note_offs)
+        };
+        let (mut note_ons, note_offs) = schedule();
+        // the same seed gives the same performance
+        assert_eq!(note_ons, schedule().0);
+
+        // all notes are played, near the beat, and for as long as written
+        let quarter = 500 * NS_PER_MS;
+        note_ons
+            .retain(|(time, _, _)| *time > quarter / 2 && *time < 12_000 * NS_PER_MS - quarter / 2);
+        assert_eq!(note_ons.len(), (3 * 8 - 1) * 3);
+        for &(on, note, velocity) in &note_ons {
+            let offset = (on + quarter / 2) % quarter;
+            assert!(offset.abs_diff(quarter / 2) <= 10 * NS_PER_MS);
+            assert!((110..=127).contains(&velocity));
+            assert!(note_offs.contains(&(on + 375 * NS_PER_MS, note)));
+        }
+        // and each pass is different
+        let pattern_length = 4_000 * NS_PER_MS;
+        assert_ne!(note_ons[0].0, note_ons[24].0 - pattern_length);
+    }
+
+    #[test]
+    fn test_scheduler_follows_external_clock() {
+        use crate::clock::ClockFollower;
+
+        // the scheduler starts out at 120 BPM, but the master plays at 125 BPM
+        let mut scheduler = create_scheduler();
+        let initial_start = scheduler.pattern_start();
+        let master_ns_per_clock = 480_000 * NS_PER_US / 24;
+        let master_start = initial_start + 1_234 * NS_PER_MS;
+        let mut follower = ClockFollower::new();
+        follower.receive(master_start - 10 * NS_PER_MS, &[0xFA]);
+
+        let mut clock = 0;
+        let mut now = initial_start;
+        let mut slice_start = 0;
+        let mut note_ons = vec![];
+        while now < master_start + 20_000 * NS_PER_MS {
+            while master_start + clock * master_ns_per_clock <= now {
+                follower.receive(master_start + clock * master_ns_per_clock, &[0xF8]);
+                clock += 1;
+            }
+            if let (Some((beat, beat_time)), Some(us_per_quarter)) =
+                (follower.beat(), follower.us_per_quarter())
+            {
+                scheduler.follow(now, &mut slice_start, beat, beat_time, us_per_quarter);
+                let (sleep_time, packet_buf) =
+                    scheduler.schedule_slice(now, &mut slice_start, C_KEY);
+                for packet in packet_buf.iter() {
+                    if packet.data()[0] == 0x90 {
+                        note_ons.push(packet.timestamp());
+                    }
+                }
+                now = now.wrapping_add(sleep_time as u64);
+            } else {
+                now += scheduler.slice_length();
+            }
+        }
+
+        // after settling, each quarter of the pattern is played on the beat
+        note_ons.retain(|time| *time > master_start + 5_000 * NS_PER_MS);
+        assert!(note_ons.len() > 20);
+        let master_quarter = 24 * master_ns_per_clock;
+        for time in note_ons {
+            let offset = (time - master_start) % master_quarter;
+            let error = std::cmp::min(offset, master_quarter - offset);
+            assert!(error < NS_PER_MS, "{} ns off the beat", error);
+        }
+    }
+
+    // the times of the note-ons of the coming slices, with the number of
+    // notes starting at each time
+    fn schedule_note_ons(
+        scheduler: &mut Scheduler,
+        slice_start: &mut u64,
+        until: u64,
+    ) -> Vec<(u64, usize)> {
+        let mut note_ons: Vec<(u64, usize)> = vec![];
+        while *slice_start < until {
+            let now = *slice_start;
+            let (_, packet_buf) = scheduler.schedule_slice(now, slice_start, C_KEY);
+            for packet in packet_buf.iter() {
+                for message in crate::midi_messages(packet.data()) {
+                    if message[0] & 0xF0 != 0x90 {
+                        continue;
+                    }
+                    match note_ons.last_mut() {
+                        Some((time, notes)) if *time == packet.timestamp() => *notes += 1,
+                        _ => note_ons.push((packet.timestamp(), 1)),
+                    }
+                }
+            }
+        }
+        note_ons
+    }
+
+    #[test]
+    fn test_scheduler_set_tempo() {
+        let mut scheduler = create_scheduler();
+        let initial_start = scheduler.pattern_start();
+        let mut slice_start = initial_start;
+        scheduler.schedule_slice(initial_start, &mut slice_start, C_KEY);
+
+        // speed up to 150 BPM from the second beat on
+        scheduler.set_tempo(400_000);
+        let note_ons = schedule_note_ons(
+            &mut scheduler,
+            &mut slice_start,
+            initial_start + 4_000 * NS_PER_MS,
+        );
+        let ms = |time: &(u64, usize)| ((time.0 - initial_start) / NS_PER_MS, time.1);
+        assert_eq!(
+            note_ons.iter().map(ms).collect::<Vec<_>>(),
+            vec![
+                (500, 3),
+                (900, 3),
+                (1_300, 3),
+                (1_700, 3),
+                (2_100, 3),
+                (2_500, 3),
+                (2_900, 3),
+                (3_300, 3),
+                (3_700, 3)
+            ]
+        );
+        assert_eq!(scheduler.us_per_quarter(), 400_000);
+        assert_eq!(scheduler.pattern_length(), 3_200 * NS_PER_MS);
+        // the second pass of the pattern starts on the ninth beat
+        assert_eq!(scheduler.pattern_start(), initial_start + 3_300 * NS_PER_MS);
+    }
+
+    #[test]
+    fn test_scheduler_position() {
+        let mut scheduler = create_scheduler();
+        let initial_start = scheduler.pattern_start();
+        let mut slice_start = initial_start;
+        assert_eq!(scheduler.position(initial_start + 250 * NS_PER_MS), 48);
+        scheduler.schedule_slice(initial_start, &mut slice_start, C_KEY);
+        scheduler.set_tempo(400_000);
+        schedule_note_ons(
+            &mut scheduler,
+            &mut slice_start,
+            initial_start + 4_000 * NS_PER_MS,
+        );
+        // the two bars of the first pass, and a beat of the second
+        let ms = |ms: u64| initial_start + ms * NS_PER_MS;
+        assert_eq!(scheduler.position(ms(3_300)), 768);
+        assert_eq!(scheduler.position(ms(3_700)), 864);
+        // before the second pass
+        assert_eq!(scheduler.position(ms(3_200)), 744);
+
+        scheduler
+            .jump_to(ms(4_000), &mut slice_start, TimeCode::new(5, 1, 0))
+            .unwrap();
+        assert_eq!(scheduler.position(ms(4_000)), 5 * 384 + 96);
+        // the last bar of 16_384 sixteenths, and the one after
+        assert!(scheduler
+            .jump_to(ms(4_000), &mut slice_start, TimeCode::new(1_023, 3, 72))
+            .is_ok());
+        assert!(scheduler
+            .jump_to(ms(4_000), &mut slice_start, TimeCode::new(1_024, 0, 0))
+            .is_err());
+        assert!(scheduler
+            .jump_to(ms(4_000), &mut slice_start, TimeCode::new(3_000_000, 0, 0))
+            .is_err());
+    }
+
+    #[test]
+    fn test_scheduler_ramp_tempo() {
+        let mut scheduler = create_scheduler();
+        let initial_start = scheduler.pattern_start();
+        let mut slice_start = initial_start;
+        scheduler.schedule_slice(initial_start, &mut slice_start, C_KEY);
+
+        // slow down from 120 to 60 BPM during a bar
+        scheduler.ramp_tempo(1_000_000, 1);
+        let note_ons = schedule_note_ons(
+            &mut scheduler,
+            &mut slice_start,
+            initial_start + 5_000 * NS_PER_MS,
+        );
+        let beats: Vec<u64> = note_ons
+            .iter()
+            .map(|(time, _)| time - initial_start)
+            .collect();
+        let intervals: Vec<u64> = beats
+            .windows(2)
+            .map(|beats| (beats[1] - beats[0]) / NS_PER_US)
+            .collect();
+        // 105, 90, 75 and then 60 BPM
+        assert_eq!(beats[0], 500 * NS_PER_MS);
+        assert_eq!(intervals[..4], [571_429, 666_667, 800_000, 1_000_000]);
+        assert!(note_ons.iter().all(|(_, notes)| *notes == 3));
+        assert_eq!(scheduler.us_per_quarter(), 1_000_000);
+    }
+
+    #[test]
+    fn test_scheduler_velocity_scale() {
+        let mut scheduler = create_eighths_scheduler(Groove::straight());
+        scheduler.set_velocity_scale(0.5);
+        let pattern_start = scheduler.pattern_start();
+        let mut slice_start = pattern_start;
+        let mut note_ons = vec![];
+        while slice_start < pattern_start + scheduler.pattern_length() {
+            let (_, packet_buf) = scheduler.schedule_slice(slice_start, &mut slice_start, C_KEY);
+            note_ons.append(&mut note_on_messages(&packet_buf, pattern_start));
+        }
+        assert_eq!(note_ons.len(), 8);
+        assert!(note_ons.iter().all(|(_, velocity)| *velocity == 50));
+    }
+
+    #[test]
+    fn test_scheduler_queue_pattern() {
+        let mut scheduler = create_eighths_scheduler(Groove::straight());
+        let pattern_start = scheduler.pattern_start();
+        let mut slice_start = pattern_start;
+        let (_, packet_buf) = scheduler.schedule_slice(slice_start, &mut slice_start, C_KEY);
+        let mut note_ons = note_on_messages(&packet_buf, pattern_start);
+
+        // quarter notes, from the next pass of the pattern on
+        let mut quarters = vec![];
+        for quarter in 0..4 {
+            let (on, off) = create_note(quarter * 96, 48, 0, NOTE_C3, 80);
+            quarters.push(on);
+            quarters.push(off);
+        }
+        scheduler.queue_pattern(quarters, 384);
+        while slice_start < pattern_start + 2 * 2_000 * NS_PER_MS {
+            let (_, packet_buf) = scheduler.schedule_slice(slice_start, &mut slice_start, C_KEY);
+            note_ons.append(&mut note_on_messages(&packet_buf, pattern_start));
+        }
+        assert_eq!(
+            note_ons,
+            vec![
+                (0, 100),
+                (250, 100),
+                (500, 100),
+                (750, 100),
+                (1_000, 100),
+                (1_250, 100),
+                (1_500, 100),
+                (1_750, 100),
+                (2_000, 80),
+                (2_500, 80),
+                (3_000, 80),
+                (3_500, 80)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scheduler_set_pattern() {
+        let mut scheduler = create_eighths_scheduler(Groove::straight());
+        let pattern_start = scheduler.pattern_start();
+        let mut slice_start = pattern_start;
+        let (_, packet_buf) = scheduler.schedule_slice(slice_start, &mut slice_start, C_KEY);
+        let mut note_ons = note_on_messages(&packet_buf, pattern_start);
+
+        // a chord change in the middle of the pattern
+        let mut timed_events = vec![];
+        for eighth in 0..8 {
+            let (on, off) = create_note(eighth * 48, 24, 0, NOTE_F3, 80);
+            timed_events.push(on);
+            timed_events.push(off);
+        }
+        scheduler.queue_pattern(vec![], 384);
+        scheduler.set_pattern(timed_events);
+        while slice_start < pattern_start + 2 * 2_000 * NS_PER_MS {
+            let (_, packet_buf) = scheduler.schedule_slice(slice_start, &mut slice_start, C_KEY);
+            note_ons.append(&mut note_on_messages(&packet_buf, pattern_start));
+        }
+        // the new pattern plays on, instead of the queued one
+        assert_eq!(note_ons.len(), 16);
+        assert_eq!(note_ons[0], (0, 100));
+        assert!(note_ons[1..]
+            .iter()
+            .enumerate()
+            .all(|(eighth, note_on)| *note_on == ((eighth as u64 + 1) * 250, 80)));
+    }
 }
@@ -0,0 +1,143 @@
+/** Progressions: the chords komp heard while playing along, placed on the
+bar grid of the accompaniment so that they can be written down as a chart.
+
+Each chord is recorded at its position from the start of the accompaniment,
+in ticks. A change is moved to the nearest beat, as chords are often played
+a little early or late, and of the chords changed to around the same beat,
+the last one is kept. Notes that komp recognizes no chord in leave the chart
+as it was, as they leave the accompaniment. The chart starts with the bar of
+the first chord.
+*/
+use crate::pattern::Meter;
+use crate::song::Change;
+use komp_core::Chord;
+
+pub struct Progression {
+    ticks_per_quarter: u32,
+    meter: Meter,
+    us_per_quarter: u32,
+    changes: Vec<(u64, Chord)>,
+}
+
+impl Progression {
+    pub fn new(ticks_per_quarter: u32, meter: Meter, us_per_quarter: u32) -> Progression {
+        Progression {
+            ticks_per_quarter,
+            meter,
+            us_per_quarter,
+            changes: vec![],
+        }
+    }
+
+    /// Record a change to `chord` at `tick`, from the start of the accompaniment.
+    pub fn record(&mut self, tick: u64, chord: Chord) {
+        self.changes.push((tick, chord));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn meter(&self) -> Meter {
+        self.meter
+    }
+
+    pub fn us_per_quarter(&self) -> u32 {
+        self.us_per_quarter
+    }
+
+    // the changes, each on its beat counted from the start
+    fn beats(&self) -> Vec<(u64, Chord)> {
+        let beat_ticks = self.meter.ticks_per_beat(self.ticks_per_quarter) as u64;
+        let mut beats: Vec<(u64, Chord)> = vec![];
+        let mut changes = self.changes.clone();
+        changes.sort_by_key(|(tick, _)| *tick);
+        for (tick, chord) in changes {
+            let beat = (tick + beat_ticks / 2) / beat_ticks;
+            if beats.last().is_some_and(|(last, _)| *last == beat) {
+                beats.pop();
+            }
+            if beats.last().is_some_and(|(_, last)| *last == chord) {
+                continue;
+            }
+            beats.push((beat, chord));
+        }
+        beats
+    }
+
+    /// The changes of each bar, from the bar of the first chord to the bar
+    /// of the last one. A bar without changes holds the chord before.
+    pub fn bars(&self) -> Vec<Vec<Change>> {
+        let beats = self.beats();
+        let beats_per_bar = self.meter.beats as u64;
+        let (first, last) = match (beats.first(), beats.last()) {
+            (Some((first, _)), Some((last, _))) => (first / beats_per_bar, last / beats_per_bar),
+            _ => return vec![],
+        };
+        (first..=last)
+            .map(|bar| {
+                beats
+                    .iter()
+                    .filter(|(beat, _)| beat / beats_per_bar == bar)
+                    .map(|(beat, chord)| Change {
+                        beat: (beat % beats_per_bar) as u8,
+                        chord: Some(*chord),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::COMMON_TIME;
+    use komp_core::*;
+
+    fn change(beat: u8, chord: Chord) -> Change {
+        Change {
+            beat,
+            chord: Some(chord),
+        }
+    }
+
+    #[test]
+    fn test_bars() {
+        let mut progression = Progression::new(96, COMMON_TIME, 500_000);
+        assert!(progression.bars().is_empty());
+        // a little early for the second bar
+        progression.record(390, Chord::Minor7(D_KEY));
+        // played key by key, and then the same chord again
+        progression.record(570, Chord::None(G_KEY));
+        progression.record(580, Chord::Major7(G_KEY));
+        progression.record(900, Chord::Major7(G_KEY));
+        // late for the fourth bar, after a bar of G7
+        progression.record(1_170, Chord::MajorMaj7(C_KEY));
+        assert_eq!(
+            progression.bars(),
+            vec![
+                vec![
+                    change(0, Chord::Minor7(D_KEY)),
+                    change(2, Chord::Major7(G_KEY))
+                ],
+                vec![],
+                vec![change(0, Chord::MajorMaj7(C_KEY))],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bars_in_six_eight() {
+        let mut progression = Progression::new(96, Meter::new(6, 8), 500_000);
+        progression.record(48 * 8, Chord::Minor(A_KEY));
+        progression.record(48 * 15, Chord::Major7(E_KEY));
+        assert_eq!(
+            progression.bars(),
+            vec![
+                vec![change(2, Chord::Minor(A_KEY))],
+                vec![change(3, Chord::Major7(E_KEY))]
+            ]
+        );
+    }
+}
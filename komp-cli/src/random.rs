@@ -0,0 +1,84 @@
+/** A small, seeded pseudo random number generator (SplitMix64).
+
+The same seed always gives the same numbers, so that anything random in
+the accompaniment can be reproduced, and tested.
+*/
+#[derive(Clone, Debug)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random { state: seed }
+    }
+
+    /// A generator for a particular use of a seed, such as the humanization
+    /// of one note in one pass of a pattern, which does not depend on what
+    /// else has been generated from the seed.
+    pub fn keyed(seed: u64, keys: &[u64]) -> Random {
+        let mut random = Random::new(seed);
+        for key in keys {
+            random = Random::new(random.next_u64() ^ key);
+        }
+        random
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A number in [-1, 1), more likely near 0 than near the ends.
+    pub fn next_centered(&mut self) -> f64 {
+        self.next_f64() + self.next_f64() - 1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_numbers() {
+        let mut a = Random::new(42);
+        let mut b = Random::new(42);
+        for _ in 0..10 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(Random::new(42).next_u64(), Random::new(43).next_u64());
+    }
+
+    #[test]
+    fn test_keyed() {
+        let mut a = Random::keyed(42, &[1, 2]);
+        let mut b = Random::keyed(42, &[1, 2]);
+        assert_eq!(a.next_u64(), b.next_u64());
+        assert_ne!(
+            Random::keyed(42, &[1, 2]).next_u64(),
+            Random::keyed(42, &[2, 1]).next_u64()
+        );
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut random = Random::new(7);
+        let mut sum = 0.0;
+        for _ in 0..10_000 {
+            let f = random.next_f64();
+            assert!((0.0..1.0).contains(&f));
+            let c = random.next_centered();
+            assert!((-1.0..1.0).contains(&c));
+            sum += c;
+        }
+        assert!((sum / 10_000.0).abs() < 0.02);
+    }
+}
@@ -0,0 +1,309 @@
+/** Rhythms that decide when a track plays its notes.
+
+A rhythm is a number of steps, evenly over a bar. A step may be a hit,
+which plays with a probability and may be ratcheted into quick repeated
+hits, or a rest. A rhythm is written as a grid, like the lanes of the
+drums, or as a Euclidean rhythm `E(k,n)`: `k` hits spread as evenly as
+possible over `n` steps, `E(k,n,r)` rotated by `r` steps.
+
+In a grid, `x` is a hit, `X` an accented hit, `o` a ghost note, `?` a hit
+half of the time, `2` to `4` a hit ratcheted into that many, and `.` or
+`-` a rest; `|` may be used to group the steps. A rhythm followed by
+`@<probability>`, such as `E(5,16)@0.8`, plays each of its hits with
+that probability.
+
+Which hits play is decided by the seed and the bar, so that the same
+rhythm plays the same way again.
+*/
+use crate::pattern::{create_note, Meter};
+use crate::play::TimedEvent;
+use crate::random::Random;
+
+const HIT_VELOCITY: u8 = 100;
+const ACCENT_VELOCITY: u8 = 127;
+const GHOST_VELOCITY: u8 = 50;
+
+/// A step of a rhythm, a rest when its velocity is 0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Step {
+    pub velocity: u8,
+    pub probability: f64,
+    pub ratchet: u8,
+}
+
+impl Step {
+    pub fn hit(velocity: u8) -> Step {
+        Step {
+            velocity,
+            probability: 1.0,
+            ratchet: 1,
+        }
+    }
+    pub fn rest() -> Step {
+        Step::hit(0)
+    }
+    pub fn is_hit(&self) -> bool {
+        self.velocity > 0
+    }
+}
+
+/// A hit in a bar: when it starts, how long it lasts and how loud it is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
+    pub timing: u32,
+    pub length: u32,
+    pub velocity: u8,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rhythm {
+    steps: Vec<Step>,
+    seed: u64,
+}
+
+impl std::str::FromStr for Rhythm {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Rhythm, String> {
+        let s = s.trim();
+        let (rhythm, probability) = match s.rfind('@') {
+            Some(at) => {
+                let probability: f64 = s[at + 1..]
+                    .parse()
+                    .map_err(|_| format!("wrong probability: {}", s))?;
+                if !(0.0..=1.0).contains(&probability) {
+                    return Err(format!("wrong probability: {}", s));
+                }
+                (&s[..at], Some(probability))
+            }
+            None => (s, None),
+        };
+        let rhythm = if rhythm.starts_with("E(") || rhythm.starts_with("e(") {
+            parse_euclidean(rhythm)?
+        } else {
+            parse_grid(rhythm)?
+        };
+        if !rhythm.steps.iter().any(Step::is_hit) {
+            return Err(format!("no hits in rhythm: {}", s));
+        }
+        Ok(match probability {
+            Some(probability) => rhythm.with_probability(probability),
+            None => rhythm,
+        })
+    }
+}
+
+fn parse_euclidean(s: &str) -> Result<Rhythm, String> {
+    let error = || format!("wrong Euclidean rhythm: {}", s);
+    let arguments = s[2..].strip_suffix(')').ok_or_else(error)?;
+    let numbers = arguments
+        .split(',')
+        .map(|number| number.trim().parse::<u8>())
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| error())?;
+    match numbers[..] {
+        [hits, steps] if hits <= steps && steps > 0 => Ok(Rhythm::euclidean(hits, steps, 0)),
+        [hits, steps, rotation] if hits <= steps && steps > 0 => {
+            Ok(Rhythm::euclidean(hits, steps, rotation))
+        }
+        _ => Err(error()),
+    }
+}
+
+fn parse_grid(s: &str) -> Result<Rhythm, String> {
+    let mut steps = vec![];
+    for c in s.chars().filter(|c| !c.is_whitespace()) {
+        let step = match c {
+            'x' => Step::hit(HIT_VELOCITY),
+            'X' => Step::hit(ACCENT_VELOCITY),
+            'o' => Step::hit(GHOST_VELOCITY),
+            '?' => Step {
+                probability: 0.5,
+                ..Step::hit(HIT_VELOCITY)
+            },
+            '2'..='4' => Step {
+                ratchet: c as u8 - b'0',
+                ..Step::hit(HIT_VELOCITY)
+            },
+            '.' | '-' => Step::rest(),
+            '|' => continue,
+            _ => return Err(format!("wrong rhythm step: {}", c)),
+        };
+        steps.push(step);
+    }
+    Ok(Rhythm::new(steps))
+}
+
+impl Rhythm {
+    pub fn new(steps: Vec<Step>) -> Rhythm {
+        Rhythm { steps, seed: 0 }
+    }
+
+    /// `hits` spread as evenly as possible over `steps`, the first on the
+    /// first step, and then rotated to the left by `rotation` steps.
+    pub fn euclidean(hits: u8, steps: u8, rotation: u8) -> Rhythm {
+        let (hits, steps) = (hits as u32, steps.max(1) as u32);
+        let is_hit = |step: u32| (step * hits) % steps < hits;
+        Rhythm::new(
+            (0..steps)
+                .map(|step| {
+                    if is_hit((step + rotation as u32) % steps) {
+                        Step::hit(HIT_VELOCITY)
+                    } else {
+                        Step::rest()
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// Every hit plays with `probability`, as well as its own.
+    pub fn with_probability(mut self, probability: f64) -> Rhythm {
+        for step in &mut self.steps {
+            step.probability *= probability.clamp(0.0, 1.0);
+        }
+        self
+    }
+    pub fn with_seed(mut self, seed: u64) -> Rhythm {
+        self.seed = seed;
+        self
+    }
+
+    /// The hits that play in `bar`, each lasting half of its step, or of
+    /// its part of a ratcheted step.
+    pub fn hits(&self, ticks_per_quarter: u32, meter: Meter, bar: u64) -> Vec<Hit> {
+        let bar_ticks = meter.ticks_per_bar(ticks_per_quarter);
+        let steps = self.steps.len() as u32;
+        let mut hits = vec![];
+        for (index, step) in self.steps.iter().enumerate() {
+            if !step.is_hit() {
+                continue;
+            }
+            if step.probability < 1.0 {
+                let mut random = Random::keyed(self.seed, &[bar, index as u64]);
+                if random.next_f64() >= step.probability {
+                    continue;
+                }
+            }
+            // from the start of the bar, so that rounding does not add up
+            let start = index as u32 * bar_ticks / steps;
+            let end = (index as u32 + 1) * bar_ticks / steps;
+            let ratchet = step.ratchet as u32;
+            for part in 0..ratchet {
+                let timing = start + part * (end - start) / ratchet;
+                hits.push(Hit {
+                    timing,
+                    length: ((end - start) / ratchet / 2).max(1),
+                    velocity: step.velocity,
+                });
+            }
+        }
+        hits
+    }
+
+    /// A bar of `notes` played on the hits of the rhythm.
+    pub fn create_bar(
+        &self,
+        ticks_per_quarter: u32,
+        meter: Meter,
+        bar: u64,
+        channel: u8,
+        notes: &[u8],
+    ) -> Vec<TimedEvent> {
+        let mut timed_events = vec![];
+        for hit in self.hits(ticks_per_quarter, meter, bar) {
+            for note in notes {
+                let (on, off) = create_note(hit.timing, hit.length, channel, *note, hit.velocity);
+                timed_events.push(on);
+                timed_events.push(off);
+            }
+        }
+        timed_events.sort();
+        timed_events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::COMMON_TIME;
+
+    fn grid(rhythm: &Rhythm) -> String {
+        rhythm
+            .steps
+            .iter()
+            .map(|step| if step.is_hit() { 'x' } else { '.' })
+            .collect()
+    }
+
+    #[test]
+    fn test_euclidean() {
+        assert_eq!(grid(&Rhythm::euclidean(3, 8, 0)), "x..x..x.");
+        assert_eq!(grid(&Rhythm::euclidean(4, 16, 0)), "x...x...x...x...");
+        assert_eq!(grid(&Rhythm::euclidean(5, 8, 0)), "x.x.xx.x");
+        assert_eq!(grid(&Rhythm::euclidean(3, 8, 1)), "..x..x.x");
+        assert_eq!(grid(&Rhythm::euclidean(0, 4, 0)), "....");
+        assert_eq!(grid(&Rhythm::euclidean(4, 4, 0)), "xxxx");
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("E(3,8)".parse(), Ok(Rhythm::euclidean(3, 8, 0)));
+        assert_eq!("E(3, 8, 2)".parse(), Ok(Rhythm::euclidean(3, 8, 2)));
+        let rhythm: Rhythm = "x?.3|X...".parse().unwrap();
+        assert_eq!(grid(&rhythm), "xx.xx...");
+        assert_eq!(rhythm.steps[1].probability, 0.5);
+        assert_eq!(rhythm.steps[3].ratchet, 3);
+        assert_eq!(rhythm.steps[4].velocity, ACCENT_VELOCITY);
+        let rhythm: Rhythm = "E(5,16)@0.8".parse().unwrap();
+        assert!(rhythm
+            .steps
+            .iter()
+            .filter(|step| step.is_hit())
+            .all(|step| step.probability == 0.8));
+        for wrong in &[
+            "E(9,8)", "E(3)", "E(3,8", "x.y.", "....", "x...@2", "E(0,0)",
+        ] {
+            assert!(wrong.parse::<Rhythm>().is_err(), "{}", wrong);
+        }
+    }
+
+    #[test]
+    fn test_hits() {
+        let rhythm: Rhythm = "x..x..2.".parse().unwrap();
+        let hits = rhythm.hits(96, COMMON_TIME, 0);
+        let timings: Vec<u32> = hits.iter().map(|hit| hit.timing).collect();
+        assert_eq!(timings, vec![0, 144, 288, 312]);
+        assert_eq!(hits[0].length, 24);
+        assert_eq!(hits[2].length, 12);
+    }
+
+    #[test]
+    fn test_probability_is_seeded() {
+        let rhythm = "xxxxxxxxxxxxxxxx@0.5"
+            .parse::<Rhythm>()
+            .unwrap()
+            .with_seed(5);
+        let bars: Vec<Vec<Hit>> = (0..8)
+            .map(|bar| rhythm.hits(96, COMMON_TIME, bar))
+            .collect();
+        // the same in the same bar
+        assert_eq!(bars[3], rhythm.hits(96, COMMON_TIME, 3));
+        // but not in every bar
+        assert!(bars.iter().any(|hits| *hits != bars[0]));
+        let played: usize = bars.iter().map(Vec::len).sum();
+        assert!((32..96).contains(&played), "{}", played);
+        assert_ne!(
+            bars[0],
+            rhythm.clone().with_seed(6).hits(96, COMMON_TIME, 0)
+        );
+    }
+
+    #[test]
+    fn test_create_bar() {
+        let rhythm: Rhythm = "x.x.".parse().unwrap();
+        let timed_events = rhythm.create_bar(96, COMMON_TIME, 0, 2, &[40, 44]);
+        assert_eq!(timed_events.len(), 8);
+        let timings: Vec<u32> = timed_events.iter().map(|te| te.timing).collect();
+        assert_eq!(timings, vec![0, 0, 48, 48, 192, 192, 240, 240]);
+    }
+}
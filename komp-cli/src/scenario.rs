@@ -0,0 +1,199 @@
+/** Scenarios: performances written in the chord change DSL (see `detect`), replayed
+through the chord detection of the MIDI input, to tell which chords komp hears.
+
+Each unit of the DSL lasts `ms_per_unit` milliseconds. A released key is let go a
+millisecond before the next step, and keys pressed together reach komp `spread_ms`
+apart, from the lowest to the highest, as they do from a real keyboard. Messages
+with the same time arrive in the same packet, as CoreMIDI delivers them.
+
+The messages go through the same steps as in the MIDI input callback: `process_midi`
+keeps track of the notes playing, and `detect_chord` decides the current chord.
+*/
+use crate::detect::{interpret_dsl, DslError};
+use crate::play::{Event, TimedEvent};
+use crate::{detect_chord, process_midi, Playing};
+use komp_core::Chord;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+const NOTE_ON: u8 = 0x90;
+const NOTE_OFF: u8 = 0x80;
+const RELEASE_VELOCITY: u8 = 64;
+
+pub struct Scenario {
+    ms_per_unit: u32,
+    spread_ms: u32,
+}
+
+impl Scenario {
+    pub fn new(ms_per_unit: u32) -> Scenario {
+        Scenario {
+            ms_per_unit: ms_per_unit.max(2),
+            spread_ms: 0,
+        }
+    }
+    pub fn with_spread(mut self, spread_ms: u32) -> Scenario {
+        self.spread_ms = spread_ms;
+        self
+    }
+
+    /// The MIDI messages of a performance, with their times in milliseconds.
+    pub fn messages(&self, chord_change_dsl: &str) -> Result<Vec<(u32, [u8; 3])>, DslError> {
+        // a millisecond to a tick, so that keys are released a tick before the next step
+        let timed_events = interpret_dsl(chord_change_dsl, 0, self.ms_per_unit, self.ms_per_unit)?;
+        let mut messages = vec![];
+        let mut pressed_together = 0;
+        let mut last_press = None;
+        for TimedEvent { timing, event } in timed_events {
+            match event {
+                Event::NoteOn {
+                    channel,
+                    note,
+                    velocity,
+                } => {
+                    pressed_together = if last_press == Some(timing) {
+                        pressed_together + 1
+                    } else {
+                        0
+                    };
+                    last_press = Some(timing);
+                    let time = timing + pressed_together * self.spread_ms;
+                    messages.push((time, [NOTE_ON | channel, note, velocity]));
+                }
+                Event::NoteOff { channel, note, .. } => {
+                    messages.push((timing, [NOTE_OFF | channel, note, RELEASE_VELOCITY]))
+                }
+            }
+        }
+        // the order of the messages at the same time is kept
+        messages.sort_by_key(|(time, _)| *time);
+        Ok(messages)
+    }
+
+    /// The chords komp changes to while the performance is played, with the time of
+    /// each change in milliseconds, `None` when komp recognizes no chord.
+    pub fn chord_changes(
+        &self,
+        chord_change_dsl: &str,
+    ) -> Result<Vec<(u32, Option<Chord>)>, DslError> {
+        let messages = self.messages(chord_change_dsl)?;
+        let current_chord: Arc<Mutex<Option<Chord>>> = Arc::new(Mutex::new(None));
+        let mut playing: Playing = HashSet::new();
+        let mut changes = vec![];
+        let mut last_chord = None;
+        let mut index = 0;
+        while index < messages.len() {
+            let time = messages[index].0;
+            let mut was_playing = playing.clone();
+            // a packet of the messages at the same time
+            while index < messages.len() && messages[index].0 == time {
+                process_midi(&messages[index].1, &mut playing);
+                index += 1;
+            }
+            detect_chord(&mut was_playing, &mut playing, &current_chord);
+            let chord = *current_chord.lock().unwrap();
+            if chord != last_chord {
+                changes.push((time, chord));
+                last_chord = chord;
+            }
+        }
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use komp_core::*;
+
+    #[test]
+    fn test_messages() {
+        let scenario = Scenario::new(500).with_spread(8);
+        assert_eq!(
+            scenario.messages("C [E -] G!90").unwrap(),
+            vec![
+                (0, [0x90, NOTE_C4, 100]),
+                (8, [0x90, NOTE_E4, 100]),
+                (16, [0x90, NOTE_G4, 90]),
+                (499, [0x80, NOTE_E4, 64]),
+                (999, [0x80, NOTE_C4, 64]),
+                (999, [0x80, NOTE_G4, 64]),
+            ]
+        );
+        assert!(scenario.messages("C [E").is_err());
+    }
+
+    #[test]
+    fn test_chord_pressed_at_once() {
+        let changes = Scenario::new(500).chord_changes("{C}").unwrap();
+        assert_eq!(changes, vec![(0, Some(Chord::Major(C_KEY)))]);
+    }
+
+    // there is no debouncing yet: komp hears each key of a chord as it is pressed
+    #[test]
+    fn test_chord_pressed_key_by_key() {
+        let changes = Scenario::new(500)
+            .with_spread(10)
+            .chord_changes("C E G")
+            .unwrap();
+        assert_eq!(
+            changes,
+            vec![
+                (0, Some(Chord::None(C_KEY))),
+                (10, None),
+                (20, Some(Chord::Major(C_KEY))),
+            ]
+        );
+    }
+
+    // the last chord is kept when all keys are released, and between the chords
+    #[test]
+    fn test_chord_memory() {
+        let changes = Scenario::new(500)
+            .chord_changes("[{Cmaj7} -] [- {Dm7}3] [- -]")
+            .unwrap();
+        assert_eq!(
+            changes,
+            vec![
+                (0, Some(Chord::MajorMaj7(C_KEY))),
+                (500, Some(Chord::Minor7(D_KEY))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_c_to_c_minor() {
+        let changes = Scenario::new(1000)
+            .with_spread(5)
+            .chord_changes("C [E Eb] G")
+            .unwrap();
+        assert_eq!(
+            changes,
+            vec![
+                (0, Some(Chord::None(C_KEY))),
+                (5, None),
+                (10, Some(Chord::Major(C_KEY))),
+                // the third is let go before the minor third is pressed
+                (999, Some(Chord::Five(C_KEY))),
+                (1000, Some(Chord::Minor(C_KEY))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_c_to_e_minor_to_g() {
+        let changes = Scenario::new(1000)
+            .chord_changes("[C - -] [E _ -] [G _ _] [- B _] [- - D]")
+            .unwrap();
+        assert_eq!(
+            changes,
+            vec![
+                (0, Some(Chord::Major(C_KEY))),
+                (999, None),
+                (1000, Some(Chord::Minor(E_KEY))),
+                (1999, None),
+                (2000, Some(Chord::Major(G_KEY))),
+            ]
+        );
+    }
+}
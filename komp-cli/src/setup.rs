@@ -1,4 +1,22 @@
+use crate::arpeggio::{Arpeggiator, ArpeggioOrder};
+use crate::bass::{BassStyle, BASS_CHANNEL};
+use crate::drums::DrumGrid;
+use crate::groove::Groove;
+use crate::humanize::Humanizer;
+use crate::import::ImportOptions;
+use crate::pattern::{Comping, Meter, COMMON_TIME, COMPING_REGISTER};
+use crate::play::NS_PER_MS;
+use crate::random::Random;
+use crate::rhythm::Rhythm;
+use crate::smf::Smf;
+use crate::song::Song;
+use crate::strum::Strummer;
+use crate::style::{Section, Style, StyleWatcher};
+use crate::tempo::{us_per_quarter_from_bpm, Tap};
+use komp_core::{Chord, VoicingStyle};
 use std::env;
+use std::path::Path;
+use std::str::FromStr;
 
 pub fn tool_name(args_iter: &mut env::Args) -> String {
     args_iter
@@ -78,3 +96,516 @@ fn print_destinations() {
         }
     }
 }
+
+pub struct Options {
+    pub send_clock: bool,
+    pub follow_clock: bool,
+    pub us_per_quarter: u32,
+    pub meter: Meter,
+    pub groove: Groove,
+    pub humanizer: Humanizer,
+    pub follow_dynamics: bool,
+    pub busier_above: Option<u8>,
+    pub register: (u8, u8),
+    pub voicing: VoicingStyle,
+    pub bass: Option<BassStyle>,
+    pub bass_rhythm: Option<Rhythm>,
+    pub drums: Option<DrumGrid>,
+    pub section: Option<Section>,
+    pub style_watcher: Option<StyleWatcher>,
+    pub song: Option<Song>,
+    /// The file to write the chords heard to, as a MusicXML lead sheet.
+    pub musicxml: Option<String>,
+    /// The file to write the chords heard to, as a ChordPro chart.
+    pub chordpro: Option<String>,
+    /// The file to write the chords heard to, as a MIDI file of XF chords.
+    pub xf: Option<String>,
+    pub comping: Comping,
+    pub seed: u64,
+    pub tap: Option<Tap>,
+}
+
+const USAGE: &str = "<source-index> <destination-index> [--clock] [--follow-clock] \
+                     [--style <file.toml>] [--section <name>] [--song <file.toml>] \
+                     [--musicxml <file.xml>] [--chordpro <file.cho>] [--xf <file.mid>] \
+                     [--tempo <bpm>] [--meter <beats/unit>] \
+                     [--swing <percent>] [--shuffle] [--groove <file.mid>] \
+                     [--jitter <ms>] [--velocity-variation <velocity>] [--strum <ms>] \
+                     [--tightness <channel>:<0..1>] [--seed <number>] \
+                     [--dynamics] [--busier-above <velocity>] [--register <low>-<high>] \
+                     [--voicing <style>] [--bass <style>] [--bass-rhythm <rhythm>] \
+                     [--drums <grid>] [--rhythm <rhythm>] \
+                     [--arpeggio <order>] [--arpeggio-rate <notes per whole>] \
+                     [--arpeggio-octaves <octaves>] [--arpeggio-gate <0..1>] \
+                     [--strum-pattern <pattern>] [--strum-spread <ms>] \
+                     [--tap-note <note>] [--tap-cc <controller>]";
+
+pub fn get_options(args_iter: &mut env::Args, tool_name: &str) -> Options {
+    let mut options = Options {
+        send_clock: false,
+        follow_clock: false,
+        us_per_quarter: 500_000,
+        meter: COMMON_TIME,
+        groove: Groove::straight(),
+        humanizer: Humanizer::new(0),
+        follow_dynamics: false,
+        busier_above: None,
+        register: COMPING_REGISTER,
+        voicing: VoicingStyle::Close,
+        bass: None,
+        bass_rhythm: None,
+        drums: None,
+        section: None,
+        style_watcher: None,
+        song: None,
+        musicxml: None,
+        chordpro: None,
+        xf: None,
+        comping: Comping::Chords,
+        seed: 0,
+        tap: None,
+    };
+    let mut groove_file = None;
+    let mut arpeggio: Option<ArpeggioOrder> = None;
+    let mut arpeggio_rate = None;
+    let mut arpeggio_octaves = None;
+    let mut arpeggio_gate = None;
+    let mut strum_pattern: Option<Strummer> = None;
+    let mut strum_spread = None;
+    let mut rhythm: Option<Rhythm> = None;
+    let mut style: Option<(String, Style)> = None;
+    let mut section_name: Option<String> = None;
+    // the meter the bars of the song are laid out in
+    let mut song_meter: Option<Meter> = None;
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--clock" => options.send_clock = true,
+            "--follow-clock" => options.follow_clock = true,
+            "--style" => {
+                let path: String = get_option_value(args_iter, &arg, tool_name);
+                match Style::read(Path::new(&path)) {
+                    Ok(read_style) => {
+                        // the options that follow may change these
+                        if let Some(us_per_quarter) = read_style.us_per_quarter {
+                            options.us_per_quarter = us_per_quarter;
+                        }
+                        if let Some(meter) = read_style.meter {
+                            options.meter = meter;
+                        }
+                        style = Some((path, read_style));
+                    }
+                    Err(e) => {
+                        println!("Cannot read style {}: {}", path, e);
+                        std::process::exit(-1);
+                    }
+                }
+            }
+            "--song" => {
+                let path: String = get_option_value(args_iter, &arg, tool_name);
+                match Song::read(Path::new(&path), options.meter) {
+                    Ok(song) => {
+                        // the options that follow may change the tempo
+                        if let Some(us_per_quarter) = song.us_per_quarter {
+                            options.us_per_quarter = us_per_quarter;
+                        }
+                        if let Some(meter) = song.meter {
+                            options.meter = meter;
+                        }
+                        song_meter = Some(options.meter);
+                        options.song = Some(song);
+                    }
+                    Err(e) => {
+                        println!("Cannot read song {}: {}", path, e);
+                        std::process::exit(-1);
+                    }
+                }
+            }
+            "--musicxml" => options.musicxml = Some(get_option_value(args_iter, &arg, tool_name)),
+            "--chordpro" => options.chordpro = Some(get_option_value(args_iter, &arg, tool_name)),
+            "--xf" => options.xf = Some(get_option_value(args_iter, &arg, tool_name)),
+            "--section" => section_name = Some(get_option_value(args_iter, &arg, tool_name)),
+            "--tempo" => {
+                let bpm: f64 = get_option_value(args_iter, &arg, tool_name);
+                if !bpm.is_finite() || bpm <= 0.0 {
+                    println!("Wrong tempo: {}", bpm);
+                    std::process::exit(-1);
+                }
+                options.us_per_quarter = us_per_quarter_from_bpm(bpm);
+            }
+            "--meter" => options.meter = get_option_value(args_iter, &arg, tool_name),
+            "--swing" => {
+                let percentage: f64 = get_option_value(args_iter, &arg, tool_name);
+                if !(50.0..=100.0).contains(&percentage) {
+                    println!("Wrong swing: {}, it should be from 50 to 100", percentage);
+                    std::process::exit(-1);
+                }
+                options.groove = Groove::swing(percentage, 2);
+            }
+            "--shuffle" => options.groove = Groove::shuffle(2),
+            "--groove" => {
+                groove_file = Some(get_option_value::<String>(args_iter, &arg, tool_name))
+            }
+            "--jitter" => {
+                let ms: f64 = get_option_value(args_iter, &arg, tool_name);
+                options.humanizer = options.humanizer.with_timing(ms_to_ns(ms));
+            }
+            "--velocity-variation" => {
+                let velocity = get_option_value(args_iter, &arg, tool_name);
+                options.humanizer = options.humanizer.with_velocity(velocity);
+            }
+            "--strum" => {
+                let ms: f64 = get_option_value(args_iter, &arg, tool_name);
+                options.humanizer = options.humanizer.with_strum(ms_to_ns(ms));
+            }
+            "--tightness" => {
+                let value: String = get_option_value(args_iter, &arg, tool_name);
+                match parse_tightness(&value) {
+                    Some((channel, tightness)) => {
+                        options.humanizer = options.humanizer.with_tightness(channel, tightness)
+                    }
+                    None => {
+                        println!("Wrong tightness: {}", value);
+                        std::process::exit(-1);
+                    }
+                }
+            }
+            "--seed" => {
+                options.seed = get_option_value(args_iter, &arg, tool_name);
+                options.humanizer = options.humanizer.with_seed(options.seed);
+            }
+            "--dynamics" => options.follow_dynamics = true,
+            "--busier-above" => {
+                options.busier_above = Some(get_option_value(args_iter, &arg, tool_name))
+            }
+            "--register" => {
+                let value: String = get_option_value(args_iter, &arg, tool_name);
+                match parse_register(&value) {
+                    Some(register) => options.register = register,
+                    None => {
+                        println!("Wrong register: {}", value);
+                        std::process::exit(-1);
+                    }
+                }
+            }
+            "--voicing" => options.voicing = get_option_value(args_iter, &arg, tool_name),
+            "--bass" => options.bass = Some(get_option_value(args_iter, &arg, tool_name)),
+            "--bass-rhythm" => {
+                options.bass_rhythm = Some(get_option_value(args_iter, &arg, tool_name))
+            }
+            "--drums" => options.drums = Some(get_option_value(args_iter, &arg, tool_name)),
+            "--rhythm" => rhythm = Some(get_option_value(args_iter, &arg, tool_name)),
+            "--arpeggio" => arpeggio = Some(get_option_value(args_iter, &arg, tool_name)),
+            "--arpeggio-rate" => arpeggio_rate = Some(get_option_value(args_iter, &arg, tool_name)),
+            "--arpeggio-octaves" => {
+                arpeggio_octaves = Some(get_option_value(args_iter, &arg, tool_name))
+            }
+            "--arpeggio-gate" => arpeggio_gate = Some(get_option_value(args_iter, &arg, tool_name)),
+            "--strum-pattern" => strum_pattern = Some(get_option_value(args_iter, &arg, tool_name)),
+            "--strum-spread" => {
+                let ms: f64 = get_option_value(args_iter, &arg, tool_name);
+                strum_spread = Some(ms_to_ns(ms));
+            }
+            "--tap-note" => {
+                options.tap = Some(Tap::Note(get_option_value(args_iter, &arg, tool_name)))
+            }
+            "--tap-cc" => {
+                options.tap = Some(Tap::Controller(get_option_value(
+                    args_iter, &arg, tool_name,
+                )))
+            }
+            _ => {
+                println!("Unknown option: {}", arg);
+                println!("Usage: {} {}", tool_name, USAGE);
+                std::process::exit(-1);
+            }
+        }
+    }
+    if let Some(meter) = song_meter {
+        if meter != options.meter {
+            println!(
+                "The song is in {}, give --meter and --style before --song",
+                meter
+            );
+            std::process::exit(-1);
+        }
+    }
+    if let Some(order) = arpeggio {
+        let mut arpeggiator = Arpeggiator::new(order).with_seed(options.seed);
+        if let Some(rate) = arpeggio_rate {
+            arpeggiator = arpeggiator.with_rate(rate);
+        }
+        if let Some(octaves) = arpeggio_octaves {
+            arpeggiator = arpeggiator.with_octaves(octaves);
+        }
+        if let Some(gate) = arpeggio_gate {
+            arpeggiator = arpeggiator.with_gate(gate);
+        }
+        options.comping = Comping::Arpeggio(arpeggiator);
+    }
+    if let Some(mut strummer) = strum_pattern {
+        if let Some(spread) = strum_spread {
+            strummer = strummer.with_spread(spread);
+        }
+        options.comping = Comping::Strum(strummer);
+    }
+    if let Some((path, style)) = style {
+        let style = style.with_seed(options.seed);
+        match style.section(section_name.as_deref()) {
+            Some(section) => {
+                options.section = Some(section.clone());
+                options.style_watcher = Some(StyleWatcher::new(
+                    Path::new(&path),
+                    &style,
+                    section_name.as_deref(),
+                    options.seed,
+                ));
+            }
+            None => {
+                println!(
+                    "No section {} in the style",
+                    section_name.unwrap_or_default()
+                );
+                std::process::exit(-1);
+            }
+        }
+    } else if section_name.is_some() {
+        println!("--section needs a --style");
+        std::process::exit(-1);
+    }
+    if let Some(rhythm) = rhythm {
+        options.comping = Comping::Rhythm(rhythm.with_seed(options.seed));
+    }
+    // seeded once all the options are known, and apart from the comping
+    let bass_seed = Random::keyed(options.seed, &[BASS_CHANNEL as u64]).next_u64();
+    options.bass_rhythm = options
+        .bass_rhythm
+        .map(|rhythm| rhythm.with_seed(bass_seed));
+    let seed = options.seed;
+    options.drums = options.drums.map(|drums| drums.with_seed(seed));
+    if let Some(path) = groove_file {
+        match Smf::read(Path::new(&path)) {
+            Ok(smf) => {
+                // a bar of sixteenths
+                let steps = options.meter.beats as usize * 16 / options.meter.unit as usize;
+                options.groove = Groove::from_smf(&smf, 4, steps);
+            }
+            Err(error) => {
+                println!("Wrong groove file: {}", error);
+                std::process::exit(-1);
+            }
+        }
+    }
+    options
+}
+
+pub struct ImportCommand {
+    pub clip: String,
+    pub output: Option<String>,
+    pub options: ImportOptions,
+}
+
+const IMPORT_USAGE: &str = "import-pattern <clip.mid> --source-chord <chord> \
+                            [--quantize <notes per whole>] [--bars <bars>] \
+                            [--channel <track>:<channel>] [--name <name>] \
+                            [--output <file.toml>]";
+
+pub fn get_import_command(args_iter: &mut env::Args, tool_name: &str) -> ImportCommand {
+    let clip = match args_iter.next() {
+        Some(clip) if !clip.starts_with("--") => clip,
+        _ => {
+            println!("Usage: {} {}", tool_name, IMPORT_USAGE);
+            std::process::exit(-1);
+        }
+    };
+    let mut source_chord: Option<Chord> = None;
+    let mut output = None;
+    let mut options = ImportOptions::new(Chord::Major(komp_core::C_KEY));
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--source-chord" => source_chord = Some(get_option_value(args_iter, &arg, tool_name)),
+            "--quantize" => {
+                let notes_per_whole: u32 = get_option_value(args_iter, &arg, tool_name);
+                if notes_per_whole == 0 {
+                    println!("Wrong value for {}", arg);
+                    std::process::exit(-1);
+                }
+                options.quantize = Some(notes_per_whole);
+            }
+            "--bars" => options.bars = Some(get_option_value(args_iter, &arg, tool_name)),
+            "--channel" => {
+                let value: String = get_option_value(args_iter, &arg, tool_name);
+                match parse_track_channel(&value) {
+                    Some(channel) => options.channels.push(channel),
+                    None => {
+                        println!("Wrong value for {}", arg);
+                        std::process::exit(-1);
+                    }
+                }
+            }
+            "--name" => options.name = get_option_value(args_iter, &arg, tool_name),
+            "--output" => output = Some(get_option_value(args_iter, &arg, tool_name)),
+            _ => {
+                println!("Unknown option: {}", arg);
+                println!("Usage: {} {}", tool_name, IMPORT_USAGE);
+                std::process::exit(-1);
+            }
+        }
+    }
+    match source_chord {
+        Some(chord) => options.source_chord = chord,
+        None => {
+            println!("Missing --source-chord");
+            println!("Usage: {} {}", tool_name, IMPORT_USAGE);
+            std::process::exit(-1);
+        }
+    }
+    ImportCommand {
+        clip,
+        output,
+        options,
+    }
+}
+
+pub struct RenderCommand {
+    pub file: String,
+    pub unit: u32,
+    /// The channel (from 0) to render, otherwise all but the drums.
+    pub channel: Option<u8>,
+}
+
+const RENDER_USAGE: &str = "render-dsl <file.mid> [--unit <steps per whole>] [--channel <1-16>]";
+
+pub fn get_render_command(args_iter: &mut env::Args, tool_name: &str) -> RenderCommand {
+    let file = match args_iter.next() {
+        Some(file) if !file.starts_with("--") => file,
+        _ => {
+            println!("Usage: {} {}", tool_name, RENDER_USAGE);
+            std::process::exit(-1);
+        }
+    };
+    let mut unit = 16;
+    let mut channel = None;
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--unit" => {
+                unit = get_option_value(args_iter, &arg, tool_name);
+                if unit == 0 {
+                    println!("Wrong value for {}", arg);
+                    std::process::exit(-1);
+                }
+            }
+            "--channel" => {
+                let value: u8 = get_option_value(args_iter, &arg, tool_name);
+                if !(1..=16).contains(&value) {
+                    println!("Wrong value for {}", arg);
+                    std::process::exit(-1);
+                }
+                channel = Some(value - 1);
+            }
+            _ => {
+                println!("Unknown option: {}", arg);
+                println!("Usage: {} {}", tool_name, RENDER_USAGE);
+                std::process::exit(-1);
+            }
+        }
+    }
+    RenderCommand {
+        file,
+        unit,
+        channel,
+    }
+}
+
+pub struct IrealCommand {
+    pub file: String,
+    pub title: Option<String>,
+    pub alternates: bool,
+    pub output: Option<String>,
+}
+
+const IREAL_USAGE: &str =
+    "import-ireal <file> [--title <title>] [--alternates] [--output <file.toml>]";
+
+pub fn get_ireal_command(args_iter: &mut env::Args, tool_name: &str) -> IrealCommand {
+    let file = match args_iter.next() {
+        Some(file) if !file.starts_with("--") => file,
+        _ => {
+            println!("Usage: {} {}", tool_name, IREAL_USAGE);
+            std::process::exit(-1);
+        }
+    };
+    let mut command = IrealCommand {
+        file,
+        title: None,
+        alternates: false,
+        output: None,
+    };
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--title" => command.title = Some(get_option_value(args_iter, &arg, tool_name)),
+            "--alternates" => command.alternates = true,
+            "--output" => command.output = Some(get_option_value(args_iter, &arg, tool_name)),
+            _ => {
+                println!("Unknown option: {}", arg);
+                println!("Usage: {} {}", tool_name, IREAL_USAGE);
+                std::process::exit(-1);
+            }
+        }
+    }
+    command
+}
+
+fn ms_to_ns(ms: f64) -> u64 {
+    (ms.max(0.0) * NS_PER_MS as f64).round() as u64
+}
+
+// a MIDI channel (1 to 16) and a tightness, like 10:0.9
+fn parse_tightness(value: &str) -> Option<(u8, f64)> {
+    let mut parts = value.splitn(2, ':');
+    let channel = parts.next()?.parse::<u8>().ok()?;
+    let tightness = parts.next()?.parse::<f64>().ok()?;
+    if (1..=16).contains(&channel) && (0.0..=1.0).contains(&tightness) {
+        Some((channel - 1, tightness))
+    } else {
+        None
+    }
+}
+
+// a track of a MIDI file and a MIDI channel (both from 1), like 2:10
+fn parse_track_channel(value: &str) -> Option<(usize, u8)> {
+    let mut parts = value.splitn(2, ':');
+    let track = parts.next()?.parse::<usize>().ok()?;
+    let channel = parts.next()?.parse::<u8>().ok()?;
+    if track >= 1 && (1..=16).contains(&channel) {
+        Some((track - 1, channel - 1))
+    } else {
+        None
+    }
+}
+
+// the lowest and highest MIDI notes of the comping chords, like 48-72
+fn parse_register(value: &str) -> Option<(u8, u8)> {
+    let mut parts = value.splitn(2, '-');
+    let low = parts.next()?.parse::<u8>().ok()?;
+    let high = parts.next()?.parse::<u8>().ok()?;
+    if low < high && high < 128 {
+        Some((low, high))
+    } else {
+        None
+    }
+}
+
+fn get_option_value<T: FromStr>(args_iter: &mut env::Args, option: &str, tool_name: &str) -> T {
+    match args_iter.next().map(|arg| arg.parse::<T>()) {
+        Some(Ok(value)) => value,
+        Some(Err(_)) => {
+            println!("Wrong value for {}", option);
+            std::process::exit(-1);
+        }
+        None => {
+            println!("Missing value for {}", option);
+            println!("Usage: {} {}", tool_name, USAGE);
+            std::process::exit(-1);
+        }
+    }
+}
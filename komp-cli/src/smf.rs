@@ -0,0 +1,479 @@
+/** Reading and writing Standard MIDI Files.
+
+Only what komp needs from a MIDI file is supported: the header, and the
+events of each track with their absolute times in ticks. SMPTE time
+divisions are not supported, and chunks other than the header and the
+tracks are skipped. Files are written without running status, and a track
+is ended for it when it has no end of track.
+*/
+use crate::play::{Event, TimedEvent};
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    Midi(Vec<u8>),
+    Meta(u8, Vec<u8>),
+    // a system exclusive message, or a packet of one with its escape status
+    SysEx(u8, Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SmfEvent {
+    pub tick: u32,
+    pub message: Message,
+}
+
+pub type Track = Vec<SmfEvent>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Smf {
+    pub format: u16,
+    pub ticks_per_quarter: u16,
+    pub tracks: Vec<Track>,
+}
+
+const META: u8 = 0xFF;
+const SYSEX: u8 = 0xF0;
+const SYSEX_ESCAPE: u8 = 0xF7;
+const END_OF_TRACK: u8 = 0x2F;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], String> {
+        if self.position + length > self.data.len() {
+            return Err(format!("unexpected end of data at {}", self.data.len()));
+        }
+        let bytes = &self.data[self.position..self.position + length];
+        self.position += length;
+        Ok(bytes)
+    }
+    fn byte(&mut self) -> Result<u8, String> {
+        self.bytes(1).map(|bytes| bytes[0])
+    }
+    fn peek(&self) -> Result<u8, String> {
+        self.data
+            .get(self.position)
+            .copied()
+            .ok_or_else(|| format!("unexpected end of data at {}", self.position))
+    }
+    fn u16(&mut self) -> Result<u16, String> {
+        self.bytes(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+    fn u32(&mut self) -> Result<u32, String> {
+        self.bytes(4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+    fn variable_length(&mut self) -> Result<u32, String> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let byte = self.byte()?;
+            value = (value << 7) | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(format!(
+            "variable length quantity too long at {}",
+            self.position
+        ))
+    }
+    fn chunk(&mut self, id: &[u8]) -> Result<Reader<'a>, String> {
+        let position = self.position;
+        match self.any_chunk()? {
+            (chunk_id, chunk) if chunk_id == id => Ok(chunk),
+            _ => Err(format!(
+                "expected {} chunk at {}",
+                String::from_utf8_lossy(id),
+                position
+            )),
+        }
+    }
+    // the next chunk with `id`, skipping the chunks of other kinds
+    fn find_chunk(&mut self, id: &[u8]) -> Result<Reader<'a>, String> {
+        loop {
+            let (chunk_id, chunk) = self.any_chunk()?;
+            if chunk_id == id {
+                return Ok(chunk);
+            }
+        }
+    }
+    fn any_chunk(&mut self) -> Result<(&'a [u8], Reader<'a>), String> {
+        let chunk_id = self.bytes(4)?;
+        let length = self.u32()? as usize;
+        let chunk = Reader {
+            data: self.bytes(length)?,
+            position: 0,
+        };
+        Ok((chunk_id, chunk))
+    }
+}
+
+// the number of data bytes following a channel message status
+fn data_length(status: u8) -> usize {
+    match status & 0xF0 {
+        0xC0 | 0xD0 => 1,
+        _ => 2,
+    }
+}
+
+fn parse_track(mut reader: Reader) -> Result<Track, String> {
+    let mut track = vec![];
+    let mut tick = 0u32;
+    let mut running_status = None;
+    while reader.position < reader.data.len() {
+        let position = reader.position;
+        tick = tick
+            .checked_add(reader.variable_length()?)
+            .ok_or_else(|| format!("track too long at {}", position))?;
+        let message = match reader.peek()? {
+            META => {
+                reader.byte()?;
+                let meta_type = reader.byte()?;
+                let length = reader.variable_length()? as usize;
+                Message::Meta(meta_type, reader.bytes(length)?.to_vec())
+            }
+            status @ SYSEX | status @ SYSEX_ESCAPE => {
+                reader.byte()?;
+                let length = reader.variable_length()? as usize;
+                Message::SysEx(status, reader.bytes(length)?.to_vec())
+            }
+            status => {
+                let status = if status & 0x80 != 0 {
+                    reader.byte()?;
+                    running_status = Some(status);
+                    status
+                } else {
+                    running_status
+                        .ok_or_else(|| format!("data byte without status at {}", reader.position))?
+                };
+                let mut data = vec![status];
+                data.extend_from_slice(reader.bytes(data_length(status))?);
+                Message::Midi(data)
+            }
+        };
+        let end_of_track = message == Message::Meta(END_OF_TRACK, vec![]);
+        track.push(SmfEvent { tick, message });
+        if end_of_track {
+            break;
+        }
+    }
+    Ok(track)
+}
+
+fn write_variable_length(value: u32, data: &mut Vec<u8>) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut rest = value >> 7;
+    while rest > 0 {
+        bytes.push((rest & 0x7F) as u8 | 0x80);
+        rest >>= 7;
+    }
+    data.extend(bytes.iter().rev());
+}
+
+fn write_chunk(id: &[u8], content: &[u8], data: &mut Vec<u8>) {
+    data.extend_from_slice(id);
+    data.extend_from_slice(&(content.len() as u32).to_be_bytes());
+    data.extend_from_slice(content);
+}
+
+fn write_track(track: &[SmfEvent]) -> Vec<u8> {
+    let mut data = vec![];
+    let mut tick = 0;
+    let mut ended = false;
+    for event in track {
+        write_variable_length(event.tick.saturating_sub(tick), &mut data);
+        tick = std::cmp::max(tick, event.tick);
+        match &event.message {
+            Message::Midi(bytes) => data.extend_from_slice(bytes),
+            Message::Meta(meta_type, bytes) => {
+                data.extend_from_slice(&[META, *meta_type]);
+                write_variable_length(bytes.len() as u32, &mut data);
+                data.extend_from_slice(bytes);
+            }
+            Message::SysEx(status, bytes) => {
+                data.push(*status);
+                write_variable_length(bytes.len() as u32, &mut data);
+                data.extend_from_slice(bytes);
+            }
+        }
+        if event.message == Message::Meta(END_OF_TRACK, vec![]) {
+            ended = true;
+            break;
+        }
+    }
+    if !ended {
+        data.extend_from_slice(&[0, META, END_OF_TRACK, 0]);
+    }
+    data
+}
+
+impl Smf {
+    pub fn parse(data: &[u8]) -> Result<Smf, String> {
+        let mut reader = Reader { data, position: 0 };
+        let mut header = reader.chunk(b"MThd")?;
+        let format = header.u16()?;
+        let track_count = header.u16()?;
+        let division = header.u16()?;
+        if division & 0x8000 != 0 {
+            return Err("SMPTE time division is not supported".to_string());
+        }
+        if division == 0 {
+            return Err("no ticks per quarter".to_string());
+        }
+        let mut tracks = vec![];
+        for _ in 0..track_count {
+            tracks.push(parse_track(reader.find_chunk(b"MTrk")?)?);
+        }
+        Ok(Smf {
+            format,
+            ticks_per_quarter: division,
+            tracks,
+        })
+    }
+
+    /// The file, as it is written.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut header = self.format.to_be_bytes().to_vec();
+        header.extend_from_slice(&(self.tracks.len() as u16).to_be_bytes());
+        header.extend_from_slice(&self.ticks_per_quarter.to_be_bytes());
+        let mut data = vec![];
+        write_chunk(b"MThd", &header, &mut data);
+        for track in &self.tracks {
+            write_chunk(b"MTrk", &write_track(track), &mut data);
+        }
+        data
+    }
+
+    pub fn read(path: &Path) -> Result<Smf, String> {
+        let data =
+            std::fs::read(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        Smf::parse(&data).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// The note-ons of all tracks, as (tick, channel, note, velocity) in time order.
+    pub fn note_ons(&self) -> Vec<(u32, u8, u8, u8)> {
+        let mut note_ons = vec![];
+        for track in &self.tracks {
+            for event in track {
+                if let Message::Midi(data) = &event.message {
+                    if data[0] & 0xF0 == 0x90 && data[2] > 0 {
+                        note_ons.push((event.tick, data[0] & 0x0F, data[1], data[2]));
+                    }
+                }
+            }
+        }
+        note_ons.sort();
+        note_ons
+    }
+
+    /// The notes of all tracks as note-ons and note-offs, in time order.
+    pub fn timed_events(&self) -> Vec<TimedEvent> {
+        let mut timed_events = vec![];
+        for track in &self.tracks {
+            for event in track {
+                if let Message::Midi(data) = &event.message {
+                    let (channel, note) = (data[0] & 0x0F, data[1]);
+                    let note_event = match data[0] & 0xF0 {
+                        0x90 if data[2] > 0 => Event::NoteOn {
+                            channel,
+                            note,
+                            velocity: data[2],
+                        },
+                        0x80 | 0x90 => Event::NoteOff {
+                            channel,
+                            note,
+                            velocity: data[2],
+                        },
+                        _ => continue,
+                    };
+                    timed_events.push(TimedEvent {
+                        timing: event.tick,
+                        event: note_event,
+                    });
+                }
+            }
+        }
+        timed_events.sort();
+        timed_events
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    // a chunk with its id and length
+    pub fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        chunk.extend_from_slice(data);
+        chunk
+    }
+
+    pub fn smf_data(ticks_per_quarter: u16, tracks: &[&[u8]]) -> Vec<u8> {
+        let mut header = vec![0, 1];
+        header.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+        header.extend_from_slice(&ticks_per_quarter.to_be_bytes());
+        let mut data = chunk(b"MThd", &header);
+        for track in tracks {
+            data.append(&mut chunk(b"MTrk", track));
+        }
+        data
+    }
+
+    #[test]
+    fn test_parse_track() {
+        let track = [
+            0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20, // tempo
+            0x00, 0x90, 0x3C, 0x64, // C4 on
+            0x81, 0x40, 0x3C, 0x00, // C4 off (running status) after 192 ticks
+            0x00, 0xFF, 0x2F, 0x00, // end of track
+        ];
+        let smf = Smf::parse(&smf_data(96, &[&track])).unwrap();
+        assert_eq!(smf.format, 1);
+        assert_eq!(smf.ticks_per_quarter, 96);
+        assert_eq!(
+            smf.tracks[0],
+            vec![
+                SmfEvent {
+                    tick: 0,
+                    message: Message::Meta(0x51, vec![0x07, 0xA1, 0x20])
+                },
+                SmfEvent {
+                    tick: 0,
+                    message: Message::Midi(vec![0x90, 0x3C, 0x64])
+                },
+                SmfEvent {
+                    tick: 192,
+                    message: Message::Midi(vec![0x90, 0x3C, 0x00])
+                },
+                SmfEvent {
+                    tick: 192,
+                    message: Message::Meta(0x2F, vec![])
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_note_ons() {
+        let drums = [0x00, 0x99, 0x24, 0x7F, 0x30, 0x99, 0x26, 0x50];
+        let bass = [0x18, 0x91, 0x28, 0x40, 0x0C, 0xC1, 0x21];
+        let smf = Smf::parse(&smf_data(96, &[&drums, &bass])).unwrap();
+        assert_eq!(
+            smf.note_ons(),
+            vec![(0, 9, 0x24, 0x7F), (24, 1, 0x28, 0x40), (48, 9, 0x26, 0x50)]
+        );
+    }
+
+    #[test]
+    fn test_timed_events() {
+        let track = [
+            0x00, 0x91, 0x3C, 0x64, 0x60, 0x3C, 0x00, 0x00, 0x81, 0x40, 0x10,
+        ];
+        let smf = Smf::parse(&smf_data(96, &[&track])).unwrap();
+        assert_eq!(
+            smf.timed_events(),
+            vec![
+                TimedEvent {
+                    timing: 0,
+                    event: Event::NoteOn {
+                        channel: 1,
+                        note: 0x3C,
+                        velocity: 0x64
+                    }
+                },
+                TimedEvent {
+                    timing: 96,
+                    event: Event::NoteOff {
+                        channel: 1,
+                        note: 0x3C,
+                        velocity: 0
+                    }
+                },
+                TimedEvent {
+                    timing: 96,
+                    event: Event::NoteOff {
+                        channel: 1,
+                        note: 0x40,
+                        velocity: 0x10
+                    }
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_bytes() {
+        let track = [
+            0x00, 0xFF, 0x03, 0x02, b'h', b'i', // name
+            0x00, 0xF0, 0x03, 0x7E, 0x09, 0x01, // sysex, in two packets
+            0x10, 0xF7, 0x02, 0x02, 0xF7, // its last packet
+            0x00, 0x90, 0x3C, 0x64, // C4 on
+            0x83, 0x60, 0x3C, 0x00, // C4 off (running status) after 480 ticks
+            0x00, 0xFF, 0x2F, 0x00, // end of track
+        ];
+        let data = smf_data(96, &[&track]);
+        let smf = Smf::parse(&data).unwrap();
+        let written = smf.to_bytes();
+        assert_eq!(
+            smf.tracks[0][2].message,
+            Message::SysEx(0xF7, vec![0x02, 0xF7])
+        );
+        assert_eq!(Smf::parse(&written), Ok(smf));
+        // with the status written again
+        assert_eq!(written.len(), data.len() + 1);
+
+        let unended = Smf {
+            format: 1,
+            ticks_per_quarter: 480,
+            tracks: vec![vec![SmfEvent {
+                tick: 0x4000,
+                message: Message::Meta(0x06, b"A".to_vec()),
+            }]],
+        };
+        assert_eq!(
+            unended.to_bytes(),
+            smf_data(
+                480,
+                &[&[0x81, 0x80, 0x00, 0xFF, 0x06, 0x01, b'A', 0x00, 0xFF, 0x2F, 0x00]]
+            )
+        );
+    }
+
+    #[test]
+    fn test_skips_unknown_chunks() {
+        let track = [0x00, 0x90, 0x3C, 0x64, 0x00, 0xFF, 0x2F, 0x00];
+        let mut data = smf_data(96, &[&track]);
+        let track_start = data.len() - track.len() - 8;
+        let alien = chunk(b"XFIH", &[1, 2, 3]);
+        data.splice(track_start..track_start, alien);
+        let smf = Smf::parse(&data).unwrap();
+        assert_eq!(smf, Smf::parse(&smf_data(96, &[&track])).unwrap());
+        // and the header comes first
+        let mut data = chunk(b"XFIH", &[]);
+        data.extend(smf_data(96, &[&track]));
+        assert!(Smf::parse(&data).is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Smf::parse(b"RIFF").is_err());
+        let truncated = smf_data(96, &[&[0x00, 0x90, 0x3C]]);
+        assert!(Smf::parse(&truncated).is_err());
+        let no_status = smf_data(96, &[&[0x00, 0x3C, 0x64]]);
+        assert!(Smf::parse(&no_status).is_err());
+        assert!(Smf::parse(&smf_data(0, &[&[]])).is_err());
+        // seventeen of the longest deltas are more ticks than a u32 holds
+        let mut too_long = vec![];
+        for _ in 0..17 {
+            too_long.extend_from_slice(&[0xFF, 0xFF, 0xFF, 0x7F, 0xB0, 0x7B, 0x00]);
+        }
+        let too_long = smf_data(96, &[&too_long]);
+        assert!(Smf::parse(&too_long).is_err());
+    }
+}
@@ -0,0 +1,827 @@
+/** Songs: chord charts, written in TOML, that komp plays hands-free.
+
+A song has a title, a tempo, a meter, sections of bars of chord symbols, and
+a form, the order the sections are played in, such as `"A A B A"`. Without
+a form, the sections are played once each, in the order they are written.
+
+The bars of a section are separated by `|`. A bar with a single chord plays
+it for the whole bar, and a bar with a chord or a `/` for each beat changes
+chords on the beats, `/` holding the chord before. Otherwise the chords of
+a bar share its beats evenly, `Dm7 G7` is two beats of each in 4/4. `%`
+repeats the bar before, and `N.C.` plays no chord. Slash chords are played
+as the chord above the slash.
+
+`|:` and `:|` repeat the bars between them once. `1.` to `9.` at the start
+of a bar begins that ending, which lasts until the next `:|`, or the end of
+the section for the last ending. The last time a section with
+`to-coda` in a bar is played, the song jumps from the end of that bar to the
+section named `Coda`, and ends there.
+
+```toml
+title = "Blues in F"
+tempo = 120
+meter = "4/4"
+form = "A A"
+
+[[section]]
+name = "A"
+bars = "F7 | Bb7 | F7 | Cm7 F7 | Bb7 | Bdim7 | F7 / D7 / | Gm7 C7"
+```
+*/
+use crate::ireal::find_ireal;
+use crate::musicxml::LeadSheet;
+use crate::pattern::{Meter, COMMON_TIME};
+use crate::play::{Event, TimedEvent};
+use crate::smf::Smf;
+use crate::tempo::{bpm, us_per_quarter_from_bpm};
+use crate::xf::read_song;
+use komp_core::Chord;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct SongFile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tempo: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    form: Option<String>,
+    #[serde(rename = "section", default)]
+    sections: Vec<SectionFile>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct SectionFile {
+    name: String,
+    bars: String,
+}
+
+/// A chord from a beat (from 0) of a bar, `None` for no chord.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Change {
+    pub beat: u8,
+    pub chord: Option<Chord>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Bar {
+    pub changes: Vec<Change>,
+    pub repeat_start: bool,
+    pub repeat_end: bool,
+    pub ending: Option<u8>,
+    pub to_coda: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SongSection {
+    pub name: String,
+    pub bars: Vec<Bar>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Song {
+    pub title: String,
+    pub us_per_quarter: Option<u32>,
+    pub meter: Option<Meter>,
+    pub sections: Vec<SongSection>,
+    /// The names of the sections, in the order they are played.
+    pub form: Vec<String>,
+}
+
+const BARLINES: [&str; 5] = [":|:", ":|", "|:", "||", "|"];
+// the most endings of a repeat
+const MAX_ENDING: u8 = 9;
+
+// barlines and words, a barline needs no space around it
+fn tokens(text: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let length = match BARLINES.iter().find(|barline| rest.starts_with(*barline)) {
+            Some(barline) => barline.len(),
+            None => match rest.find(|c: char| c.is_whitespace() || c == '|' || c == ':') {
+                // a colon on its own
+                Some(0) => 1,
+                Some(end) => end,
+                None => rest.len(),
+            },
+        };
+        tokens.push(&rest[..length]);
+        rest = rest[length..].trim_start();
+    }
+    tokens
+}
+
+fn parse_chord(symbol: &str) -> Result<Chord, String> {
+    // a slash chord is played as the chord above the slash, but 6/9 is a chord
+    symbol.parse().or_else(|e| match symbol.rfind('/') {
+        Some(slash) if slash > 0 => symbol[..slash].parse(),
+        _ => Err(e),
+    })
+}
+
+// the changes of a bar from its chords, `None` for each `/`
+fn lay_out(symbols: &[Option<Option<Chord>>], beats: u8) -> Result<Vec<Change>, String> {
+    let count = symbols.len();
+    let beats_each = if count == beats as usize {
+        1
+    } else if (beats as usize).is_multiple_of(count) {
+        beats as usize / count
+    } else {
+        return Err(format!("{} chords in a bar of {} beats", count, beats));
+    };
+    let mut changes = vec![];
+    for (index, symbol) in symbols.iter().enumerate() {
+        match symbol {
+            Some(chord) => changes.push(Change {
+                beat: (index * beats_each) as u8,
+                chord: *chord,
+            }),
+            None if index == 0 => return Err("a bar starts with /".to_string()),
+            None => (),
+        }
+    }
+    Ok(changes)
+}
+
+fn parse_bars(text: &str, beats: u8) -> Result<Vec<Bar>, String> {
+    let mut bars: Vec<Bar> = vec![];
+    let mut bar = Bar::default();
+    let mut symbols = vec![];
+    let mut ending = None;
+    let mut simile = false;
+    for token in tokens(text).into_iter().chain(std::iter::once("|")) {
+        let number = bars.len() + 1;
+        let error = |message: String| format!("bar {}: {}", number, message);
+        if BARLINES.contains(&token) {
+            if !symbols.is_empty() || simile {
+                if simile {
+                    if !symbols.is_empty() {
+                        return Err(error("% is not alone in its bar".to_string()));
+                    }
+                    bar.changes = match bars.last() {
+                        Some(last) => last.changes.clone(),
+                        None => return Err(error("% without a bar before".to_string())),
+                    };
+                } else {
+                    bar.changes = lay_out(&symbols, beats).map_err(error)?;
+                }
+                bar.ending = ending;
+                bars.push(std::mem::take(&mut bar));
+                symbols.clear();
+                simile = false;
+            } else if bar.ending.is_some() || bar.to_coda {
+                return Err(error("no chords in the bar".to_string()));
+            }
+            if token.starts_with(':') {
+                match bars.last_mut() {
+                    Some(last) => last.repeat_end = true,
+                    None => return Err(error(":| without a bar before".to_string())),
+                }
+            }
+            if token.ends_with(':') {
+                bar.repeat_start = true;
+            }
+            // an ending lasts until the repeat, or a new part
+            if token != "|" {
+                ending = None;
+            }
+            continue;
+        }
+        match token {
+            "%" => simile = true,
+            "/" => symbols.push(None),
+            "N.C." | "NC" => symbols.push(Some(None)),
+            "to-coda" => bar.to_coda = true,
+            _ if token.ends_with('.') && token[..token.len() - 1].parse::<u8>().is_ok() => {
+                if !symbols.is_empty() || simile {
+                    return Err(error(format!("{} is not at the start of the bar", token)));
+                }
+                match token[..token.len() - 1].parse::<u8>() {
+                    Ok(number) if (1..=MAX_ENDING).contains(&number) => ending = Some(number),
+                    _ => return Err(error(format!("wrong ending: {}", token))),
+                }
+                bar.ending = ending;
+            }
+            _ => symbols.push(Some(Some(parse_chord(token).map_err(error)?))),
+        }
+    }
+    if bars.is_empty() {
+        return Err("no bars".to_string());
+    }
+    Ok(bars)
+}
+
+fn chord_text(chord: Option<Chord>) -> String {
+    match chord {
+        Some(chord) => chord.to_string(),
+        None => "N.C.".to_string(),
+    }
+}
+
+/// The changes of a bar from the chords placed on its beats, the last one
+/// placed on a beat kept, and `before`, the chord before the bar, going on
+/// until its first change.
+pub fn bar_changes(
+    placed: impl IntoIterator<Item = (u8, Option<Chord>)>,
+    before: Option<Chord>,
+) -> Vec<Change> {
+    let mut changes: Vec<Change> = vec![];
+    for (beat, chord) in placed {
+        changes.retain(|change| change.beat != beat);
+        changes.push(Change { beat, chord });
+    }
+    if changes.first().is_none_or(|change| change.beat > 0) {
+        changes.insert(
+            0,
+            Change {
+                beat: 0,
+                chord: before,
+            },
+        );
+    }
+    changes
+}
+
+/// The changes of a bar as they are read, each chord written by
+/// `chord_text`: evenly over the bar when they can be, otherwise with a
+/// chord or a `/` for each beat.
+pub fn changes_text(
+    changes: &[Change],
+    beats: u8,
+    chord_text: impl Fn(Option<Chord>) -> String,
+) -> String {
+    let count = changes.len();
+    let even = (beats as usize).is_multiple_of(count)
+        && changes
+            .iter()
+            .enumerate()
+            .all(|(index, change)| change.beat as usize == index * beats as usize / count);
+    if even {
+        return changes
+            .iter()
+            .map(|change| chord_text(change.chord))
+            .collect::<Vec<String>>()
+            .join(" ");
+    }
+    (0..beats)
+        .map(
+            |beat| match changes.iter().find(|change| change.beat == beat) {
+                Some(change) => chord_text(change.chord),
+                None => "/".to_string(),
+            },
+        )
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn bars_text(bars: &[Bar], beats: u8) -> String {
+    let mut text = String::new();
+    let mut previous: Option<&Bar> = None;
+    for bar in bars {
+        let barline = match (previous.is_some_and(|bar| bar.repeat_end), bar.repeat_start) {
+            (true, true) => ":|:",
+            (true, false) => ":|",
+            (false, true) => "|:",
+            // the end of an ending without a repeat
+            _ if previous.is_some_and(|previous| {
+                previous.ending.is_some() && previous.ending != bar.ending
+            }) =>
+            {
+                "||"
+            }
+            _ if previous.is_some() => "|",
+            _ => "",
+        };
+        text += barline;
+        if !text.is_empty() {
+            text += " ";
+        }
+        if let Some(ending) = bar.ending {
+            if previous.is_none_or(|previous| previous.ending != bar.ending || previous.repeat_end)
+            {
+                text += &format!("{}. ", ending);
+            }
+        }
+        match previous {
+            Some(previous) if previous.changes == bar.changes => text += "%",
+            _ => text += &changes_text(&bar.changes, beats, chord_text),
+        }
+        if bar.to_coda {
+            text += " to-coda";
+        }
+        text += " ";
+        previous = Some(bar);
+    }
+    if previous.is_some_and(|bar| bar.repeat_end) {
+        text += ":|";
+    }
+    text.trim_end().to_string()
+}
+
+// the bars of a section in the order they are played, through its repeats
+// and endings
+fn play_order(bars: &[Bar]) -> Vec<&Bar> {
+    let mut order = vec![];
+    let mut start = 0;
+    let mut pass = 1;
+    let mut index = 0;
+    while index < bars.len() {
+        let bar = &bars[index];
+        if bar.repeat_start && index != start {
+            start = index;
+            pass = 1;
+        }
+        if bar.ending.is_none_or(|ending| ending == pass) {
+            order.push(bar);
+            if bar.repeat_end {
+                // an ending goes back for the next one, a plain repeat once
+                match bar.ending {
+                    Some(ending) => pass = ending + 1,
+                    None if pass == 1 => pass = 2,
+                    None => {
+                        start = index + 1;
+                        pass = 1;
+                        index += 1;
+                        continue;
+                    }
+                }
+                index = start;
+                continue;
+            }
+        }
+        index += 1;
+    }
+    order
+}
+
+impl Song {
+    #[cfg(test)]
+    pub fn parse(text: &str) -> Result<Song, String> {
+        Song::parse_in(text, COMMON_TIME)
+    }
+
+    /// The song of a chart, its bars laid out in `meter` unless it has a
+    /// meter of its own.
+    pub fn parse_in(text: &str, meter: Meter) -> Result<Song, String> {
+        let file: SongFile = toml::from_str(text).map_err(|e| e.to_string())?;
+        let us_per_quarter = match file.tempo {
+            Some(bpm) if bpm.is_finite() && bpm > 0.0 => Some(us_per_quarter_from_bpm(bpm)),
+            Some(bpm) => return Err(format!("wrong tempo: {}", bpm)),
+            None => None,
+        };
+        let (meter, file_meter) = match file.meter {
+            Some(file_meter) => {
+                let file_meter: Meter = file_meter.parse()?;
+                (file_meter, Some(file_meter))
+            }
+            None => (meter, None),
+        };
+        let beats = meter.beats;
+        if file.sections.is_empty() {
+            return Err("no sections in the song".to_string());
+        }
+        let sections = file
+            .sections
+            .into_iter()
+            .map(|section| {
+                let bars = parse_bars(&section.bars, beats)
+                    .map_err(|e| format!("section {}, {}", section.name, e))?;
+                Ok(SongSection {
+                    name: section.name,
+                    bars,
+                })
+            })
+            .collect::<Result<Vec<SongSection>, String>>()?;
+        let form: Vec<String> = match file.form {
+            Some(form) => form.split_whitespace().map(str::to_string).collect(),
+            None => sections
+                .iter()
+                .map(|section| section.name.clone())
+                .filter(|name| !name.eq_ignore_ascii_case("coda"))
+                .collect(),
+        };
+        if form.is_empty() {
+            return Err("no sections in the form".to_string());
+        }
+        for name in &form {
+            if !sections.iter().any(|section| section.name == *name) {
+                return Err(format!("no section {} in the song", name));
+            }
+        }
+        Ok(Song {
+            title: file.title.unwrap_or_default(),
+            us_per_quarter,
+            meter: file_meter,
+            sections,
+            form,
+        })
+    }
+
+    /// The song of a chart in TOML, laid out in `meter` unless it has a
+    /// meter of its own, the first song of an iReal Pro link (see `ireal`),
+    /// the chord symbols of a MusicXML score (see `musicxml`), or the XF
+    /// chords of a MIDI file (see `xf`). The others are in 4/4 unless they
+    /// tell otherwise, as they are in their own programs.
+    pub fn read(path: &Path, meter: Meter) -> Result<Song, String> {
+        let data = std::fs::read(path).map_err(|e| e.to_string())?;
+        let file_title = || {
+            path.file_stem()
+                .map_or(String::new(), |stem| stem.to_string_lossy().to_string())
+        };
+        let in_common_time = |mut song: Song| {
+            song.meter.get_or_insert(COMMON_TIME);
+            song
+        };
+        if data.starts_with(b"MThd") {
+            return read_song(&Smf::parse(&data)?, &file_title()).map(in_common_time);
+        }
+        let text = String::from_utf8(data).map_err(|e| e.to_string())?;
+        if text.contains("irealb://") || text.contains("irealbook://") {
+            return find_ireal(&text, false).map(|mut songs| in_common_time(songs.remove(0)));
+        }
+        if text.contains("<score-partwise") {
+            // the ticks only place the chords on their beats
+            let sheet = LeadSheet::parse(&text, 96)?;
+            let title = sheet.title.clone().unwrap_or_else(file_title);
+            return Ok(in_common_time(sheet.to_song(&title)));
+        }
+        Song::parse_in(&text, meter)
+    }
+
+    /// The song written in TOML, as it would be read.
+    pub fn to_toml(&self) -> Result<String, String> {
+        let beats = self.meter.unwrap_or(COMMON_TIME).beats;
+        let file = SongFile {
+            title: Some(self.title.clone()).filter(|title| !title.is_empty()),
+            // to a hundredth of a BPM, as it was probably written
+            tempo: self
+                .us_per_quarter
+                .map(|us_per_quarter| (bpm(us_per_quarter) * 100.0).round() / 100.0),
+            meter: self.meter.map(|meter| meter.to_string()),
+            form: Some(self.form.join(" ")),
+            sections: self
+                .sections
+                .iter()
+                .map(|section| SectionFile {
+                    name: section.name.clone(),
+                    bars: bars_text(&section.bars, beats),
+                })
+                .collect(),
+        };
+        toml::to_string(&file).map_err(|e| e.to_string())
+    }
+
+    fn section(&self, name: &str) -> Option<&SongSection> {
+        self.sections.iter().find(|section| section.name == name)
+    }
+
+    /// The changes of each bar of the song, from the first bar to the last,
+    /// through the form, the repeats and the endings.
+    pub fn progression(&self) -> Vec<Vec<Change>> {
+        let coda = self
+            .sections
+            .iter()
+            .find(|section| section.name.eq_ignore_ascii_case("coda"));
+        let mut progression = vec![];
+        for (index, name) in self.form.iter().enumerate() {
+            let order = match self.section(name) {
+                Some(section) => play_order(&section.bars),
+                None => continue,
+            };
+            let last_time = !self.form[index + 1..].contains(name);
+            let jump = match coda {
+                Some(coda) if last_time && coda.name != *name => {
+                    order.iter().rposition(|bar| bar.to_coda)
+                }
+                _ => None,
+            };
+            match (jump, coda) {
+                (Some(jump), Some(coda)) => {
+                    progression.extend(order[..=jump].iter().map(|bar| bar.changes.clone()));
+                    progression
+                        .extend(play_order(&coda.bars).iter().map(|bar| bar.changes.clone()));
+                    break;
+                }
+                _ => progression.extend(order.iter().map(|bar| bar.changes.clone())),
+            }
+        }
+        progression
+    }
+}
+
+// the pattern up to `length`, the notes still sounding let go a tick
+// before, so that the same notes may start again there
+fn cut(timed_events: Vec<TimedEvent>, length: u32) -> Vec<TimedEvent> {
+    let mut sounding = vec![];
+    let mut cut_events = vec![];
+    for TimedEvent { timing, event } in timed_events {
+        match event {
+            Event::NoteOn { channel, note, .. } if timing < length => {
+                sounding.push((channel, note));
+                cut_events.push(TimedEvent { timing, event });
+            }
+            Event::NoteOff { channel, note, .. } => {
+                if let Some(index) = sounding.iter().position(|n| *n == (channel, note)) {
+                    sounding.remove(index);
+                    cut_events.push(TimedEvent {
+                        timing: timing.min(length - 1),
+                        event,
+                    });
+                }
+            }
+            _ => (),
+        }
+    }
+    cut_events
+}
+
+/// The pattern of a whole progression: each chord is played by `play`, with
+/// the chord that comes after it, from its change to the next change.
+pub fn create_song_pattern(
+    ticks_per_quarter: u32,
+    meter: Meter,
+    progression: &[Vec<Change>],
+    mut play: impl FnMut(Chord, Chord) -> Vec<TimedEvent>,
+) -> Vec<TimedEvent> {
+    let ticks_per_beat = meter.ticks_per_beat(ticks_per_quarter);
+    let bar_ticks = meter.ticks_per_bar(ticks_per_quarter);
+    // the start, the length and the chord of each change
+    let mut spans = vec![];
+    for (bar, changes) in progression.iter().enumerate() {
+        for (index, change) in changes.iter().enumerate() {
+            let end = changes
+                .get(index + 1)
+                .map_or(meter.beats, |next| next.beat)
+                .min(meter.beats);
+            if change.beat < end {
+                spans.push((
+                    bar as u32 * bar_ticks + change.beat as u32 * ticks_per_beat,
+                    (end - change.beat) as u32 * ticks_per_beat,
+                    change.chord,
+                ));
+            }
+        }
+    }
+    let mut timed_events = vec![];
+    for (index, (start, length, chord)) in spans.iter().enumerate() {
+        if let Some(chord) = chord {
+            // the song loops, the first chord comes after the last
+            let next = spans[index + 1..]
+                .iter()
+                .chain(spans[..=index].iter())
+                .find_map(|(_, _, chord)| *chord)
+                .unwrap_or(*chord);
+            let mut pattern = play(*chord, next);
+            pattern.sort();
+            timed_events.extend(cut(pattern, *length).into_iter().map(
+                |TimedEvent { timing, event }| TimedEvent {
+                    timing: timing + start,
+                    event,
+                },
+            ));
+        }
+    }
+    timed_events.sort();
+    timed_events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::create_note;
+    use komp_core::*;
+
+    fn change(beat: u8, chord: Chord) -> Change {
+        Change {
+            beat,
+            chord: Some(chord),
+        }
+    }
+
+    fn chart(form: &str, sections: &[(&str, &str)]) -> Result<Song, String> {
+        let mut text = format!("title = \"Test\"\nform = \"{}\"\n", form);
+        for (name, bars) in sections {
+            text += &format!("[[section]]\nname = \"{}\"\nbars = \"{}\"\n", name, bars);
+        }
+        Song::parse(&text)
+    }
+
+    // the chord on the first beat of each bar
+    fn roots(song: &Song) -> Vec<String> {
+        song.progression()
+            .iter()
+            .map(|changes| match changes[0].chord {
+                Some(chord) => chord.to_string(),
+                None => "N.C.".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_song() {
+        let song = Song::parse(
+            r#"
+title = "Blues in F"
+tempo = 120
+meter = "3/4"
+
+[[section]]
+name = "A"
+bars = "|F7 | Bb7 / C7 | Cm7 F7 F7 | % | N.C. | F/A|"
+"#,
+        )
+        .unwrap();
+        assert_eq!(song.title, "Blues in F");
+        assert_eq!(song.us_per_quarter, Some(500_000));
+        assert_eq!(song.meter, Some(Meter::new(3, 4)));
+        assert_eq!(song.form, vec!["A"]);
+        let bars: Vec<Vec<Change>> = song.sections[0]
+            .bars
+            .iter()
+            .map(|bar| bar.changes.clone())
+            .collect();
+        assert_eq!(
+            bars,
+            vec![
+                vec![change(0, Chord::Major7(F_KEY))],
+                vec![
+                    change(0, Chord::Major7(ASHARP_KEY)),
+                    change(2, Chord::Major7(C_KEY))
+                ],
+                vec![
+                    change(0, Chord::Minor7(C_KEY)),
+                    change(1, Chord::Major7(F_KEY)),
+                    change(2, Chord::Major7(F_KEY))
+                ],
+                vec![
+                    change(0, Chord::Minor7(C_KEY)),
+                    change(1, Chord::Major7(F_KEY)),
+                    change(2, Chord::Major7(F_KEY))
+                ],
+                vec![Change {
+                    beat: 0,
+                    chord: None
+                }],
+                vec![change(0, Chord::Major(F_KEY))],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_in() {
+        let text = "form = \"A\"\n[[section]]\nname = \"A\"\nbars = \"C D E\"";
+        assert!(Song::parse(text).is_err());
+        let song = Song::parse_in(text, Meter::new(3, 4)).unwrap();
+        assert_eq!(song.meter, None);
+        assert_eq!(song.progression()[0].len(), 3);
+        let text = format!("meter = \"6/8\"\n{}", text);
+        assert!(Song::parse_in(&text, Meter::new(3, 4)).is_ok());
+    }
+
+    #[test]
+    fn test_bar_changes() {
+        let c = Some(Chord::Major(C_KEY));
+        let f = Some(Chord::Major(F_KEY));
+        assert_eq!(
+            bar_changes(vec![(2, f), (2, c)], f),
+            vec![
+                change(0, Chord::Major(F_KEY)),
+                change(2, Chord::Major(C_KEY))
+            ]
+        );
+        assert_eq!(
+            bar_changes(vec![(0, c)], f),
+            vec![change(0, Chord::Major(C_KEY))]
+        );
+        assert_eq!(
+            bar_changes(vec![], None),
+            vec![Change {
+                beat: 0,
+                chord: None
+            }]
+        );
+    }
+
+    #[test]
+    fn test_chords_share_the_bar() {
+        assert!(chart("A", &[("A", "C6/9 Am7 | Dm7 G7 C")]).is_err());
+        let song = chart("A", &[("A", "C6/9 Am7")]).unwrap();
+        assert_eq!(
+            song.sections[0].bars[0].changes,
+            vec![
+                change(0, Chord::Major6_9(C_KEY)),
+                change(2, Chord::Minor7(A_KEY))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_form() {
+        let song = chart("A A B A", &[("A", "C | G"), ("B", "F")]).unwrap();
+        assert_eq!(roots(&song), ["C", "G", "C", "G", "F", "C", "G"]);
+    }
+
+    #[test]
+    fn test_repeats_and_endings() {
+        let song = chart("A", &[("A", "|: C | D :|: E |1. F :|2. G | A |: Bb :| B")]).unwrap();
+        assert_eq!(
+            roots(&song),
+            ["C", "D", "C", "D", "E", "F", "E", "G", "A", "Bb", "Bb", "B"]
+        );
+        let song = chart("A", &[("A", "C |1. D | E :|2. F || G")]).unwrap();
+        assert_eq!(roots(&song), ["C", "D", "E", "C", "F", "G"]);
+    }
+
+    #[test]
+    fn test_coda() {
+        let song = chart(
+            "A B A",
+            &[("A", "C | D to-coda | E"), ("B", "F"), ("Coda", "G")],
+        )
+        .unwrap();
+        assert_eq!(roots(&song), ["C", "D", "E", "F", "C", "D", "G"]);
+    }
+
+    #[test]
+    fn test_example() {
+        let song = Song::parse(include_str!("../../songs/blues-in-f.toml")).unwrap();
+        // the head twice through its endings, a chorus to solo over, and the
+        // head again up to the coda
+        assert_eq!(song.progression().len(), 24 + 12 + 23 + 3);
+    }
+
+    #[test]
+    fn test_to_toml() {
+        let song = chart(
+            "A B A",
+            &[
+                ("A", "|: C | D / E / |1. F :|2. G || % | C6/9 to-coda"),
+                ("B", "|: N.C. | C D E / :|: F / / G :|"),
+                ("Coda", "C"),
+            ],
+        )
+        .unwrap();
+        let text = song.to_toml().unwrap();
+        assert!(text.contains("|: C | D E | 1. F :| 2. G || % | C6/9 to-coda"));
+        assert_eq!(Song::parse(&text), Ok(song));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(chart("A", &[("A", "")]).is_err());
+        assert!(chart("A", &[("A", "% | C")]).is_err());
+        assert!(chart("A", &[("A", "C % | D")]).is_err());
+        assert!(chart("A", &[("A", "/ C | D")]).is_err());
+        assert!(chart("A", &[("A", "C | Xm7")]).is_err());
+        assert!(chart("A", &[("A", "C | D 1.")]).is_err());
+        assert!(chart("A", &[("A", "|: C |255. D :|")]).is_err());
+        assert!(chart("A", &[("A", "|: C |10. D :|")]).is_err());
+        assert!(chart("A B", &[("A", "C")]).is_err());
+        assert!(Song::parse("form = \"A\"").is_err());
+    }
+
+    #[test]
+    fn test_song_pattern() {
+        let progression = vec![
+            vec![
+                change(0, Chord::Major(C_KEY)),
+                change(2, Chord::Major(F_KEY)),
+            ],
+            vec![Change {
+                beat: 0,
+                chord: None,
+            }],
+        ];
+        let mut played = vec![];
+        let timed_events = create_song_pattern(96, COMMON_TIME, &progression, |chord, next| {
+            played.push((chord, next));
+            // a whole bar of the root
+            let (on, off) = create_note(0, 4 * 96, 0, chord.key().0 + 60, 100);
+            vec![off, on]
+        });
+        assert_eq!(
+            played,
+            vec![
+                (Chord::Major(C_KEY), Chord::Major(F_KEY)),
+                (Chord::Major(F_KEY), Chord::Major(C_KEY))
+            ]
+        );
+        let timings: Vec<(u32, u8)> = timed_events
+            .iter()
+            .map(|te| match te.event {
+                Event::NoteOn { note, .. } | Event::NoteOff { note, .. } => (te.timing, note),
+            })
+            .collect();
+        // each chord for its two beats, and nothing in the bar without a chord
+        assert_eq!(
+            timings,
+            vec![(0, NOTE_C4), (191, NOTE_C4), (192, NOTE_F4), (383, NOTE_F4)]
+        );
+    }
+}
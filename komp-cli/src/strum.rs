@@ -0,0 +1,183 @@
+/** Guitar-style strumming of the chord.
+
+A strum pattern is a grid of steps over a bar, written like `D-DU-UDU`:
+`D` is a down strum, from the lowest note up, `U` an up strum, from the
+highest note down, and `-` lets the chord ring. The notes of a strum are
+staggered by a few milliseconds, and ring until the next strum.
+*/
+use crate::pattern::{create_note, Meter};
+use crate::play::{TimedEvent, NS_PER_MS, NS_PER_US};
+
+const DOWN_VELOCITY: u8 = 110;
+// up strums are usually lighter
+const UP_VELOCITY: u8 = 90;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stroke {
+    Down,
+    Up,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Strummer {
+    grid: Vec<Option<Stroke>>,
+    // the time between two strings, in nanoseconds
+    spread: u64,
+}
+
+impl std::str::FromStr for Strummer {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Strummer, String> {
+        let grid = s
+            .trim()
+            .chars()
+            .map(|c| match c {
+                'D' | 'd' => Ok(Some(Stroke::Down)),
+                'U' | 'u' => Ok(Some(Stroke::Up)),
+                '-' | '.' => Ok(None),
+                _ => Err(format!("wrong strum pattern: {}", s)),
+            })
+            .collect::<Result<Vec<Option<Stroke>>, String>>()?;
+        if grid.iter().all(Option::is_none) {
+            return Err(format!("no strums in pattern: {}", s));
+        }
+        Ok(Strummer::new(grid))
+    }
+}
+
+impl Strummer {
+    /// Strums on a grid of steps over a bar, 12 ms between the strings.
+    pub fn new(grid: Vec<Option<Stroke>>) -> Strummer {
+        Strummer {
+            grid,
+            spread: 12 * NS_PER_MS,
+        }
+    }
+    pub fn with_spread(mut self, spread: u64) -> Strummer {
+        self.spread = spread;
+        self
+    }
+
+    /// A bar of strums of `notes`, on the comping channel. The strings are
+    /// staggered in ticks, for the tempo of `us_per_quarter`.
+    pub fn create_bar(
+        &self,
+        ticks_per_quarter: u32,
+        meter: Meter,
+        notes: &[u8],
+        us_per_quarter: u32,
+    ) -> Vec<TimedEvent> {
+        let bar_ticks = meter.ticks_per_bar(ticks_per_quarter);
+        // a grid finer than the ticks has a step a tick long, and the
+        // steps past the end of the bar are not played
+        let step_ticks = std::cmp::max(1, bar_ticks / self.grid.len() as u32);
+        let ns_per_tick = us_per_quarter as u64 * NS_PER_US / ticks_per_quarter as u64;
+        let stagger = (self.spread as f64 / ns_per_tick as f64).round() as u32;
+        let strums: Vec<(u32, Stroke)> = self
+            .grid
+            .iter()
+            .enumerate()
+            .filter_map(|(step, stroke)| stroke.map(|stroke| (step as u32 * step_ticks, stroke)))
+            .filter(|(timing, _)| *timing < bar_ticks)
+            .collect();
+        let mut notes = notes.to_vec();
+        notes.sort();
+        let mut timed_events = vec![];
+        for (index, (timing, stroke)) in strums.iter().enumerate() {
+            // ring until the next strum, or the end of the bar
+            let end = strums.get(index + 1).map_or(bar_ticks, |next| next.0);
+            let (order, velocity): (Vec<u8>, u8) = match stroke {
+                Stroke::Down => (notes.clone(), DOWN_VELOCITY),
+                Stroke::Up => (notes.iter().rev().copied().collect(), UP_VELOCITY),
+            };
+            for (string, note) in order.iter().enumerate() {
+                let start = (timing + string as u32 * stagger).min(end - 1);
+                let (on, off) = create_note(start, end - start, 0, *note, velocity);
+                timed_events.push(on);
+                timed_events.push(off);
+            }
+        }
+        timed_events.sort();
+        timed_events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::COMMON_TIME;
+    use crate::play::Event;
+    use komp_core::*;
+
+    #[test]
+    fn test_parse_pattern() {
+        let strummer: Strummer = "D-DU".parse().unwrap();
+        assert_eq!(
+            strummer.grid,
+            vec![
+                Some(Stroke::Down),
+                None,
+                Some(Stroke::Down),
+                Some(Stroke::Up)
+            ]
+        );
+        assert!("D-X".parse::<Strummer>().is_err());
+        assert!("----".parse::<Strummer>().is_err());
+    }
+
+    #[test]
+    fn test_strums() {
+        // at 120 BPM and 96 ticks per quarter, a tick is about 5.2 ms
+        let strummer = "D-DU-UDU"
+            .parse::<Strummer>()
+            .unwrap()
+            .with_spread(10 * NS_PER_MS);
+        let notes = [NOTE_E3, NOTE_C3, NOTE_G3];
+        let timed_events = strummer.create_bar(96, COMMON_TIME, &notes, 500_000);
+        let note_ons: Vec<(u32, u8, u8)> = timed_events
+            .iter()
+            .filter_map(|te| match te.event {
+                Event::NoteOn { note, velocity, .. } => Some((te.timing, note, velocity)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(note_ons.len(), 6 * 3);
+        // down from the lowest string
+        assert_eq!(
+            note_ons[..3],
+            [(0, NOTE_C3, 110), (2, NOTE_E3, 110), (4, NOTE_G3, 110)]
+        );
+        // up from the highest string, on the fourth eighth
+        assert_eq!(
+            note_ons[6..9],
+            [(144, NOTE_G3, 90), (146, NOTE_E3, 90), (148, NOTE_C3, 90)]
+        );
+        // the chord rings until the next strum
+        let c_off = timed_events
+            .iter()
+            .find(|te| {
+                te.event
+                    == Event::NoteOff {
+                        channel: 0,
+                        note: NOTE_C3,
+                        velocity: 64,
+                    }
+            })
+            .unwrap();
+        assert_eq!(c_off.timing, 96);
+    }
+
+    #[test]
+    fn test_grid_finer_than_the_ticks() {
+        // a bar of 4/4 at 2 ticks per quarter is 8 ticks
+        let strummer: Strummer = "DUDUDUDUDU".parse().unwrap();
+        let timed_events = strummer.create_bar(2, COMMON_TIME, &[NOTE_C3], 500_000);
+        let note_ons: Vec<u32> = timed_events
+            .iter()
+            .filter(|te| matches!(te.event, Event::NoteOn { .. }))
+            .map(|te| te.timing)
+            .collect();
+        assert_eq!(note_ons, (0..8).collect::<Vec<u32>>());
+        assert!(timed_events.iter().all(|te| te.timing <= 8));
+    }
+}
@@ -0,0 +1,860 @@
+/** Accompaniment styles, written in TOML.
+
+A style has a tempo, a meter and sections, each a loop of some bars with
+tracks. A track plays on a MIDI channel (1 to 16) and may pick a General
+MIDI instrument (1 to 128). Its notes are written in the chord change DSL
+(see `detect`), with `unit` steps to a whole note, as a drum grid (see
+`drums`), or as a clip of notes over a `source_chord`, each note
+`[tick, length, note, velocity]` with `resolution` ticks to a quarter.
+The source chord is a chord symbol such as `Cmaj7`, as given to
+`import-pattern --source-chord`, and is written back as one.
+The notes of a track are transposed to the key of the chord that is
+played, and the chord tones of a clip follow the chord, a third stays a
+third. Tracks with `transpose = "none"` are not transposed, which is the
+default for drums and for the tenth channel.
+
+```toml
+name = "Two-beat"
+tempo = 100
+meter = "4/4"
+
+[[section]]
+name = "A"
+bars = 1
+
+[[section.track]]
+name = "piano"
+channel = 1
+instrument = 1
+unit = 8
+notes = "[- C - C - C - C] [- E - E - E - E] [- G - G - G - G]"
+
+[[section.track]]
+name = "drums"
+channel = 10
+drums = "BD: x...x... SN: ..x...x. HH: xxxxxxxx"
+```
+*/
+use crate::detect::interpret_dsl;
+use crate::drums::{DrumGrid, DRUM_CHANNEL};
+use crate::pattern::{create_note, Meter};
+use crate::play::{Event, TimedEvent};
+use crate::tempo::bpm;
+use crate::tempo::us_per_quarter_from_bpm;
+use komp_core::{Chord, OCTAVE_STEPS};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct StyleFile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tempo: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meter: Option<String>,
+    #[serde(rename = "section", default)]
+    sections: Vec<SectionFile>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct SectionFile {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bars: Option<u32>,
+    #[serde(rename = "track", default)]
+    tracks: Vec<TrackFile>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct TrackFile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    channel: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instrument: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transpose: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    notes: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    drums: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_chord: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resolution: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clip: Option<Vec<[u32; 4]>>,
+}
+
+/// Whether the notes of a track follow the key of the chord.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transpose {
+    Chord,
+    Fixed,
+}
+
+impl std::fmt::Display for Transpose {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Transpose::Chord => write!(f, "chord"),
+            Transpose::Fixed => write!(f, "none"),
+        }
+    }
+}
+
+impl std::str::FromStr for Transpose {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Transpose, String> {
+        match s.trim() {
+            "chord" => Ok(Transpose::Chord),
+            "none" => Ok(Transpose::Fixed),
+            _ => Err(format!("wrong transposition: {}", s)),
+        }
+    }
+}
+
+/// A note of a clip, in the ticks of the clip.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClipNote {
+    pub tick: u32,
+    pub length: u32,
+    pub note: u8,
+    pub velocity: u8,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Content {
+    /// In the chord change DSL, with `unit` steps to a whole note.
+    Notes { dsl: String, unit: u8 },
+    /// A drum grid, and how it was written.
+    Drums { grid: DrumGrid, text: String },
+    /// Notes played over `source`, with `resolution` ticks to a quarter.
+    Clip {
+        source: Chord,
+        resolution: u32,
+        notes: Vec<ClipNote>,
+    },
+}
+
+impl Content {
+    // drums and the tenth channel are not transposed, unless asked to
+    fn default_transpose(&self, channel: u8) -> Transpose {
+        match self {
+            Content::Drums { .. } => Transpose::Fixed,
+            _ if channel == DRUM_CHANNEL => Transpose::Fixed,
+            _ => Transpose::Chord,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Track {
+    pub name: String,
+    // from 0, as in the MIDI messages
+    pub channel: u8,
+    pub program: Option<u8>,
+    pub transpose: Transpose,
+    pub content: Content,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Section {
+    pub name: String,
+    pub bars: u32,
+    pub tracks: Vec<Track>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Style {
+    pub name: String,
+    pub us_per_quarter: Option<u32>,
+    pub meter: Option<Meter>,
+    pub sections: Vec<Section>,
+}
+
+// the shortest step of the notes is a 64th note
+const MAX_UNIT: u8 = 64;
+
+fn parse_track(index: usize, track: TrackFile) -> Result<Track, String> {
+    let name = track.name.unwrap_or(format!("track {}", index + 1));
+    let error = |message: String| format!("{}: {}", name, message);
+    if !(1..=16).contains(&track.channel) {
+        return Err(error(format!("wrong channel: {}", track.channel)));
+    }
+    let program = match track.instrument {
+        Some(instrument) if (1..=128).contains(&instrument) => Some(instrument - 1),
+        Some(instrument) => return Err(error(format!("wrong instrument: {}", instrument))),
+        None => None,
+    };
+    let content = match (track.notes, track.drums, track.clip) {
+        (Some(dsl), None, None) => {
+            let unit = track.unit.unwrap_or(4);
+            if !(1..=MAX_UNIT).contains(&unit) {
+                return Err(error(format!("wrong unit: {}", unit)));
+            }
+            interpret_dsl(&dsl, 0, 1, 4).map_err(|e| error(e.to_string()))?;
+            Content::Notes { dsl, unit }
+        }
+        (None, Some(text), None) => Content::Drums {
+            grid: text.parse().map_err(error)?,
+            text,
+        },
+        (None, None, Some(clip)) => {
+            let source = match track.source_chord {
+                Some(source) => source.parse().map_err(error)?,
+                None => return Err(error("a clip needs a source_chord".to_string())),
+            };
+            let resolution = match track.resolution {
+                Some(resolution) if resolution > 0 => resolution,
+                _ => return Err(error("a clip needs a resolution".to_string())),
+            };
+            let notes = clip
+                .iter()
+                .map(|&[tick, length, note, velocity]| {
+                    if note > 127 || !(1..=127).contains(&velocity) || length == 0 {
+                        return Err(error(format!(
+                            "wrong clip note: [{}, {}, {}, {}]",
+                            tick, length, note, velocity
+                        )));
+                    }
+                    Ok(ClipNote {
+                        tick,
+                        length,
+                        note: note as u8,
+                        velocity: velocity as u8,
+                    })
+                })
+                .collect::<Result<Vec<ClipNote>, String>>()?;
+            Content::Clip {
+                source,
+                resolution,
+                notes,
+            }
+        }
+        _ => return Err(error("needs one of notes, drums or clip".to_string())),
+    };
+    let transpose = match track.transpose {
+        Some(transpose) => transpose.parse().map_err(error)?,
+        None => content.default_transpose(track.channel - 1),
+    };
+    Ok(Track {
+        name,
+        channel: track.channel - 1,
+        program,
+        transpose,
+        content,
+    })
+}
+
+fn track_file(track: &Track) -> TrackFile {
+    let mut file = TrackFile {
+        name: Some(track.name.clone()),
+        channel: track.channel + 1,
+        instrument: track.program.map(|program| program + 1),
+        ..TrackFile::default()
+    };
+    if track.transpose != track.content.default_transpose(track.channel) {
+        file.transpose = Some(track.transpose.to_string());
+    }
+    match &track.content {
+        Content::Notes { dsl, unit } => {
+            file.notes = Some(dsl.clone());
+            file.unit = Some(*unit);
+        }
+        Content::Drums { text, .. } => file.drums = Some(text.clone()),
+        Content::Clip {
+            source,
+            resolution,
+            notes,
+        } => {
+            file.source_chord = Some(source.to_string());
+            file.resolution = Some(*resolution);
+            file.clip = Some(
+                notes
+                    .iter()
+                    .map(|note| {
+                        [
+                            note.tick,
+                            note.length,
+                            note.note as u32,
+                            note.velocity as u32,
+                        ]
+                    })
+                    .collect(),
+            );
+        }
+    }
+    file
+}
+
+// the event on `channel`, with its note changed
+fn place(event: Event, channel: u8, change: impl Fn(u8) -> u8) -> Event {
+    match event {
+        Event::NoteOn { note, velocity, .. } => Event::NoteOn {
+            channel,
+            note: change(note),
+            velocity,
+        },
+        Event::NoteOff { note, velocity, .. } => Event::NoteOff {
+            channel,
+            note: change(note),
+            velocity,
+        },
+    }
+}
+
+// the degree of an interval above the root: the root, a second (or a
+// ninth), a third, a fourth, a fifth, a sixth or a seventh
+fn degree(interval: u8) -> u8 {
+    [1, 2, 2, 3, 3, 4, 5, 5, 5, 6, 7, 7][(interval % OCTAVE_STEPS) as usize]
+}
+
+/// A note played over `from` moved to `to`: the root moves by the smallest
+/// step, and a chord tone becomes the tone of the same degree in `to`, if
+/// it has one.
+pub fn retarget(note: u8, from: &Chord, to: &Chord) -> u8 {
+    let (from_root, to_root) = (from.key().0, to.key().0);
+    let interval = (note % OCTAVE_STEPS + OCTAVE_STEPS - from_root) % OCTAVE_STEPS;
+    let to_tones = to.tones();
+    let to_interval = if from.tones().contains(&interval) {
+        to_tones
+            .iter()
+            .copied()
+            .filter(|tone| degree(*tone) == degree(interval))
+            .min_by_key(|tone| (*tone as i32 - interval as i32).abs())
+            .unwrap_or(interval)
+    } else {
+        interval
+    };
+    let shift = ((to_root + OCTAVE_STEPS - from_root) % OCTAVE_STEPS) as i32;
+    let shift = if shift > 6 { shift - 12 } else { shift };
+    (note as i32 - interval as i32 + shift + to_interval as i32).clamp(0, 127) as u8
+}
+
+impl Style {
+    pub fn parse(text: &str) -> Result<Style, String> {
+        let file: StyleFile = toml::from_str(text).map_err(|e| e.to_string())?;
+        let us_per_quarter = match file.tempo {
+            Some(bpm) if bpm.is_finite() && bpm > 0.0 => Some(us_per_quarter_from_bpm(bpm)),
+            Some(bpm) => return Err(format!("wrong tempo: {}", bpm)),
+            None => None,
+        };
+        let meter = match file.meter {
+            Some(meter) => Some(meter.parse()?),
+            None => None,
+        };
+        if file.sections.is_empty() {
+            return Err("no sections in the style".to_string());
+        }
+        let sections = file
+            .sections
+            .into_iter()
+            .map(|section| {
+                let name = section.name;
+                let tracks = section
+                    .tracks
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, track)| parse_track(index, track))
+                    .collect::<Result<Vec<Track>, String>>()
+                    .map_err(|e| format!("section {}, {}", name, e))?;
+                Ok(Section {
+                    name,
+                    bars: section.bars.unwrap_or(1).max(1),
+                    tracks,
+                })
+            })
+            .collect::<Result<Vec<Section>, String>>()?;
+        Ok(Style {
+            name: file.name.unwrap_or_default(),
+            us_per_quarter,
+            meter,
+            sections,
+        })
+    }
+
+    pub fn read(path: &Path) -> Result<Style, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Style::parse(&text)
+    }
+
+    /// The style written in TOML, as it would be read.
+    pub fn to_toml(&self) -> Result<String, String> {
+        let file = StyleFile {
+            name: Some(self.name.clone()).filter(|name| !name.is_empty()),
+            // to a hundredth of a BPM, as it was probably written
+            tempo: self
+                .us_per_quarter
+                .map(|us_per_quarter| (bpm(us_per_quarter) * 100.0).round() / 100.0),
+            meter: self.meter.map(|meter| meter.to_string()),
+            sections: self
+                .sections
+                .iter()
+                .map(|section| SectionFile {
+                    name: section.name.clone(),
+                    bars: Some(section.bars),
+                    tracks: section.tracks.iter().map(track_file).collect(),
+                })
+                .collect(),
+        };
+        toml::to_string_pretty(&file).map_err(|e| e.to_string())
+    }
+
+    /// The section called `name`, or the first one.
+    pub fn section(&self, name: Option<&str>) -> Option<&Section> {
+        match name {
+            Some(name) => self.sections.iter().find(|section| section.name == name),
+            None => self.sections.first(),
+        }
+    }
+
+    /// The hits of the drums that only play some of the time are picked
+    /// by `seed`.
+    pub fn with_seed(mut self, seed: u64) -> Style {
+        for section in &mut self.sections {
+            for track in &mut section.tracks {
+                if let Content::Drums { grid, .. } = &mut track.content {
+                    *grid = grid.clone().with_seed(seed);
+                }
+            }
+        }
+        self
+    }
+}
+
+impl Section {
+    /// The instruments of the tracks, as (channel, program).
+    pub fn programs(&self) -> Vec<(u8, u8)> {
+        self.tracks
+            .iter()
+            .filter_map(|track| track.program.map(|program| (track.channel, program)))
+            .collect()
+    }
+
+    /// The events of the section, for `chord`. `bar` is the number of the
+    /// first bar, which picks the drums that only play some of the time.
+    pub fn create_pattern(
+        &self,
+        ticks_per_quarter: u32,
+        meter: Meter,
+        chord: &Chord,
+        bar: u64,
+    ) -> Vec<TimedEvent> {
+        let bar_ticks = meter.ticks_per_bar(ticks_per_quarter);
+        let pattern_ticks = self.bars * bar_ticks;
+        let mut timed_events = vec![];
+        for track in &self.tracks {
+            let change = |note: u8| match (&track.content, track.transpose) {
+                (_, Transpose::Fixed) => note,
+                (Content::Clip { source, .. }, Transpose::Chord) => retarget(note, source, chord),
+                (_, Transpose::Chord) => (note + chord.key().0).min(127),
+            };
+            let track_events = match &track.content {
+                Content::Notes { dsl, unit } => {
+                    let ticks_per_unit = 4 * ticks_per_quarter / *unit as u32;
+                    // checked when the style was read
+                    interpret_dsl(dsl, 0, ticks_per_unit, 4).unwrap_or_default()
+                }
+                Content::Drums { grid, .. } => (0..self.bars)
+                    .flat_map(|index| {
+                        grid.create_bar(ticks_per_quarter, meter, bar + index as u64)
+                            .into_iter()
+                            .map(move |te| TimedEvent {
+                                timing: te.timing + index * bar_ticks,
+                                event: te.event,
+                            })
+                    })
+                    .collect(),
+                Content::Clip {
+                    resolution, notes, ..
+                } => clip_events(notes, *resolution, ticks_per_quarter),
+            };
+            for te in track_events {
+                // the notes that go past the end of the section are cut
+                let timing = match te.event {
+                    Event::NoteOn { .. } if te.timing >= pattern_ticks => continue,
+                    _ => te.timing.min(pattern_ticks),
+                };
+                timed_events.push(TimedEvent {
+                    timing,
+                    event: place(te.event, track.channel, change),
+                });
+            }
+        }
+        timed_events.sort();
+        timed_events
+    }
+}
+
+// the notes of a clip, in the ticks of the pattern
+fn clip_events(notes: &[ClipNote], resolution: u32, ticks_per_quarter: u32) -> Vec<TimedEvent> {
+    let ticks =
+        |clip_ticks: u32| (clip_ticks as u64 * ticks_per_quarter as u64 / resolution as u64) as u32;
+    let mut timed_events = vec![];
+    for note in notes {
+        let (on, off) = create_note(
+            ticks(note.tick),
+            ticks(note.length).max(1),
+            0,
+            note.note,
+            note.velocity,
+        );
+        timed_events.push(on);
+        timed_events.push(off);
+    }
+    timed_events
+}
+
+// the value read again, if it changed; a tempo or meter taken out of the
+// file leaves the one played
+fn changed<T: Copy + PartialEq>(before: &mut Option<T>, after: Option<T>) -> Option<T> {
+    if after.is_some() && after != *before {
+        *before = after;
+        after
+    } else {
+        None
+    }
+}
+
+/// A style read again: the section that is played, and the tempo and the
+/// meter of the style if they changed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reload {
+    pub section: Section,
+    pub us_per_quarter: Option<u32>,
+    pub meter: Option<Meter>,
+}
+
+/// Watches the file of a style, to play the changes made to it while
+/// playing.
+pub struct StyleWatcher {
+    path: PathBuf,
+    section: Option<String>,
+    seed: u64,
+    modified: Option<SystemTime>,
+    us_per_quarter: Option<u32>,
+    meter: Option<Meter>,
+}
+
+impl StyleWatcher {
+    /// Watch the file of `style` from the time it was read, for changes to
+    /// the section called `section`, or the first one.
+    pub fn new(path: &Path, style: &Style, section: Option<&str>, seed: u64) -> StyleWatcher {
+        let mut watcher = StyleWatcher {
+            path: path.to_path_buf(),
+            section: section.map(str::to_string),
+            seed,
+            modified: None,
+            us_per_quarter: style.us_per_quarter,
+            meter: style.meter,
+        };
+        watcher.modified = watcher.modified();
+        watcher
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// The style read again, if the file has changed since it was last
+    /// read, or why it could not be.
+    pub fn poll(&mut self) -> Option<Result<Reload, String>> {
+        let modified = self.modified();
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        let style = match Style::read(&self.path) {
+            Ok(style) => style.with_seed(self.seed),
+            Err(e) => return Some(Err(e)),
+        };
+        let section = match style.section(self.section.as_deref()) {
+            Some(section) => section.clone(),
+            None => {
+                return Some(Err(format!(
+                    "no section {}",
+                    self.section.as_deref().unwrap_or_default()
+                )))
+            }
+        };
+        Some(Ok(Reload {
+            section,
+            us_per_quarter: changed(&mut self.us_per_quarter, style.us_per_quarter),
+            meter: changed(&mut self.meter, style.meter),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::COMMON_TIME;
+    use komp_core::*;
+
+    const STYLE: &str = r#"
+        name = "Two-beat"
+        tempo = 120
+        meter = "3/4"
+
+        [[section]]
+        name = "A"
+
+        [[section.track]]
+        name = "piano"
+        channel = 1
+        instrument = 1
+        unit = 4
+        notes = "[C - -] [E - -] [G - -]"
+
+        [[section.track]]
+        channel = 10
+        drums = "BD: x.. SN: .xx"
+
+        [[section]]
+        name = "B"
+        bars = 2
+
+        [[section.track]]
+        channel = 2
+        instrument = 33
+        transpose = "none"
+        unit = 2
+        notes = "[C E G -]"
+    "#;
+
+    fn note_ons(timed_events: &[TimedEvent]) -> Vec<(u32, u8, u8)> {
+        timed_events
+            .iter()
+            .filter_map(|te| match te.event {
+                Event::NoteOn { channel, note, .. } => Some((te.timing, channel, note)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let style = Style::parse(STYLE).unwrap();
+        assert_eq!(style.name, "Two-beat");
+        assert_eq!(style.us_per_quarter, Some(500_000));
+        assert_eq!(style.meter, Some(Meter::new(3, 4)));
+        assert_eq!(style.sections.len(), 2);
+        let a = style.section(None).unwrap();
+        assert_eq!(a.name, "A");
+        assert_eq!(a.bars, 1);
+        assert_eq!(a.tracks[0].transpose, Transpose::Chord);
+        assert_eq!(a.tracks[1].name, "track 2");
+        assert_eq!(a.tracks[1].transpose, Transpose::Fixed);
+        assert_eq!(a.programs(), vec![(0, 0)]);
+        let b = style.section(Some("B")).unwrap();
+        assert_eq!(b.bars, 2);
+        assert_eq!(b.programs(), vec![(1, 32)]);
+        assert_eq!(style.section(Some("C")), None);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let errors = [
+            "tempo = 120",
+            "[[section]]\nname = \"A\"\n[[section.track]]\nchannel = 17\nnotes = \"C\"",
+            "[[section]]\nname = \"A\"\n[[section.track]]\nchannel = 1\ninstrument = 0\nnotes = \"C\"",
+            "[[section]]\nname = \"A\"\n[[section.track]]\nchannel = 1",
+            "[[section]]\nname = \"A\"\n[[section.track]]\nchannel = 1\nnotes = \"C\"\ndrums = \"BD: x\"",
+            "[[section]]\nname = \"A\"\n[[section.track]]\nchannel = 10\ndrums = \"GONG: x\"",
+            "[[section]]\nname = \"A\"\n[[section.track]]\nchannel = 1\nnotes = \"C\"\ntranspose = \"up\"",
+            "[[section]]\nname = \"A\"\n[[section.track]]\nchannel = 1\nnote = \"C\"",
+            "meter = \"3:4\"\n[[section]]\nname = \"A\"",
+            "[[section]]\nname = \"A\"\n[[section.track]]\nchannel = 1\nnotes = \"[C E\"",
+            "[[section]]\nname = \"A\"\n[[section.track]]\nchannel = 1\nnotes = \"\"",
+            "[[section]]\nname = \"A\"\n[[section.track]]\nchannel = 1\nunit = 0\nnotes = \"C\"",
+            "[[section]]\nname = \"A\"\n[[section.track]]\nchannel = 1\nunit = 128\nnotes = \"C\"",
+        ];
+        for text in errors.iter() {
+            assert!(Style::parse(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_create_pattern() {
+        let style = Style::parse(STYLE).unwrap();
+        let meter = style.meter.unwrap();
+        let a = style.section(None).unwrap();
+        // in F, the piano is transposed and the drums are not
+        let timed_events = a.create_pattern(96, meter, &Chord::Major(F_KEY), 0);
+        assert_eq!(
+            note_ons(&timed_events),
+            vec![
+                (0, 0, NOTE_F4),
+                (0, 0, NOTE_A4),
+                (0, 0, NOTE_C5),
+                (0, 9, 36),
+                (96, 9, 38),
+                (192, 9, 38)
+            ]
+        );
+        // the note-offs are within the section
+        assert!(timed_events.iter().all(|te| te.timing <= 288));
+    }
+
+    #[test]
+    fn test_section_of_bars() {
+        let style = Style::parse(STYLE).unwrap();
+        let b = style.section(Some("B")).unwrap();
+        let timed_events = b.create_pattern(96, COMMON_TIME, &Chord::Major(F_KEY), 0);
+        // half notes over two bars, not transposed
+        assert_eq!(
+            note_ons(&timed_events),
+            vec![(0, 1, NOTE_C4), (192, 1, NOTE_E4), (384, 1, NOTE_G4)]
+        );
+    }
+
+    #[test]
+    fn test_example_style() {
+        let style = Style::parse(include_str!("../../styles/two-beat.toml")).unwrap();
+        for section in &style.sections {
+            let timed_events = section.create_pattern(96, COMMON_TIME, &Chord::Major(C_KEY), 0);
+            assert!(!timed_events.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_style_watcher() {
+        let path = std::env::temp_dir().join(format!("komp-style-{}.toml", std::process::id()));
+        let write = |text: &str, seconds: u64| {
+            std::fs::write(&path, text).unwrap();
+            // an edit is seen by its time, which may not have moved yet
+            let modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(seconds);
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        };
+        write(STYLE, 1_000);
+        let style = Style::parse(STYLE).unwrap();
+        let mut watcher = StyleWatcher::new(&path, &style, Some("B"), 0);
+        assert_eq!(watcher.poll(), None);
+
+        write(&STYLE.replace("bars = 2", "bars = 4"), 2_000);
+        let reload = watcher.poll().unwrap().unwrap();
+        assert_eq!(
+            (reload.section.name.as_str(), reload.section.bars),
+            ("B", 4)
+        );
+        assert_eq!((reload.us_per_quarter, reload.meter), (None, None));
+        assert_eq!(watcher.poll(), None);
+
+        // the notes are checked as the style is read again
+        write(&STYLE.replace("notes = \"", "notes = \"[C "), 2_500);
+        assert!(watcher.poll().unwrap().is_err());
+
+        // errors are reported, and the next change is read again
+        write("[[section]]", 3_000);
+        assert!(watcher.poll().unwrap().is_err());
+        write(&STYLE.replace("name = \"B\"", "name = \"C\""), 4_000);
+        assert!(watcher.poll().unwrap().is_err());
+        write(STYLE, 5_000);
+        assert_eq!(watcher.poll().unwrap().unwrap().section.bars, 2);
+
+        // only the tempo and meter that changed are reported
+        let slower = STYLE.replace("tempo = 120", "tempo = 100");
+        write(&slower, 6_000);
+        let reload = watcher.poll().unwrap().unwrap();
+        assert_eq!((reload.us_per_quarter, reload.meter), (Some(600_000), None));
+        write(&slower.replace("meter = \"3/4\"", "meter = \"4/4\""), 7_000);
+        let reload = watcher.poll().unwrap().unwrap();
+        assert_eq!(
+            (reload.us_per_quarter, reload.meter),
+            (None, Some(COMMON_TIME))
+        );
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(watcher.poll().unwrap().is_err());
+    }
+
+    const CLIP: &str = r#"
+        [[section]]
+        name = "A"
+
+        [[section.track]]
+        name = "piano"
+        channel = 1
+        source_chord = "Cmaj7"
+        resolution = 480
+        clip = [[0, 240, 60, 100], [0, 240, 64, 100], [480, 240, 67, 90], [960, 240, 71, 90], [1440, 240, 62, 80]]
+
+        [[section.track]]
+        name = "drums"
+        channel = 10
+        source_chord = "C"
+        resolution = 480
+        clip = [[0, 120, 36, 100]]
+    "#;
+
+    #[test]
+    fn test_retarget() {
+        let cmaj7 = Chord::MajorMaj7(C_KEY);
+        let retargeted = |to: Chord| -> Vec<u8> {
+            [NOTE_C4, NOTE_E4, NOTE_G4, NOTE_B4, NOTE_D4]
+                .iter()
+                .map(|note| retarget(*note, &cmaj7, &to))
+                .collect()
+        };
+        assert_eq!(
+            retargeted(Chord::Minor7(D_KEY)),
+            vec![NOTE_D4, NOTE_F4, NOTE_A4, NOTE_C5, NOTE_E4]
+        );
+        // down a fourth rather than up a fifth
+        assert_eq!(
+            retargeted(Chord::Major7(G_KEY)),
+            vec![NOTE_G3, NOTE_B3, NOTE_D4, NOTE_F4, NOTE_A3]
+        );
+        // without a seventh in the chord, the seventh is moved along
+        assert_eq!(
+            retargeted(Chord::Minor(A_KEY)),
+            vec![NOTE_A3, NOTE_C4, NOTE_E4, NOTE_GSHARP4, NOTE_B3]
+        );
+    }
+
+    #[test]
+    fn test_clip() {
+        let style = Style::parse(CLIP).unwrap();
+        let a = style.section(None).unwrap();
+        assert_eq!(a.tracks[0].transpose, Transpose::Chord);
+        assert_eq!(a.tracks[1].transpose, Transpose::Fixed);
+        let timed_events = a.create_pattern(96, COMMON_TIME, &Chord::Minor7(D_KEY), 0);
+        assert_eq!(
+            note_ons(&timed_events),
+            vec![
+                (0, 0, NOTE_D4),
+                (0, 0, NOTE_F4),
+                (0, 9, 36),
+                (96, 0, NOTE_A4),
+                (192, 0, NOTE_C5),
+                (288, 0, NOTE_E4)
+            ]
+        );
+        assert!(Style::parse(&CLIP.replace("source_chord = \"Cmaj7\"", "")).is_err());
+        assert!(Style::parse(&CLIP.replace("resolution = 480", "resolution = 0")).is_err());
+        assert!(Style::parse(&CLIP.replace("[0, 120, 36, 100]", "[0, 120, 36, 0]")).is_err());
+    }
+
+    #[test]
+    fn test_to_toml() {
+        for text in [STYLE, CLIP, include_str!("../../styles/two-beat.toml")].iter() {
+            let style = Style::parse(text).unwrap();
+            let written = style.to_toml().unwrap();
+            assert_eq!(Style::parse(&written), Ok(style), "{}", written);
+        }
+    }
+}
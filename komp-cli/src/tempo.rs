@@ -0,0 +1,117 @@
+const US_PER_MINUTE: f64 = 60_000_000.0;
+// the tempos that can be typed or tapped, in BPM
+pub const MIN_BPM: f64 = 20.0;
+pub const MAX_BPM: f64 = 400.0;
+// the longest tempo ramp, in bars
+pub const MAX_RAMP_BARS: u32 = 64;
+// taps further apart than this start a new tempo
+const MAX_TAP_INTERVAL: u64 = 2_000_000_000;
+// taps closer than this, faster than MAX_BPM, are left out
+const MIN_TAP_INTERVAL: u64 = (US_PER_MINUTE / MAX_BPM) as u64 * 1_000;
+const TAPS: usize = 5;
+
+pub fn bpm(us_per_quarter: u32) -> f64 {
+    US_PER_MINUTE / us_per_quarter as f64
+}
+
+pub fn us_per_quarter_from_bpm(bpm: f64) -> u32 {
+    (US_PER_MINUTE / bpm).round() as u32
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TempoChange {
+    Set(u32),
+    // change to a tempo during a number of bars
+    Ramp(u32, u32),
+}
+
+/// What to tap the tempo with: a key, or a controller such as a footswitch.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tap {
+    Note(u8),
+    Controller(u8),
+}
+
+/// Tap tempo, averaging the intervals between the latest few taps.
+pub struct TapTempo {
+    taps: Vec<u64>,
+}
+
+impl TapTempo {
+    pub fn new() -> TapTempo {
+        TapTempo { taps: vec![] }
+    }
+
+    /// Register a tap at `time` (in nanoseconds), returning the tapped
+    /// tempo once there are at least two taps. A tap too soon after the
+    /// last one for a tempo of `MAX_BPM` is left out.
+    pub fn tap(&mut self, time: u64) -> Option<u32> {
+        if let Some(&last) = self.taps.last() {
+            if time >= last && time - last < MIN_TAP_INTERVAL {
+                return None;
+            }
+            if time < last || time - last > MAX_TAP_INTERVAL {
+                self.taps.clear();
+            }
+        }
+        self.taps.push(time);
+        if self.taps.len() > TAPS {
+            self.taps.remove(0);
+        }
+        match (self.taps.first(), self.taps.last()) {
+            (Some(first), Some(last)) if self.taps.len() > 1 => {
+                let interval = (last - first) / (self.taps.len() as u64 - 1);
+                Some((interval / 1_000) as u32)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::play::NS_PER_MS;
+
+    #[test]
+    fn test_bpm() {
+        assert_eq!(bpm(500_000), 120.0);
+        assert_eq!(us_per_quarter_from_bpm(90.0), 666_667);
+    }
+
+    #[test]
+    fn test_tap_tempo() {
+        let mut tap_tempo = TapTempo::new();
+        assert_eq!(tap_tempo.tap(1_000 * NS_PER_MS), None);
+        assert_eq!(tap_tempo.tap(1_500 * NS_PER_MS), Some(500_000));
+        // the latest taps are averaged
+        assert_eq!(tap_tempo.tap(2_020 * NS_PER_MS), Some(510_000));
+    }
+
+    #[test]
+    fn test_tap_tempo_only_uses_the_latest_taps() {
+        let mut tap_tempo = TapTempo::new();
+        for tap in 0..10 {
+            tap_tempo.tap(tap * 600 * NS_PER_MS);
+        }
+        assert_eq!(tap_tempo.tap(5_800 * NS_PER_MS), Some(550_000));
+    }
+
+    #[test]
+    fn test_tap_tempo_starts_over_after_a_pause() {
+        let mut tap_tempo = TapTempo::new();
+        tap_tempo.tap(1_000 * NS_PER_MS);
+        tap_tempo.tap(1_500 * NS_PER_MS);
+        assert_eq!(tap_tempo.tap(5_000 * NS_PER_MS), None);
+        assert_eq!(tap_tempo.tap(5_400 * NS_PER_MS), Some(400_000));
+    }
+
+    #[test]
+    fn test_tap_tempo_leaves_out_double_taps() {
+        let mut tap_tempo = TapTempo::new();
+        tap_tempo.tap(1_000 * NS_PER_MS);
+        assert_eq!(tap_tempo.tap(1_000 * NS_PER_MS), None);
+        assert_eq!(tap_tempo.tap(1_100 * NS_PER_MS), None);
+        assert_eq!(tap_tempo.tap(1_600 * NS_PER_MS), Some(600_000));
+    }
+}
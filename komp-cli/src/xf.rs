@@ -0,0 +1,338 @@
+/** Yamaha XF chords: the chord names that Yamaha MIDI songs (XF files) carry
+as sequencer-specific meta events, `FF 7F 07 43 7B 01 cr ct bn bt`.
+
+`cr` is the root, a note from 1 (C) to 7 (B) in the low nibble and an
+accidental from 0 (three flats) through 3 (natural) to 6 (three sharps) in
+the high nibble. `ct` is the type of chord, from 0 (major) to 33, and 34 for
+no chord. `bn` is the bass note, written as the root is, and `bt` the type
+of the lower chord of a compound chord, both 0x7F when there is none.
+
+The chords of a song become a song for `--song` (see `song`), each on the
+beat it starts on, and the chords heard are written as a MIDI file of these
+events, which Yamaha keyboards and sequencers show as chord names. A minor
+6/9 has no type of its own and is written as a minor sixth.
+*/
+use crate::import::{meter, tempo, track_name};
+use crate::musicxml::{Harmony, LeadSheet};
+use crate::pattern::{Meter, COMMON_TIME};
+use crate::smf::{Message, Smf, SmfEvent};
+use crate::song::{Change, Song};
+use komp_core::{Chord, Key};
+
+const SEQUENCER_SPECIFIC: u8 = 0x7F;
+const XF_CHORD: [u8; 3] = [0x43, 0x7B, 0x01];
+const NO_CHORD: u8 = 34;
+const NONE: u8 = 0x7F;
+const NATURAL: u8 = 3;
+const STEPS: [u8; 7] = [0, 2, 4, 5, 7, 9, 11];
+const TICKS_PER_QUARTER: u16 = 96;
+
+type Kind = fn(Key) -> Chord;
+
+// the chords of the XF chord types, in their order
+const TYPES: [Kind; 34] = [
+    Chord::Major,
+    Chord::Major6,
+    Chord::MajorMaj7,
+    Chord::MajorMaj7Plus11,
+    Chord::MajorAdd9,
+    Chord::MajorMaj7_9,
+    Chord::Major6_9,
+    Chord::Aug,
+    Chord::Minor,
+    Chord::Minor6,
+    Chord::Minor7,
+    Chord::Minor7b5,
+    Chord::MinorAdd9,
+    Chord::Minor7_9,
+    Chord::Minor7_11,
+    Chord::MinorMaj7,
+    Chord::MinorMaj7_9,
+    Chord::Dim,
+    Chord::Dim7,
+    Chord::Major7,
+    Chord::SevenSus4,
+    Chord::Major7b5,
+    Chord::Major7_9,
+    Chord::Major7Plus11,
+    Chord::Major7_13,
+    Chord::Major7b9,
+    Chord::Major7b13,
+    Chord::Major7Plus9,
+    Chord::MajorMaj7Aug,
+    Chord::Major7Aug,
+    // the root and its octave, and a power chord
+    Chord::None,
+    Chord::Five,
+    Chord::Sus4,
+    Chord::Sus2,
+];
+
+fn read_key(byte: u8) -> Option<Key> {
+    let (accidental, note) = (byte >> 4, byte & 0x0F);
+    if !(1..=7).contains(&note) || accidental > 6 {
+        return None;
+    }
+    let key = STEPS[note as usize - 1] as i8 + accidental as i8 - NATURAL as i8;
+    Some(Key(key.rem_euclid(12) as u8))
+}
+
+fn key_byte(key: Key) -> u8 {
+    let name = key.to_string();
+    let note = "CDEFGAB".find(&name[..1]).unwrap_or(0) as u8 + 1;
+    let accidental = match &name[1..] {
+        "b" => NATURAL - 1,
+        "#" => NATURAL + 1,
+        _ => NATURAL,
+    };
+    accidental << 4 | note
+}
+
+/// The chord, `None` for no chord, and the bass note of the data of an XF
+/// chord meta event.
+pub fn read_chord(data: &[u8]) -> Option<(Option<Chord>, Option<Key>)> {
+    match data {
+        [a, b, c, root, chord_type, bass, ..] if [*a, *b, *c] == XF_CHORD => {
+            let bass = if *bass == NONE { None } else { read_key(*bass) };
+            if *chord_type == NO_CHORD {
+                return Some((None, None));
+            }
+            let kind = TYPES.get(*chord_type as usize)?;
+            let root = read_key(*root)?;
+            Some((Some(kind(root)), bass.filter(|bass| *bass != root)))
+        }
+        _ => None,
+    }
+}
+
+/// The data of the XF chord meta event of `chord`, `None` for no chord.
+pub fn chord_data(chord: Option<Chord>) -> Vec<u8> {
+    let (root, chord_type) = match chord {
+        Some(Chord::Minor6_9(key)) => (key_byte(key), 9),
+        Some(chord) => {
+            let key = *chord.key();
+            let chord_type = TYPES.iter().position(|kind| kind(key) == chord);
+            (key_byte(key), chord_type.unwrap_or(0) as u8)
+        }
+        None => (key_byte(komp_core::C_KEY), NO_CHORD),
+    };
+    let mut data = XF_CHORD.to_vec();
+    data.extend_from_slice(&[root, chord_type, NONE, NONE]);
+    data
+}
+
+/// The XF chords of all tracks, with their bass notes, in time order.
+pub fn chords(smf: &Smf) -> Vec<(u32, Option<Chord>, Option<Key>)> {
+    let mut chords: Vec<(u32, Option<Chord>, Option<Key>)> = smf
+        .tracks
+        .iter()
+        .flatten()
+        .filter_map(|event| match &event.message {
+            Message::Meta(SEQUENCER_SPECIFIC, data) => {
+                read_chord(data).map(|(chord, bass)| (event.tick, chord, bass))
+            }
+            _ => None,
+        })
+        .collect();
+    chords.sort_by_key(|(tick, _, _)| *tick);
+    chords
+}
+
+/// A song of the XF chords of `smf`, to its last bar, named after its first
+/// track when it has a name.
+pub fn read_song(smf: &Smf, title: &str) -> Result<Song, String> {
+    let chords = chords(smf);
+    if chords.is_empty() {
+        return Err("no XF chords in the file".to_string());
+    }
+    let ticks_per_quarter = smf.ticks_per_quarter as u32;
+    if ticks_per_quarter == 0 {
+        return Err("no ticks per quarter in the file".to_string());
+    }
+    let meter = meter(smf)?;
+    let bar_ticks = meter
+        .unwrap_or(COMMON_TIME)
+        .ticks_per_bar(ticks_per_quarter);
+    // a beat shorter than a tick has no place for its chords
+    if meter
+        .unwrap_or(COMMON_TIME)
+        .ticks_per_beat(ticks_per_quarter)
+        == 0
+        || bar_ticks == 0
+    {
+        return Err("too few ticks per quarter for the meter".to_string());
+    }
+    let end = smf
+        .tracks
+        .iter()
+        .filter_map(|track| track.last().map(|event| event.tick))
+        .max()
+        .unwrap_or(0);
+    let last_chord = chords.last().map_or(0, |(tick, _, _)| *tick);
+    let sheet = LeadSheet {
+        title: smf.tracks.first().and_then(track_name),
+        us_per_quarter: tempo(smf),
+        meter,
+        ticks_per_quarter,
+        measures: std::cmp::max(end.div_ceil(bar_ticks), last_chord / bar_ticks + 1),
+        harmonies: chords
+            .into_iter()
+            .map(|(tick, chord, bass)| Harmony {
+                measure: tick / bar_ticks,
+                tick: tick % bar_ticks,
+                chord,
+                bass,
+            })
+            .collect(),
+    };
+    let title = sheet.title.clone().unwrap_or_else(|| title.to_string());
+    Ok(sheet.to_song(&title))
+}
+
+/// A MIDI file of the changes of each bar as XF chords, with the title, the
+/// tempo and the meter.
+pub fn write_xf(title: &str, meter: Meter, us_per_quarter: u32, bars: &[Vec<Change>]) -> Vec<u8> {
+    let ticks_per_quarter = TICKS_PER_QUARTER as u32;
+    let bar_ticks = meter.ticks_per_bar(ticks_per_quarter);
+    let beat_ticks = meter.ticks_per_beat(ticks_per_quarter);
+    let meta = |tick, meta_type, data: Vec<u8>| SmfEvent {
+        tick,
+        message: Message::Meta(meta_type, data),
+    };
+    let mut track = vec![
+        meta(0, 0x03, title.as_bytes().to_vec()),
+        meta(0, 0x51, us_per_quarter.to_be_bytes()[1..].to_vec()),
+        meta(
+            0,
+            0x58,
+            vec![meter.beats, meter.unit.trailing_zeros() as u8, 24, 8],
+        ),
+    ];
+    for (bar, changes) in bars.iter().enumerate() {
+        for change in changes {
+            let tick = bar as u32 * bar_ticks + change.beat as u32 * beat_ticks;
+            track.push(meta(tick, SEQUENCER_SPECIFIC, chord_data(change.chord)));
+        }
+    }
+    track.push(meta(bars.len() as u32 * bar_ticks, 0x2F, vec![]));
+    Smf {
+        format: 0,
+        ticks_per_quarter: TICKS_PER_QUARTER,
+        tracks: vec![track],
+    }
+    .to_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smf::tests::smf_data;
+    use komp_core::*;
+
+    #[test]
+    fn test_read_chord() {
+        // F#m7/C#
+        assert_eq!(
+            read_chord(&[0x43, 0x7B, 0x01, 0x44, 10, 0x41, 0x7F]),
+            Some((Some(Chord::Minor7(FSHARP_KEY)), Some(CSHARP_KEY)))
+        );
+        // Bb7, a compound chord with the bass type left out
+        assert_eq!(
+            read_chord(&[0x43, 0x7B, 0x01, 0x27, 19, 0x27, 0x00]),
+            Some((Some(Chord::Major7(ASHARP_KEY)), None))
+        );
+        // Cb is B
+        assert_eq!(
+            read_chord(&[0x43, 0x7B, 0x01, 0x21, 8, 0x7F, 0x7F]),
+            Some((Some(Chord::Minor(B_KEY)), None))
+        );
+        assert_eq!(
+            read_chord(&[0x43, 0x7B, 0x01, 0x31, 34, 0x7F, 0x7F]),
+            Some((None, None))
+        );
+        assert_eq!(read_chord(&[0x43, 0x7B, 0x01, 0x31, 35, 0x7F, 0x7F]), None);
+        assert_eq!(read_chord(&[0x43, 0x7B, 0x01, 0x38, 0, 0x7F, 0x7F]), None);
+        assert_eq!(read_chord(&[0x43, 0x7B, 0x00, 0x58, 0x46, 0x30]), None);
+    }
+
+    #[test]
+    fn test_chord_data() {
+        for (index, kind) in TYPES.iter().enumerate() {
+            for key in 0..12 {
+                let chord = kind(Key(key));
+                assert_eq!(chord_data(Some(chord))[4], index as u8);
+                assert_eq!(
+                    read_chord(&chord_data(Some(chord))),
+                    Some((Some(chord), None))
+                );
+            }
+        }
+        assert_eq!(
+            chord_data(Some(Chord::Minor6_9(D_KEY))),
+            [0x43, 0x7B, 0x01, 0x32, 9, 0x7F, 0x7F]
+        );
+        assert_eq!(read_chord(&chord_data(None)), Some((None, None)));
+    }
+
+    #[test]
+    fn test_read_song() {
+        let track = [
+            0x00, 0xFF, 0x03, 0x04, b'S', b'o', b'n', b'g', // name
+            0x00, 0xFF, 0x58, 0x04, 0x03, 0x02, 0x18, 0x08, // 3/4
+            0x00, 0xFF, 0x7F, 0x07, 0x43, 0x7B, 0x01, 0x31, 0x02, 0x7F, 0x7F, // Cmaj7
+            0x82, 0x20, 0xFF, 0x7F, 0x07, 0x43, 0x7B, 0x01, 0x32, 0x0A, 0x7F, 0x7F, // Dm7
+            0x60, 0xFF, 0x7F, 0x07, 0x43, 0x7B, 0x01, 0x35, 0x13, 0x7F, 0x7F, // G7
+            0x85, 0x40, 0xFF, 0x2F, 0x00, // end of track, in the fourth bar
+        ];
+        let song = read_song(&Smf::parse(&smf_data(96, &[&track])).unwrap(), "file").unwrap();
+        assert_eq!(song.title, "Song");
+        assert_eq!(song.meter, Some(Meter::new(3, 4)));
+        assert_eq!(song.us_per_quarter, None);
+        assert_eq!(
+            song.to_toml().unwrap(),
+            "title = \"Song\"\n\
+             meter = \"3/4\"\n\
+             form = \"A\"\n\
+             \n\
+             [[section]]\n\
+             name = \"A\"\n\
+             bars = \"Cmaj7 | Dm7 G7 / | G7 | %\"\n"
+        );
+        let silent = Smf::parse(&smf_data(96, &[&[0x00, 0xFF, 0x2F, 0x00]])).unwrap();
+        assert!(read_song(&silent, "file").is_err());
+        // an eighth is shorter than the only tick of a quarter
+        let mut eighths = track;
+        eighths[13] = 0x03;
+        let coarse = Smf::parse(&smf_data(1, &[&eighths])).unwrap();
+        assert!(read_song(&coarse, "file").is_err());
+    }
+
+    #[test]
+    fn test_write_xf() {
+        let change = |beat, chord| Change { beat, chord };
+        let bars = vec![
+            vec![
+                change(0, Some(Chord::Minor7(D_KEY))),
+                change(2, Some(Chord::Major7(G_KEY))),
+            ],
+            vec![],
+            vec![change(0, Some(Chord::MajorMaj7(C_KEY))), change(3, None)],
+        ];
+        let data = write_xf("jam", COMMON_TIME, 600_000, &bars);
+        let smf = Smf::parse(&data).unwrap();
+        assert_eq!(tempo(&smf), Some(600_000));
+        assert_eq!(meter(&smf), Ok(Some(COMMON_TIME)));
+        assert_eq!(
+            chords(&smf),
+            vec![
+                (0, Some(Chord::Minor7(D_KEY)), None),
+                (192, Some(Chord::Major7(G_KEY)), None),
+                (768, Some(Chord::MajorMaj7(C_KEY)), None),
+                (1_056, None, None),
+            ]
+        );
+        let song = read_song(&smf, "file").unwrap();
+        assert_eq!(song.title, "jam");
+        assert_eq!(song.sections[0].bars.len(), 3);
+    }
+}
@@ -80,7 +80,8 @@ impl Chord {
         }
     }
 
-    fn template(&self) -> &'static [u8] {
+    /// The notes of the chord, as semitones above the root.
+    pub fn tones(&self) -> &'static [u8] {
         match self {
             Chord::None(_) => NONE,
             Chord::Major(_) => MAJOR,
@@ -121,9 +122,9 @@ impl Chord {
     }
 
     fn matches(&self, proposed: &Vec<u8>) -> bool {
-        self.template().len() == proposed.len()
+        self.tones().len() == proposed.len()
             && self
-                .template()
+                .tones()
                 .iter()
                 .zip(proposed.iter())
                 .all(|(a, b)| a == b)
@@ -132,7 +133,7 @@ impl Chord {
     pub fn notes(&self, octave: u8, mut inversion: u8) -> Vec<u8> {
         let mut notes: Vec<u8> = match self.key() {
             Key(k) => self
-                .template()
+                .tones()
                 .iter()
                 .map(|offset| {
                     let invert = if inversion > 0 {
@@ -1,8 +1,11 @@
 mod chord;
 mod key;
+mod symbol;
+mod voicing;
 
 pub use chord::*;
 pub use key::*;
+pub use voicing::*;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,176 @@
+/** Chord symbols, such as `C`, `F#m7`, `Bbmaj7` or `Eø7`.
+
+A symbol is the root, a letter with an optional `#` or `b`, and a suffix
+for the kind of chord. Most kinds have a few common spellings, `Cmaj7`,
+`CMaj7`, `CM7` and `CΔ7` are the same chord. Chords are written with the
+first of the spellings, and with flats rather than sharps except for F#.
+*/
+use crate::chord::Chord;
+use crate::key::*;
+use std::fmt;
+use std::str::FromStr;
+
+const ROOT_NAMES: [&str; 12] = [
+    "C", "Db", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B",
+];
+
+type Kind = fn(Key) -> Chord;
+
+// the spellings of each kind of chord, the first is how it is written
+const SUFFIXES: &[(&[&str], Kind)] = &[
+    (&[""], Chord::Major),
+    (&["m", "min", "-"], Chord::Minor),
+    (&["aug", "+"], Chord::Aug),
+    (&["dim", "°"], Chord::Dim),
+    (&["dim7", "°7"], Chord::Dim7),
+    (&["sus2"], Chord::Sus2),
+    (&["sus4", "sus"], Chord::Sus4),
+    (&["5"], Chord::Five),
+    (&["1"], Chord::None),
+    (&["7sus4", "7sus"], Chord::SevenSus4),
+    (&["6"], Chord::Major6),
+    (&["m6", "-6"], Chord::Minor6),
+    (&["6/9", "69"], Chord::Major6_9),
+    (&["m6/9", "m69", "-69"], Chord::Minor6_9),
+    (&["7"], Chord::Major7),
+    (&["9", "7(9)"], Chord::Major7_9),
+    (&["7b9"], Chord::Major7b9),
+    (&["7#9"], Chord::Major7Plus9),
+    (&["7#11"], Chord::Major7Plus11),
+    (&["7b13"], Chord::Major7b13),
+    (&["13", "7(13)"], Chord::Major7_13),
+    (&["7#5", "aug7", "+7"], Chord::Major7Aug),
+    (&["m7", "min7", "-7"], Chord::Minor7),
+    (&["m9", "m7(9)", "-9"], Chord::Minor7_9),
+    (&["m11", "m7(11)", "-11"], Chord::Minor7_11),
+    (&["7b5"], Chord::Major7b5),
+    (&["m7b5", "ø7", "ø", "-7b5"], Chord::Minor7b5),
+    (&["maj7", "Maj7", "M7", "Δ7", "Δ", "ma7"], Chord::MajorMaj7),
+    (&["maj9", "Maj9", "M9", "Δ9"], Chord::MajorMaj7_9),
+    (&["maj7#11", "Maj7#11", "Δ7#11"], Chord::MajorMaj7Plus11),
+    (&["maj7#5", "Maj7#5", "+maj7", "Δ7#5"], Chord::MajorMaj7Aug),
+    (&["mMaj7", "m(maj7)", "mM7", "-Δ7", "mΔ7"], Chord::MinorMaj7),
+    (&["mMaj9", "m(maj9)", "mM9", "-Δ9"], Chord::MinorMaj7_9),
+    (&["add9", "add2"], Chord::MajorAdd9),
+    (&["madd9", "m(add9)", "-add9"], Chord::MinorAdd9),
+];
+
+impl FromStr for Key {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Key, String> {
+        let mut chars = s.chars();
+        let natural = match chars.next() {
+            Some('C') => 0,
+            Some('D') => 2,
+            Some('E') => 4,
+            Some('F') => 5,
+            Some('G') => 7,
+            Some('A') => 9,
+            Some('B') => 11,
+            _ => return Err(format!("wrong root: {}", s)),
+        };
+        match chars.as_str() {
+            "" => Ok(Key(natural)),
+            "#" => Ok(Key((natural + 1) % OCTAVE_STEPS)),
+            "b" => Ok(Key((natural + OCTAVE_STEPS - 1) % OCTAVE_STEPS)),
+            _ => Err(format!("wrong root: {}", s)),
+        }
+    }
+}
+
+impl FromStr for Chord {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Chord, String> {
+        let s = s.trim();
+        // a sharp or flat belongs to the root, "Bb" is B flat
+        let root_length = match s.get(1..2) {
+            Some("#") | Some("b") => 2,
+            _ => 1,
+        };
+        let root = s
+            .get(..root_length)
+            .ok_or_else(|| format!("wrong chord: {}", s))?;
+        let key: Key = root.parse().map_err(|_| format!("wrong chord: {}", s))?;
+        let suffix = &s[root_length..];
+        SUFFIXES
+            .iter()
+            .find(|(spellings, _)| spellings.contains(&suffix))
+            .map(|(_, kind)| kind(key))
+            .ok_or_else(|| format!("wrong chord: {}", s))
+    }
+}
+
+impl Chord {
+    /// The suffix of the symbol of the chord, such as "m7" for Cm7.
+    pub fn suffix(&self) -> &'static str {
+        let key = *self.key();
+        SUFFIXES
+            .iter()
+            .find(|(_, kind)| kind(key) == *self)
+            .map(|(spellings, _)| spellings[0])
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(ROOT_NAMES[self.0 as usize % ROOT_NAMES.len()])
+    }
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.key(), self.suffix())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!("C".parse(), Ok(C_KEY));
+        assert_eq!("F#".parse(), Ok(FSHARP_KEY));
+        assert_eq!("Bb".parse(), Ok(ASHARP_KEY));
+        assert_eq!("Cb".parse(), Ok(B_KEY));
+        assert_eq!("E#".parse(), Ok(F_KEY));
+        assert!("H".parse::<Key>().is_err());
+        assert!("C##".parse::<Key>().is_err());
+    }
+
+    #[test]
+    fn test_parse_chord() {
+        assert_eq!("C".parse(), Ok(Chord::Major(C_KEY)));
+        assert_eq!("CMaj7".parse(), Ok(Chord::MajorMaj7(C_KEY)));
+        assert_eq!("Cmaj7".parse(), Ok(Chord::MajorMaj7(C_KEY)));
+        assert_eq!("F#m7".parse(), Ok(Chord::Minor7(FSHARP_KEY)));
+        assert_eq!("Bbm7b5".parse(), Ok(Chord::Minor7b5(ASHARP_KEY)));
+        assert_eq!("Bø".parse(), Ok(Chord::Minor7b5(B_KEY)));
+        assert_eq!("Bb".parse(), Ok(Chord::Major(ASHARP_KEY)));
+        assert_eq!("Ab7#9".parse(), Ok(Chord::Major7Plus9(GSHARP_KEY)));
+        assert_eq!("D6/9".parse(), Ok(Chord::Major6_9(D_KEY)));
+        assert_eq!(" G7sus ".parse(), Ok(Chord::SevenSus4(G_KEY)));
+        for wrong in &["", "H7", "Cmaj8", "c", "C/E", "Bbb"] {
+            assert!(wrong.parse::<Chord>().is_err(), "{}", wrong);
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Chord::MajorMaj7(C_KEY).to_string(), "Cmaj7");
+        assert_eq!(Chord::Minor7(DSHARP_KEY).to_string(), "Ebm7");
+        assert_eq!(Chord::Major7_9(FSHARP_KEY).to_string(), "F#9");
+        assert_eq!(Chord::Minor7b5(B_KEY).to_string(), "Bm7b5");
+    }
+
+    #[test]
+    fn test_every_chord_round_trips() {
+        for &(_, kind) in SUFFIXES {
+            for key in 0..OCTAVE_STEPS {
+                let chord = kind(Key(key));
+                assert_eq!(chord.to_string().parse(), Ok(chord));
+            }
+        }
+    }
+}
@@ -0,0 +1,511 @@
+use crate::chord::Chord;
+use crate::key::*;
+use std::convert::TryFrom;
+
+/// How the notes of a chord are spread out.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VoicingStyle {
+    /// All the notes of the chord, within an octave.
+    Close,
+    /// The root, third and seventh.
+    Shell,
+    /// Third, fifth (thirteenth on dominants), seventh and ninth.
+    RootlessA,
+    /// Seventh, ninth, third and fifth (thirteenth on dominants).
+    RootlessB,
+    /// A close voicing of root, third, fifth and seventh, with the second
+    /// note from the top dropped an octave.
+    Drop2,
+    /// The same, with the third note from the top dropped an octave.
+    Drop3,
+    /// Stacked fourths of chord tones and tensions.
+    Quartal,
+    /// Root and fifth at the bottom, with third, seventh and ninth above.
+    Spread,
+}
+
+impl std::str::FromStr for VoicingStyle {
+    type Err = String;
+    fn from_str(s: &str) -> Result<VoicingStyle, String> {
+        match s.trim() {
+            "close" => Ok(VoicingStyle::Close),
+            "shell" => Ok(VoicingStyle::Shell),
+            "rootless-a" => Ok(VoicingStyle::RootlessA),
+            "rootless-b" => Ok(VoicingStyle::RootlessB),
+            "drop-2" => Ok(VoicingStyle::Drop2),
+            "drop-3" => Ok(VoicingStyle::Drop3),
+            "quartal" => Ok(VoicingStyle::Quartal),
+            "spread" => Ok(VoicingStyle::Spread),
+            _ => Err(format!("wrong voicing style: {}", s)),
+        }
+    }
+}
+
+/// Voices chords with voice leading: each chord is played in the
+/// inversion and octave that moves the voices the least from the previous
+/// voicing, keeping all notes between `low` and `high`, and no interval
+/// below its low interval limit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Voicer {
+    low: u8,
+    high: u8,
+    style: VoicingStyle,
+    previous: Option<Vec<u8>>,
+}
+
+// the octaves that Chord::notes can stack a chord from, within MIDI notes
+const OCTAVES: u8 = 9;
+
+// the lowest note of each interval (in semitones) that does not sound
+// muddy, from a minor second up to a major ninth
+const LOW_INTERVAL_LIMITS: [u8; 15] = [
+    0,
+    NOTE_E3,
+    NOTE_EFLAT3,
+    NOTE_C3,
+    NOTE_BFLAT2,
+    NOTE_A2,
+    NOTE_BFLAT2,
+    NOTE_BFLAT1,
+    NOTE_G2,
+    NOTE_F2,
+    NOTE_F2,
+    NOTE_F2,
+    0,
+    NOTE_E2,
+    NOTE_EFLAT2,
+];
+
+impl Voicer {
+    pub fn new(low: u8, high: u8) -> Voicer {
+        Voicer {
+            low,
+            high,
+            style: VoicingStyle::Close,
+            previous: None,
+        }
+    }
+
+    pub fn with_style(mut self, style: VoicingStyle) -> Voicer {
+        self.style = style;
+        self
+    }
+
+    /// Forget the previous voicing, so the next chord starts afresh.
+    pub fn reset(&mut self) {
+        self.previous = None;
+    }
+
+    /// The voicings of a chord within the register, from low to high.
+    pub fn candidates(&self, chord: &Chord) -> Vec<Vec<u8>> {
+        let shapes = shapes(self.style, chord.tones());
+        let mut candidates: Vec<Vec<u8>> = if shapes.is_empty() {
+            let inversions = chord.notes(0, 0).len() as u8;
+            (0..OCTAVES)
+                .flat_map(|octave| {
+                    (0..inversions).map(move |inversion| chord.notes(octave, inversion))
+                })
+                .collect()
+        } else {
+            let root = chord.key().0 as i32;
+            (0..=OCTAVES + 1)
+                .flat_map(|octave| {
+                    let base = root + (octave * OCTAVE_STEPS) as i32;
+                    shapes.iter().filter_map(move |shape| {
+                        shape
+                            .iter()
+                            .map(|offset| u8::try_from(base + offset).ok())
+                            .collect::<Option<Vec<u8>>>()
+                    })
+                })
+                .collect()
+        };
+        candidates.retain(|notes| self.fits(notes));
+        candidates.sort();
+        candidates.dedup();
+        candidates
+    }
+
+    fn fits(&self, notes: &[u8]) -> bool {
+        notes
+            .iter()
+            .all(|note| (self.low..=self.high).contains(note))
+            && notes.windows(2).all(|pair| {
+                let interval = (pair[1] - pair[0]) as usize;
+                interval >= LOW_INTERVAL_LIMITS.len() || pair[0] >= LOW_INTERVAL_LIMITS[interval]
+            })
+    }
+
+    /// The notes to play for `chord`. The first chord is played in root
+    /// position if it fits, as low as the register allows.
+    pub fn voice(&mut self, chord: &Chord) -> Vec<u8> {
+        let candidates = self.candidates(chord);
+        let voicing = match &self.previous {
+            Some(previous) => candidates
+                .into_iter()
+                .min_by_key(|notes| (movement(previous, notes), center_distance(previous, notes))),
+            None => {
+                let root = chord.key().0;
+                let root_position = candidates
+                    .iter()
+                    .position(|notes| notes[0] % OCTAVE_STEPS == root)
+                    .unwrap_or(0);
+                candidates.get(root_position).cloned()
+            }
+        }
+        // the register is too narrow for the chord
+        .unwrap_or_else(|| self.lowest(chord));
+        self.previous = Some(voicing.clone());
+        voicing
+    }
+
+    fn lowest(&self, chord: &Chord) -> Vec<u8> {
+        (0..OCTAVES)
+            .map(|octave| chord.notes(octave, 0))
+            .find(|notes| notes[0] >= self.low)
+            .unwrap_or_else(|| chord.notes(3, 0))
+    }
+}
+
+// the degrees of a chord template, as semitones above the root
+struct Degrees {
+    third: Option<u8>,
+    fifth: Option<u8>,
+    seventh: Option<u8>,
+    ninth: Option<u8>,
+    thirteenth: Option<u8>,
+}
+
+fn degrees(template: &[u8]) -> Degrees {
+    let first = |offsets: &[u8]| offsets.iter().copied().find(|o| template.contains(o));
+    // suspended chords have the fourth or the second for a third, and
+    // sixth chords have the sixth for a seventh
+    let third = first(&[4, 3, 5, 2]);
+    let seventh = first(&[10, 11, 9]);
+    Degrees {
+        third,
+        fifth: first(&[7, 6, 8]),
+        seventh,
+        ninth: [1, 2, 3]
+            .iter()
+            .copied()
+            .find(|o| template.contains(o) && Some(*o) != third),
+        thirteenth: first(&[9]).filter(|_| seventh != Some(9)),
+    }
+}
+
+// the notes of each degree above the previous one
+fn stack(degrees: &[u8]) -> Vec<i32> {
+    let mut notes: Vec<i32> = vec![];
+    for degree in degrees {
+        let mut note = *degree as i32;
+        while notes.last().is_some_and(|last| note <= *last) {
+            note += OCTAVE_STEPS as i32;
+        }
+        notes.push(note);
+    }
+    notes
+}
+
+// the shapes of a style, as semitones above the root; none for close
+// voicings, and for chords without a third
+fn shapes(style: VoicingStyle, template: &[u8]) -> Vec<Vec<i32>> {
+    let degrees = degrees(template);
+    let third = match degrees.third {
+        Some(third) => third,
+        None => return vec![],
+    };
+    let fifth = degrees.fifth.unwrap_or(7);
+    let dominant = third == 4 && degrees.seventh == Some(10);
+    // triads get the sixth for a seventh, and the major ninth
+    let upper_seventh = degrees.seventh.unwrap_or(9);
+    let ninth = degrees.ninth.unwrap_or(2);
+    let upper_fifth = degrees
+        .thirteenth
+        .or(if dominant { Some(9) } else { None })
+        .unwrap_or(fifth);
+    match style {
+        VoicingStyle::Close => vec![],
+        VoicingStyle::Shell => vec![stack(&[0, third, degrees.seventh.unwrap_or(fifth)])],
+        VoicingStyle::RootlessA => vec![stack(&[third, upper_fifth, upper_seventh, ninth])],
+        VoicingStyle::RootlessB => vec![stack(&[upper_seventh, ninth, third, upper_fifth])],
+        VoicingStyle::Drop2 | VoicingStyle::Drop3 => {
+            let drop = if style == VoicingStyle::Drop2 { 2 } else { 3 };
+            let mut core = vec![0, third, fifth];
+            core.extend(degrees.seventh);
+            (0..core.len())
+                .map(|inversion| {
+                    let inverted: Vec<u8> = core[inversion..]
+                        .iter()
+                        .chain(&core[..inversion])
+                        .copied()
+                        .collect();
+                    let mut notes = stack(&inverted);
+                    if notes.len() > drop {
+                        let dropped = notes.len() - drop;
+                        notes[dropped] -= OCTAVE_STEPS as i32;
+                        notes.sort();
+                    }
+                    notes
+                })
+                .collect()
+        }
+        VoicingStyle::Quartal => quartal(template, &degrees).into_iter().collect(),
+        VoicingStyle::Spread => {
+            let mut degrees_up = vec![0, fifth, third];
+            degrees_up.extend(degrees.seventh);
+            degrees_up.extend(degrees.ninth);
+            vec![stack(&degrees_up)]
+        }
+    }
+}
+
+const FOURTH: u8 = 5;
+
+// fourths stacked on the third, the seventh, the root, the fifth or the
+// ninth, using only chord tones and the natural tensions of the chord
+fn quartal(template: &[u8], degrees: &Degrees) -> Option<Vec<i32>> {
+    let third = degrees.third?;
+    let mut available = template.to_vec();
+    let altered_ninth = template.contains(&1) || (third == 4 && template.contains(&3));
+    if !altered_ninth {
+        available.push(2);
+    }
+    if third == 4 && !template.contains(&8) {
+        available.push(9);
+    }
+    if third == 3 {
+        available.push(5);
+    }
+    let size = if template.len() >= 4 { 4 } else { 3 };
+    [
+        Some(third),
+        degrees.seventh,
+        Some(0),
+        degrees.fifth,
+        Some(2),
+    ]
+    .iter()
+    .flatten()
+    .map(|start| {
+        (0..size)
+            .map(|i| (start + i * FOURTH) % OCTAVE_STEPS)
+            .collect::<Vec<u8>>()
+    })
+    .find(|degrees| {
+        degrees.iter().all(|degree| available.contains(degree)) && degrees.contains(&third)
+    })
+    .map(|degrees| stack(&degrees))
+}
+
+// how far the voices move, with each note going to the nearest note of
+// the other chord, both ways so that notes can split and merge
+fn movement(from: &[u8], to: &[u8]) -> u32 {
+    let nearest = |note: u8, chord: &[u8]| {
+        chord
+            .iter()
+            .map(|other| (note as i32 - *other as i32).unsigned_abs())
+            .min()
+            .unwrap_or(0)
+    };
+    let forth: u32 = to.iter().map(|note| nearest(*note, from)).sum();
+    let back: u32 = from.iter().map(|note| nearest(*note, to)).sum();
+    forth + back
+}
+
+// how far apart the average notes of the chords are, in fractions of
+// both lengths
+fn center_distance(from: &[u8], to: &[u8]) -> u32 {
+    let sum = |notes: &[u8]| notes.iter().map(|note| *note as u32).sum::<u32>();
+    (sum(from) * to.len() as u32).abs_diff(sum(to) * from.len() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_chord_in_root_position() {
+        let mut voicer = Voicer::new(NOTE_C3, NOTE_C5);
+        assert_eq!(
+            voicer.voice(&Chord::Major7(F_KEY)),
+            vec![NOTE_F3, NOTE_A3, NOTE_C4, NOTE_DSHARP4]
+        );
+    }
+
+    #[test]
+    fn test_smallest_movement() {
+        let mut voicer = Voicer::new(NOTE_C3, NOTE_C5);
+        voicer.voice(&Chord::Major(C_KEY));
+        // the C is held, E and G move up
+        assert_eq!(
+            voicer.voice(&Chord::Major(F_KEY)),
+            vec![NOTE_C3, NOTE_F3, NOTE_A3]
+        );
+        assert_eq!(
+            voicer.voice(&Chord::Major(G_KEY)),
+            vec![NOTE_D3, NOTE_G3, NOTE_B3]
+        );
+    }
+
+    #[test]
+    fn test_ii_v_i() {
+        let mut voicer = Voicer::new(NOTE_C3, NOTE_C5);
+        let progression = [
+            Chord::Minor7(D_KEY),
+            Chord::Major7(G_KEY),
+            Chord::MajorMaj7(C_KEY),
+        ];
+        let voicings: Vec<Vec<u8>> = progression
+            .iter()
+            .map(|chord| voicer.voice(chord))
+            .collect();
+        assert_eq!(voicings[0], vec![NOTE_D3, NOTE_F3, NOTE_A3, NOTE_C4]);
+        assert_eq!(voicings[1], vec![NOTE_D3, NOTE_F3, NOTE_G3, NOTE_B3]);
+        // the G and the B are held
+        assert_eq!(voicings[2], vec![NOTE_E3, NOTE_G3, NOTE_B3, NOTE_C4]);
+    }
+
+    #[test]
+    fn test_stays_in_register() {
+        let mut voicer = Voicer::new(NOTE_C4, NOTE_C5);
+        let mut chord = Chord::Major(C_KEY);
+        for _ in 0..24 {
+            // up a fifth, around the circle
+            chord = Chord::Major(Key((chord.key().0 + 7) % OCTAVE_STEPS));
+            let notes = voicer.voice(&chord);
+            assert!(notes.iter().all(|note| (NOTE_C4..=NOTE_C5).contains(note)));
+        }
+    }
+
+    #[test]
+    fn test_first_chord_inverted_to_fit() {
+        let mut voicer = Voicer::new(NOTE_C4, NOTE_C5);
+        assert_eq!(
+            voicer.voice(&Chord::Major(G_KEY)),
+            vec![NOTE_D4, NOTE_G4, NOTE_B4]
+        );
+    }
+
+    #[test]
+    fn test_narrow_register() {
+        let mut voicer = Voicer::new(NOTE_C4, NOTE_E4);
+        assert_eq!(
+            voicer.voice(&Chord::Major(C_KEY)),
+            vec![NOTE_C4, NOTE_E4, NOTE_G4]
+        );
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut voicer = Voicer::new(NOTE_C3, NOTE_C5);
+        voicer.voice(&Chord::Major(C_KEY));
+        voicer.reset();
+        assert_eq!(
+            voicer.voice(&Chord::Major(F_KEY)),
+            vec![NOTE_F3, NOTE_A3, NOTE_C4]
+        );
+    }
+
+    fn voice(style: VoicingStyle, chord: Chord) -> Vec<u8> {
+        Voicer::new(NOTE_C3, NOTE_C6)
+            .with_style(style)
+            .voice(&chord)
+    }
+
+    #[test]
+    fn test_low_interval_limits() {
+        // a close C major triad is muddy below C3
+        let mut voicer = Voicer::new(NOTE_C2, NOTE_C4);
+        assert_eq!(
+            voicer.voice(&Chord::Major(C_KEY)),
+            vec![NOTE_C3, NOTE_E3, NOTE_G3]
+        );
+    }
+
+    #[test]
+    fn test_shell() {
+        assert_eq!(
+            voice(VoicingStyle::Shell, Chord::Major7(G_KEY)),
+            vec![NOTE_G3, NOTE_B3, NOTE_F4]
+        );
+    }
+
+    #[test]
+    fn test_rootless() {
+        assert_eq!(
+            voice(VoicingStyle::RootlessA, Chord::Minor7(D_KEY)),
+            vec![NOTE_F3, NOTE_A3, NOTE_C4, NOTE_E4]
+        );
+        // with the thirteenth for the fifth of a dominant
+        assert_eq!(
+            voice(VoicingStyle::RootlessB, Chord::Major7(G_KEY)),
+            vec![NOTE_F3, NOTE_A3, NOTE_B3, NOTE_E4]
+        );
+    }
+
+    #[test]
+    fn test_drop_2_and_3() {
+        assert_eq!(
+            voice(VoicingStyle::Drop2, Chord::MajorMaj7(C_KEY)),
+            vec![NOTE_C3, NOTE_G3, NOTE_B3, NOTE_E4]
+        );
+        assert_eq!(
+            voice(VoicingStyle::Drop3, Chord::MajorMaj7(C_KEY)),
+            vec![NOTE_C3, NOTE_B3, NOTE_E4, NOTE_G4]
+        );
+    }
+
+    #[test]
+    fn test_quartal() {
+        assert_eq!(
+            voice(VoicingStyle::Quartal, Chord::Minor7(D_KEY)),
+            vec![NOTE_D3, NOTE_G3, NOTE_C4, NOTE_F4]
+        );
+        assert_eq!(
+            voice(VoicingStyle::Quartal, Chord::Major7(G_KEY)),
+            vec![NOTE_B3, NOTE_E4, NOTE_A4, NOTE_D5]
+        );
+        assert_eq!(
+            voice(VoicingStyle::Quartal, Chord::Major(C_KEY)),
+            vec![NOTE_E3, NOTE_A3, NOTE_D4]
+        );
+    }
+
+    #[test]
+    fn test_spread() {
+        assert_eq!(
+            voice(VoicingStyle::Spread, Chord::MajorMaj7(C_KEY)),
+            vec![NOTE_C3, NOTE_G3, NOTE_E4, NOTE_B4]
+        );
+    }
+
+    #[test]
+    fn test_styles_voice_lead() {
+        let mut voicer = Voicer::new(NOTE_C3, NOTE_C5).with_style(VoicingStyle::Drop2);
+        voicer.voice(&Chord::MajorMaj7(C_KEY));
+        for chord in [
+            Chord::Minor7(A_KEY),
+            Chord::Minor7(D_KEY),
+            Chord::Major7(G_KEY),
+        ]
+        .iter()
+        {
+            let notes = voicer.voice(chord);
+            assert_eq!(notes.len(), 4);
+            assert!(notes.iter().all(|note| (NOTE_C3..=NOTE_C5).contains(note)));
+        }
+        // chords without a third are voiced close
+        assert_eq!(
+            voice(VoicingStyle::Shell, Chord::Five(C_KEY)),
+            vec![NOTE_C3, NOTE_G3]
+        );
+    }
+
+    #[test]
+    fn test_voicing_style_from_str() {
+        assert_eq!("drop-2".parse(), Ok(VoicingStyle::Drop2));
+        assert_eq!("rootless-b".parse(), Ok(VoicingStyle::RootlessB));
+        assert!("drop-4".parse::<VoicingStyle>().is_err());
+    }
+}